@@ -0,0 +1,156 @@
+use crate::db::{ChatMessage, UserId};
+use crate::messages::{t, Key, Lang};
+use crate::stats::{count_first_solves, distinct_katas};
+use std::collections::HashSet;
+
+/// What the badge conditions look at, computed once per user
+#[derive(Debug, Default)]
+pub struct BadgeStats {
+    pub distinct: usize,
+    pub languages: usize,
+    /// Lowest claimed kyu, the hardest one
+    pub hardest_kyu: Option<u8>,
+    pub first_solves: usize,
+}
+
+pub struct Badge {
+    /// Stored in `UserSettings::announced_badges`, never rename. Names are in `messages`
+    pub id: &'static str,
+    earned: fn(&BadgeStats) -> bool,
+}
+
+/// Every badge, a new one only needs an entry here and its /help line
+pub const BADGES: &[Badge] = &[
+    Badge {
+        id: "century",
+        earned: |stats| stats.distinct >= 100,
+    },
+    Badge {
+        id: "polyglot",
+        earned: |stats| stats.languages >= 5,
+    },
+    Badge {
+        id: "hard_mode",
+        earned: |stats| matches!(stats.hardest_kyu, Some(kyu) if kyu <= 2),
+    },
+    Badge {
+        id: "early_bird",
+        earned: |stats| stats.first_solves >= 10,
+    },
+];
+
+impl Badge {
+    pub fn name(&self, lang: Lang) -> String {
+        t(lang, Key::BadgeName(self.id))
+    }
+}
+
+/// `messages` are the whole chat's, first solves depend on the others
+pub fn badge_stats(user: UserId, messages: &[ChatMessage]) -> BadgeStats {
+    let own: Vec<_> = messages.iter().filter(|msg| msg.from == user).collect();
+    let solutions = || own.iter().filter_map(|msg| msg.solution.as_ref());
+    let first_solves = count_first_solves(messages);
+    BadgeStats {
+        distinct: distinct_katas(own.iter().copied()),
+        languages: solutions()
+            .filter_map(|solution| solution.language.as_ref())
+            .collect::<HashSet<_>>()
+            .len(),
+        hardest_kyu: solutions().filter_map(|solution| solution.kyu).min(),
+        first_solves: first_solves.get(&user).copied().unwrap_or_default(),
+    }
+}
+
+/// Badges `user` earned in the order of `BADGES`
+pub fn compute_badges(user: UserId, messages: &[ChatMessage]) -> Vec<&'static Badge> {
+    let stats = badge_stats(user, messages);
+    BADGES
+        .iter()
+        .filter(|badge| (badge.earned)(&stats))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_parse::{Codewars, SolutionDetector};
+
+    fn solution(id: i32, from: i32, text: &str) -> ChatMessage {
+        ChatMessage {
+            id,
+            solution: Codewars.parse_solution(text),
+            text: text.to_owned(),
+            from: UserId(from),
+            kata_id: None,
+            date: None,
+        }
+    }
+
+    fn earned(user: i32, messages: &[ChatMessage]) -> Vec<&'static str> {
+        compute_badges(UserId(user), messages)
+            .iter()
+            .map(|badge| badge.id)
+            .collect()
+    }
+
+    #[test]
+    fn century_needs_a_hundred_distinct_katas() {
+        let mut messages: Vec<_> = (0..99)
+            .map(|id| {
+                solution(
+                    id,
+                    1,
+                    &format!("7 Kata {}\nhttps://pastebin.com/p{}", id, id),
+                )
+            })
+            .collect();
+        // reposts don't count
+        messages.push(solution(99, 1, "7 Kata 0\nhttps://pastebin.com/p99"));
+        assert!(!earned(1, &messages).contains(&"century"));
+        messages.push(solution(100, 1, "7 Kata 100\nhttps://pastebin.com/p100"));
+        assert!(earned(1, &messages).contains(&"century"));
+    }
+
+    #[test]
+    fn polyglot_needs_five_languages() {
+        let mut messages: Vec<_> = ["rust", "python", "go", "rust", "haskell"]
+            .iter()
+            .enumerate()
+            .map(|(id, lang)| {
+                let text = format!("7 Kata {}\nhttps://pastebin.com/p{} {}", id, id, lang);
+                solution(id as i32, 1, &text)
+            })
+            .collect();
+        assert_eq!(badge_stats(UserId(1), &messages).languages, 4);
+        assert!(earned(1, &messages).is_empty());
+        messages.push(solution(5, 1, "7 Kata 5\nhttps://pastebin.com/p5 #kotlin"));
+        assert_eq!(earned(1, &messages), vec!["polyglot"]);
+    }
+
+    #[test]
+    fn hard_mode_needs_a_one_or_two_kyu() {
+        let messages = vec![
+            solution(1, 1, "3 Kata\nhttps://pastebin.com/p1"),
+            solution(2, 2, "2 Kata\nhttps://pastebin.com/p2"),
+            solution(3, 3, "1 kyu Kata\nhttps://pastebin.com/p3"),
+        ];
+        assert!(earned(1, &messages).is_empty());
+        assert_eq!(earned(2, &messages), vec!["hard_mode"]);
+        assert_eq!(earned(3, &messages), vec!["hard_mode"]);
+    }
+
+    #[test]
+    fn early_bird_needs_ten_first_solves() {
+        let mut messages = Vec::new();
+        for kata in 0..10 {
+            let text = format!("7 Kata {}\nhttps://pastebin.com/p{}", kata, kata);
+            // the second user always posts later
+            messages.push(solution(kata * 2, 1, &text));
+            messages.push(solution(kata * 2 + 1, 2, &text));
+        }
+        assert_eq!(earned(1, &messages), vec!["early_bird"]);
+        assert!(earned(2, &messages).is_empty());
+        messages.retain(|msg| msg.id != 0);
+        assert!(earned(1, &messages).is_empty());
+    }
+}
@@ -1,9 +1,11 @@
 use crate::error::{CodewarsApiError, MainError};
-use reqwest;
+use crate::http::Fetch;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-pub async fn get_honor(username: &str) -> Result<i64, MainError> {
+const CODEWARS_API: &str = "https://www.codewars.com/api/v1";
+
+pub async fn get_honor(http: &dyn Fetch, username: &str) -> Result<i64, MainError> {
     fn parse(user: CodewarsHonorResponse, username: &str) -> Result<User, MainError> {
         Ok(match user {
             CodewarsHonorResponse::Success(user) => Ok(user),
@@ -14,29 +16,24 @@ pub async fn get_honor(username: &str) -> Result<i64, MainError> {
         }?)
     }
     let honor: CodewarsHonorResponse = serde_json::from_str(
-        reqwest::get(&{
-            let url = format!("https://www.codewars.com/api/v1/users/{}", username);
-            log::info!("Request: {}", &url);
-            url
-        })
-        .await?
-        .text()
-        .await?
-        .as_str(),
+        http.get_text(&format!("{}/users/{}", CODEWARS_API, username))
+            .await?
+            .as_str(),
     )?;
 
     Ok(parse(honor, username)?.honor)
 }
 
-pub async fn get_completed(username: &str) -> Result<Vec<CompletedKata>, MainError> {
-    fn url(user: &str, page: i32) -> String {
-        let url = format!(
-            "https://www.codewars.com/api/v1/users/{}/code-challenges/completed?page={}",
-            user, page
-        );
-        log::info!("Request: {}", &url);
-        url
-    }
+pub async fn get_completed(
+    http: &dyn Fetch,
+    username: &str,
+) -> Result<Vec<CompletedKata>, MainError> {
+    let url = |page: i32| {
+        format!(
+            "{}/users/{}/code-challenges/completed?page={}",
+            CODEWARS_API, username, page
+        )
+    };
     fn parse(pages: CodewarsResponse, username: &str) -> Result<CompletedKatas, MainError> {
         Ok(match pages {
             CodewarsResponse::Success(katas) => Ok(katas),
@@ -47,24 +44,12 @@ pub async fn get_completed(username: &str) -> Result<Vec<CompletedKata>, MainErr
         }?)
     }
 
-    let pages: CodewarsResponse = serde_json::from_str(
-        reqwest::get(&url(username, 0))
-            .await?
-            .text()
-            .await?
-            .as_str(),
-    )?;
+    let pages: CodewarsResponse = serde_json::from_str(http.get_text(&url(0)).await?.as_str())?;
     let mut pages = vec![parse(pages, username)?];
 
     for page in 1..pages.first().unwrap().total_pages {
         let new = parse(
-            serde_json::from_str(
-                reqwest::get(&url(username, page))
-                    .await?
-                    .text()
-                    .await?
-                    .as_str(),
-            )?,
+            serde_json::from_str(http.get_text(&url(page)).await?.as_str())?,
             username,
         )?;
         pages.push(new)
@@ -75,6 +60,41 @@ pub async fn get_completed(username: &str) -> Result<Vec<CompletedKata>, MainErr
     }))
 }
 
+/// `None` for beta katas that have no rank yet
+pub async fn get_kata_kyu(http: &dyn Fetch, kata_id: &str) -> Result<Option<u8>, MainError> {
+    let kata: CodewarsChallengeResponse = serde_json::from_str(
+        http.get_text(&format!("{}/code-challenges/{}", CODEWARS_API, kata_id))
+            .await?
+            .as_str(),
+    )?;
+
+    match kata {
+        CodewarsChallengeResponse::Success(kata) => {
+            Ok(kata.rank.id.filter(|id| *id < 0).map(|id| -id as u8))
+        }
+        CodewarsChallengeResponse::Fail { reason, .. } => {
+            log::warn!("kyu of kata {} not known: {}", kata_id, reason);
+            Err(CodewarsApiError::NotFound(kata_id.to_owned()).into())
+        }
+    }
+}
+
+/// Looks a kata up by its slug or id, a posted name only resolves if it slugs like the title
+pub async fn get_kata(http: &dyn Fetch, slug: &str) -> Result<CanonicalKata, MainError> {
+    let kata: CodewarsKataResponse = serde_json::from_str(
+        http.get_text(&format!("{}/code-challenges/{}", CODEWARS_API, slug))
+            .await?
+            .as_str(),
+    )?;
+
+    match kata {
+        CodewarsKataResponse::Success(kata) => Ok(kata),
+        CodewarsKataResponse::Fail { .. } => {
+            Err(CodewarsApiError::NotFound(slug.to_owned()).into())
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(untagged)]
 enum CodewarsHonorResponse {
@@ -101,8 +121,42 @@ struct CompletedKatas {
     data: Vec<CompletedKata>,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum CodewarsChallengeResponse {
+    Fail { success: bool, reason: String },
+    Success(CodeChallenge),
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum CodewarsKataResponse {
+    Fail { success: bool, reason: String },
+    Success(CanonicalKata),
+}
+
+/// A kata the way Codewars names it, whatever variant of the name was posted
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CanonicalKata {
+    pub id: String,
+    pub name: String,
+    pub slug: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CodeChallenge {
+    rank: Rank,
+}
+
+/// Negative ids are kyu, positive ones are dan
+#[derive(Deserialize, Serialize, Debug)]
+struct Rank {
+    id: Option<i32>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CompletedKata {
+    pub id: String,
     pub name: String,
     #[serde(rename = "completedLanguages")]
     pub completed_languages: Vec<String>,
@@ -0,0 +1,154 @@
+use crate::error::{ConfigError, MainError};
+use serde::Deserialize;
+use smart_default::SmartDefault;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const DEFAULT_PATH: &str = "config.toml";
+
+/// Read from `config.toml` or the file in `CONFIG_PATH`, env variables win over the file
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    pub telegram_token: Option<String>,
+    #[default = "CodeWarsCheatStats_bot"]
+    pub bot_name: String,
+    /// Directory of the sled databases, the working directory if empty
+    pub data_dir: PathBuf,
+    #[default = 268_435_456]
+    pub cache_size: u64,
+    #[default = "info"]
+    pub log_level: String,
+    #[default = "logs.log"]
+    pub log_file: PathBuf,
+    /// Directory scanned for `exported_messages*.json`, the working directory if empty
+    pub import_dir: PathBuf,
+    #[default = "codewars"]
+    pub solution_source: String,
+    /// Timeout of the Codewars and pastebin requests
+    #[default = 30]
+    pub http_timeout_secs: u64,
+    /// Where `/metrics` is served, e.g. "127.0.0.1:9898", nowhere if unset
+    pub metrics_addr: Option<String>,
+    /// Read-only maintenance: no import, migrations, retention or stored messages,
+    /// commands that write are refused
+    pub safe_mode: bool,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, MainError> {
+        let path = std::env::var("CONFIG_PATH").unwrap_or_else(|_| DEFAULT_PATH.to_owned());
+        let config = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(ConfigError::Parse)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(config
+            .override_with(|var| std::env::var(var).ok())
+            .validate()?)
+    }
+
+    fn override_with(mut self, var: impl Fn(&str) -> Option<String>) -> Self {
+        if let Some(token) = var("TELEGRAM_TOKEN") {
+            self.telegram_token = Some(token);
+        }
+        if let Some(name) = var("BOT_NAME") {
+            self.bot_name = name;
+        }
+        if let Some(dir) = var("DATA_DIR") {
+            self.data_dir = dir.into();
+        }
+        if let Some(level) = var("LOG_LEVEL") {
+            self.log_level = level;
+        }
+        if let Some(file) = var("LOG_FILE") {
+            self.log_file = file.into();
+        }
+        if let Some(dir) = var("IMPORT_DIR") {
+            self.import_dir = dir.into();
+        }
+        if let Some(source) = var("SOLUTION_SOURCE") {
+            self.solution_source = source;
+        }
+        if let Some(timeout) = var("HTTP_TIMEOUT_SECS").and_then(|t| t.parse().ok()) {
+            self.http_timeout_secs = timeout;
+        }
+        if let Some(addr) = var("METRICS_ADDR") {
+            self.metrics_addr = Some(addr);
+        }
+        if let Some(safe_mode) = var("SAFE_MODE") {
+            self.safe_mode = matches!(safe_mode.as_str(), "1" | "true");
+        }
+        self
+    }
+
+    /// Reports every problem at once instead of failing on the first one
+    fn validate(self) -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+        if self.token().is_empty() {
+            problems.push(
+                "telegram_token is missing, set it in the config or TELEGRAM_TOKEN".to_owned(),
+            );
+        }
+        if self.bot_name.is_empty() {
+            problems.push("bot_name is empty".to_owned());
+        }
+        if self.log_level().is_none() {
+            problems.push(format!("unknown log_level {}", self.log_level));
+        }
+        if self.http_timeout_secs == 0 {
+            problems.push("http_timeout_secs must be positive".to_owned());
+        }
+        if self.solution_source != "codewars" {
+            problems.push(format!("unknown solution_source {}", self.solution_source));
+        }
+        if problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    pub fn log_level(&self) -> Option<log::LevelFilter> {
+        log::LevelFilter::from_str(&self.log_level).ok()
+    }
+
+    pub fn token(&self) -> &str {
+        self.telegram_token.as_deref().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_wins_and_problems_are_listed() {
+        let config: Config =
+            toml::from_str("bot_name = \"from_file\"\nlog_level = \"loud\"").unwrap();
+        assert_eq!(config.cache_size, 268_435_456);
+        assert_eq!(config.import_dir, PathBuf::new());
+
+        let config = config.override_with(|var| match var {
+            "BOT_NAME" => Some("from_env".to_owned()),
+            _ => None,
+        });
+        assert_eq!(config.bot_name, "from_env");
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(problems.len(), 2),
+            res => panic!("expected invalid config, got {:?}", res),
+        }
+
+        let config = Config::default()
+            .override_with(|var| match var {
+                "TELEGRAM_TOKEN" => Some("123:abc".to_owned()),
+                "SAFE_MODE" => Some("1".to_owned()),
+                _ => None,
+            })
+            .validate()
+            .unwrap();
+        assert_eq!(config.token(), "123:abc");
+        assert!(config.safe_mode);
+        assert_eq!(config.log_level(), Some(log::LevelFilter::Info));
+    }
+}
@@ -0,0 +1,500 @@
+use crate::db::{ChatMessage, UserId};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, PartialEq)]
+pub struct Streak {
+    pub days: usize,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Streaks {
+    pub longest: Option<Streak>,
+    pub current: usize,
+    pub skipped: usize,
+}
+
+pub fn to_day(date: DateTime<Utc>, tz: Tz) -> NaiveDate {
+    date.with_timezone(&tz).date().naive_local()
+}
+
+pub fn today(tz: Tz) -> NaiveDate {
+    to_day(Utc::now(), tz)
+}
+
+/// Inclusive range of calendar days in a timezone
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    tz: Tz,
+}
+
+impl Window {
+    /// `days` days ending today, shifted `back` whole windows into the past
+    pub fn last_days(days: i64, back: i64, tz: Tz) -> Self {
+        let end = today(tz) - Duration::days(days * back);
+        Self {
+            start: end - Duration::days(days - 1),
+            end,
+            tz,
+        }
+    }
+
+    pub fn contains(&self, date: DateTime<Utc>) -> bool {
+        let day = to_day(date, self.tz);
+        self.start <= day && day <= self.end
+    }
+
+    /// Messages without a timestamp never match
+    pub fn filter<'a>(
+        self,
+        messages: impl IntoIterator<Item = &'a ChatMessage>,
+    ) -> impl Iterator<Item = &'a ChatMessage> {
+        messages
+            .into_iter()
+            .filter(move |msg| matches!(msg.date, Some(date) if self.contains(date)))
+    }
+}
+
+/// Chat numbers of two windows, `(current, previous)` everywhere
+#[derive(Debug, PartialEq)]
+pub struct PeriodDiff {
+    pub distinct: (usize, usize),
+    pub active: (usize, usize),
+    /// Users who posted in either window, their distinct katas, the biggest gain first
+    pub per_user: Vec<(UserId, usize, usize)>,
+    pub skipped: usize,
+}
+
+pub fn compute_period_diff(
+    messages: &[ChatMessage],
+    current: Window,
+    previous: Window,
+) -> PeriodDiff {
+    let distinct = |window: Window| {
+        window
+            .filter(messages)
+            .filter_map(|msg| msg.kata_key())
+            .unique()
+            .count()
+    };
+    let per_user = |window: Window| {
+        let mut counts: HashMap<UserId, usize> = HashMap::new();
+        for (user, _) in window
+            .filter(messages)
+            .filter_map(|msg| Some((msg.from, msg.kata_key()?)))
+            .unique()
+        {
+            *counts.entry(user).or_insert(0) += 1;
+        }
+        counts
+    };
+    let (now, before) = (per_user(current), per_user(previous));
+    let count = |counts: &HashMap<UserId, usize>, user| counts.get(&user).copied().unwrap_or(0);
+    let per_user = now
+        .keys()
+        .chain(before.keys())
+        .unique()
+        .map(|&user| (user, count(&now, user), count(&before, user)))
+        .sorted_by_key(|&(user, now, before)| (before as i64 - now as i64, Reverse(now), user.0))
+        .collect();
+    PeriodDiff {
+        distinct: (distinct(current), distinct(previous)),
+        active: (now.len(), before.len()),
+        per_user,
+        skipped: messages.iter().filter(|msg| msg.date.is_none()).count(),
+    }
+}
+
+/// Users without a message dated `since` or later, sorted by id. Messages without
+/// a timestamp can't show recent activity
+pub fn inactive_users(
+    users: impl IntoIterator<Item = UserId>,
+    messages: &[ChatMessage],
+    since: DateTime<Utc>,
+) -> Vec<UserId> {
+    let active: HashSet<UserId> = messages
+        .iter()
+        .filter(|msg| matches!(msg.date, Some(date) if date >= since))
+        .map(|msg| msg.from)
+        .collect();
+    users
+        .into_iter()
+        .filter(|user| !active.contains(user))
+        .sorted_by_key(|user| user.0)
+        .collect()
+}
+
+/// Solved counts `/pace` aims for once there's no goal left to reach
+pub const MILESTONES: &[usize] = &[10, 25, 50, 100, 250, 500, 1000];
+
+/// The goal while it's ahead, otherwise the next milestone or thousand
+pub fn next_target(solved: usize, goal: Option<usize>) -> usize {
+    match goal {
+        Some(goal) if goal > solved => goal,
+        _ => MILESTONES
+            .iter()
+            .copied()
+            .find(|&milestone| milestone > solved)
+            .unwrap_or((solved / 1000 + 1) * 1000),
+    }
+}
+
+/// Day `target` is reached solving as many katas as the `recent` of the last
+/// `days` days, rounded up to whole days. `None` when nothing was solved lately
+pub fn project_pace(
+    today: NaiveDate,
+    solved: usize,
+    target: usize,
+    recent: usize,
+    days: i64,
+) -> Option<NaiveDate> {
+    if recent == 0 {
+        return None;
+    }
+    let (remaining, recent) = (target.saturating_sub(solved) as i64, recent as i64);
+    Some(today + Duration::days((remaining * days + recent - 1) / recent))
+}
+
+/// Messages of each calendar day and the number of messages without a timestamp
+pub fn solves_per_day<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+    tz: Tz,
+) -> (BTreeMap<NaiveDate, usize>, usize) {
+    let mut skipped = 0;
+    let mut days = BTreeMap::new();
+    for msg in messages {
+        match msg.date {
+            Some(date) => *days.entry(to_day(date, tz)).or_insert(0) += 1,
+            None => skipped += 1,
+        }
+    }
+    (days, skipped)
+}
+
+/// Calendar days with at least one message and the number of messages without a timestamp
+pub fn solved_days<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+    tz: Tz,
+) -> (BTreeSet<NaiveDate>, usize) {
+    let (days, skipped) = solves_per_day(messages, tz);
+    (days.keys().copied().collect(), skipped)
+}
+
+/// Every day of the window with its count, days without solutions too
+pub fn calendar(per_day: &BTreeMap<NaiveDate, usize>, window: Window) -> Vec<(NaiveDate, usize)> {
+    itertools::iterate(window.start, NaiveDate::succ)
+        .take_while(|day| *day <= window.end)
+        .map(|day| (day, per_day.get(&day).copied().unwrap_or(0)))
+        .collect()
+}
+
+/// The current streak stays active until a whole day passes without a solution
+pub fn compute_streaks<'a>(messages: impl IntoIterator<Item = &'a ChatMessage>, tz: Tz) -> Streaks {
+    let today = today(tz);
+    let (days, skipped) = solved_days(messages, tz);
+
+    let mut runs: Vec<Streak> = Vec::new();
+    for day in days {
+        match runs.last_mut() {
+            Some(run) if run.end + Duration::days(1) == day => {
+                run.end = day;
+                run.days += 1;
+            }
+            _ => runs.push(Streak {
+                days: 1,
+                start: day,
+                end: day,
+            }),
+        }
+    }
+
+    let current = match runs.last() {
+        Some(run) if run.end >= today - Duration::days(1) => run.days,
+        _ => 0,
+    };
+    // on ties the earliest streak wins
+    let longest = runs
+        .into_iter()
+        .fold(None, |best: Option<Streak>, run| match best {
+            Some(best) if best.days >= run.days => Some(best),
+            _ => Some(run),
+        });
+
+    Streaks {
+        longest,
+        current,
+        skipped,
+    }
+}
+
+/// Distinct katas posted closer together than anyone plausibly solves them
+#[derive(Debug, PartialEq)]
+pub struct Burst {
+    pub katas: usize,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Every stretch where `threshold` distinct katas fit into `window`, overlapping
+/// stretches are merged. Only the first post of a kata counts and untimestamped
+/// messages are skipped. It's a hint at mass-posting, not proof of cheating
+pub fn compute_bursts<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+    threshold: usize,
+    window: Duration,
+) -> Vec<Burst> {
+    let mut firsts: HashMap<String, DateTime<Utc>> = HashMap::new();
+    for msg in messages {
+        if let (Some(key), Some(date)) = (msg.kata_key(), msg.date) {
+            let first = firsts.entry(key).or_insert(date);
+            *first = (*first).min(date);
+        }
+    }
+    let times: Vec<_> = firsts.values().copied().sorted().collect();
+
+    let threshold = threshold.max(1);
+    let mut bursts: Vec<Burst> = Vec::new();
+    let (mut from, mut burst_from) = (0, 0);
+    for (i, &time) in times.iter().enumerate() {
+        while time - times[from] > window {
+            from += 1;
+        }
+        if i + 1 - from < threshold {
+            continue;
+        }
+        match bursts.last_mut() {
+            Some(burst) if times[from] <= burst.end => {
+                burst.end = time;
+                burst.katas = i + 1 - burst_from;
+            }
+            _ => {
+                burst_from = from;
+                bursts.push(Burst {
+                    katas: i + 1 - from,
+                    start: times[from],
+                    end: time,
+                });
+            }
+        }
+    }
+    bursts
+}
+
+/// Messages of the season started at `start`, all of them without a season.
+/// Untimestamped messages can't be placed, they only count all-time
+pub fn season_messages(
+    messages: Vec<ChatMessage>,
+    start: Option<DateTime<Utc>>,
+) -> Vec<ChatMessage> {
+    match start {
+        Some(start) => messages
+            .into_iter()
+            .filter(|msg| matches!(msg.date, Some(date) if date >= start))
+            .collect(),
+        None => messages,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_parse::{Codewars, SolutionDetector};
+    use chrono::TimeZone;
+
+    fn message(id: i32, from: i32, date: Option<DateTime<Utc>>) -> ChatMessage {
+        ChatMessage {
+            id,
+            text: String::new(),
+            from: UserId(from),
+            kata_id: None,
+            date,
+            solution: None,
+        }
+    }
+
+    fn solution(id: i32, from: i32, name: &str, date: Option<DateTime<Utc>>) -> ChatMessage {
+        let text = format!("7\n{}\nhttps://pastebin.com/abc{}", name, id);
+        ChatMessage {
+            solution: Codewars.parse_solution(&text),
+            text,
+            ..message(id, from, date)
+        }
+    }
+
+    #[test]
+    fn calendar_keeps_empty_days() {
+        let messages = vec![
+            message(1, 1, Some(Utc.ymd(2020, 4, 8).and_hms(23, 0, 0))),
+            message(2, 1, Some(Utc.ymd(2020, 4, 10).and_hms(8, 0, 0))),
+            message(3, 1, Some(Utc.ymd(2020, 4, 10).and_hms(9, 0, 0))),
+            message(4, 1, None),
+        ];
+        let (per_day, skipped) = solves_per_day(&messages, Tz::Europe__Moscow);
+        assert_eq!(skipped, 1);
+
+        let window = Window {
+            start: NaiveDate::from_ymd(2020, 4, 8),
+            end: NaiveDate::from_ymd(2020, 4, 10),
+            tz: Tz::Europe__Moscow,
+        };
+        assert_eq!(
+            calendar(&per_day, window),
+            vec![
+                (NaiveDate::from_ymd(2020, 4, 8), 0),
+                (NaiveDate::from_ymd(2020, 4, 9), 1),
+                (NaiveDate::from_ymd(2020, 4, 10), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn pace_projects_the_remaining_katas() {
+        let today = NaiveDate::from_ymd(2020, 4, 10);
+        // 6 katas in 30 days is one every 5 days, 4 left
+        assert_eq!(
+            project_pace(today, 21, 25, 6, 30),
+            Some(NaiveDate::from_ymd(2020, 4, 30))
+        );
+        // 7 katas in 30 days, 2 left take 60 / 7 days rounded up
+        assert_eq!(
+            project_pace(today, 23, 25, 7, 30),
+            Some(NaiveDate::from_ymd(2020, 4, 19))
+        );
+        assert_eq!(project_pace(today, 25, 25, 3, 30), Some(today));
+        assert_eq!(project_pace(today, 21, 25, 0, 30), None);
+
+        assert_eq!(next_target(21, None), 25);
+        assert_eq!(next_target(21, Some(40)), 40);
+        assert_eq!(next_target(50, Some(50)), 100);
+        assert_eq!(next_target(1000, None), 2000);
+    }
+
+    #[test]
+    fn to_day_depends_on_timezone() {
+        let date = Utc.ymd(2020, 4, 10).and_hms(22, 30, 0);
+
+        assert_eq!(to_day(date, Tz::UTC), NaiveDate::from_ymd(2020, 4, 10));
+        assert_eq!(
+            to_day(date, Tz::Europe__Moscow),
+            NaiveDate::from_ymd(2020, 4, 11)
+        );
+        assert_eq!(
+            to_day(Utc.ymd(2020, 4, 10).and_hms(2, 0, 0), Tz::America__New_York),
+            NaiveDate::from_ymd(2020, 4, 9)
+        );
+    }
+
+    #[test]
+    fn bursts_merge_overlapping_windows() {
+        let start = Utc.ymd(2020, 4, 10).and_hms(12, 0, 0);
+        let solution =
+            |id, name: &str, minute| solution(id, 1, name, Some(start + Duration::minutes(minute)));
+        let mut messages: Vec<_> = (0..5)
+            .map(|i| solution(i, &format!("Kata {}", i), i as i64))
+            .collect();
+        // a repost, untimestamped and slow solutions don't add to a burst
+        messages.push(solution(5, "Kata 0", 2));
+        messages.push(ChatMessage {
+            date: None,
+            ..solution(6, "Kata 6", 0)
+        });
+        messages.push(solution(7, "Kata 7", 60));
+        messages.push(solution(8, "Kata 8", 90));
+
+        let bursts = compute_bursts(&messages, 3, Duration::minutes(2));
+        assert_eq!(
+            bursts,
+            vec![Burst {
+                katas: 5,
+                start,
+                end: start + Duration::minutes(4),
+            }]
+        );
+        assert!(compute_bursts(&messages, 4, Duration::minutes(2)).is_empty());
+        assert_eq!(compute_bursts(&messages, 2, Duration::minutes(30)).len(), 2);
+    }
+
+    #[test]
+    fn season_counts_exclude_pre_season_messages() {
+        use crate::stats::aggregate_stats;
+        use std::collections::HashMap;
+
+        let season = Utc.ymd(2020, 4, 10).and_hms(12, 0, 0);
+        let messages = vec![
+            solution(1, 1, "Kata 1", Some(season - Duration::days(1))),
+            solution(2, 1, "Kata 2", None),
+            solution(3, 1, "Kata 3", Some(season)),
+            solution(4, 2, "Kata 1", Some(season + Duration::days(1))),
+            solution(5, 2, "Kata 4", Some(season + Duration::days(2))),
+        ];
+        let distinct = |messages: &[ChatMessage]| {
+            let summary = aggregate_stats(&HashMap::new(), messages);
+            let per_user = |id| summary.unregistered.get(&UserId(id)).map(|u| u.distinct);
+            (summary.total_distinct, per_user(1), per_user(2))
+        };
+
+        assert_eq!(
+            distinct(&season_messages(messages.clone(), None)),
+            (4, Some(3), Some(2))
+        );
+        let in_season = season_messages(messages, Some(season));
+        assert_eq!(
+            in_season.iter().map(|msg| msg.id).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(distinct(&in_season), (3, Some(1), Some(2)));
+    }
+
+    #[test]
+    fn period_diff_compares_two_windows() {
+        let tz = Tz::UTC;
+        let day = |d: u32| Some(Utc.ymd(2020, 4, d).and_hms(12, 0, 0));
+        let messages = vec![
+            solution(1, 1, "Kata 1", day(2)),
+            solution(2, 1, "Kata 2", day(3)),
+            solution(3, 2, "Kata 1", day(4)),
+            solution(4, 1, "Kata 3", day(9)),
+            solution(5, 3, "Kata 4", day(10)),
+            solution(6, 3, "Kata 4", day(11)),
+            solution(7, 3, "Kata 5", day(12)),
+            solution(8, 3, "Kata 6", None),
+        ];
+        let window = |start, end| Window {
+            start: NaiveDate::from_ymd(2020, 4, start),
+            end: NaiveDate::from_ymd(2020, 4, end),
+            tz,
+        };
+
+        let diff = compute_period_diff(&messages, window(8, 14), window(1, 7));
+        assert_eq!(diff.distinct, (3, 2));
+        assert_eq!(diff.active, (2, 2));
+        assert_eq!(
+            diff.per_user,
+            vec![(UserId(3), 2, 0), (UserId(1), 1, 2), (UserId(2), 0, 1)]
+        );
+        assert_eq!(diff.skipped, 1);
+    }
+
+    #[test]
+    fn inactive_users_have_nothing_recent() {
+        let since = Utc.ymd(2020, 4, 10).and_hms(0, 0, 0);
+        let messages = vec![
+            message(1, 1, Some(since)),
+            message(2, 2, Some(since - Duration::seconds(1))),
+            message(3, 3, None),
+            message(4, 5, Some(since + Duration::days(3))),
+        ];
+        let users = vec![UserId(4), UserId(3), UserId(2), UserId(1)];
+        assert_eq!(
+            inactive_users(users, &messages, since),
+            vec![UserId(2), UserId(3), UserId(4)]
+        );
+    }
+}
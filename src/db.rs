@@ -1,8 +1,23 @@
 use crate::error::MainError;
+use crate::message_parse::{
+    normalize_kata_name, strip_kyu, Codewars, ParsedSolution, SolutionDetector,
+};
+use crate::messages::Lang;
 use crate::typed_db::TypedDb;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use smart_default::SmartDefault;
+use std::collections::{HashMap, HashSet};
 use std::convert::identity;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::thread::ThreadId;
+
+const WRITE_ATTEMPTS: u32 = 4;
+const PARSED_FIELDS: &str = "parsed_fields";
+const KATA_IDS: &str = "kata_ids";
 
 #[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Copy, Clone)]
 pub struct ChatId(pub i64);
@@ -19,6 +34,34 @@ pub struct CodeUser {
     pub firstname: String,
     pub telegram_id: UserId,
     pub codewars_name: String,
+    /// Given to solutions that don't name a language, set with /language
+    #[serde(default)]
+    pub default_language: Option<String>,
+}
+
+impl CodeUser {
+    /// Same user with `pseudonym` instead of the telegram names
+    pub fn anonymized(self) -> Self {
+        let name = pseudonym(self.telegram_id);
+        CodeUser {
+            username: self.username.map(|_| name.clone()),
+            codewars_name: name.clone(),
+            firstname: name,
+            ..self
+        }
+    }
+}
+
+/// Stable across restarts and versions, unlike `DefaultHasher`
+pub fn pseudonym(id: UserId) -> String {
+    // 32 bit FNV-1a
+    let hash =
+        id.0.to_le_bytes()
+            .iter()
+            .fold(0x811c_9dc5_u32, |hash, &byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            });
+    format!("user-{:08x}", hash)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,62 +69,805 @@ pub struct ChatMessage {
     pub id: i32,
     pub text: String,
     pub from: UserId,
+    #[serde(default)]
+    pub date: Option<DateTime<Utc>>,
+    /// Parsed once when the message is stored, reads never look at `text`
+    #[serde(default)]
+    pub solution: Option<ParsedSolution>,
+    /// Codewars id of the kata when the API knew the posted name, it groups
+    /// the solutions instead of the name
+    #[serde(default)]
+    pub kata_id: Option<String>,
+}
+
+impl ChatMessage {
+    /// The Codewars id, or the name without the claimed kyu, the kata index is keyed by it too
+    pub fn kata_key(&self) -> Option<String> {
+        let solution = self.solution.as_ref()?;
+        Some(
+            self.kata_id
+                .clone()
+                .unwrap_or_else(|| kata_index_key(&solution.kata_name)),
+        )
+    }
+
+    /// Sort key by posting time, untimestamped messages last and ties by id. The
+    /// first message in this order gives a kata the name it's listed under
+    pub fn posted_order(&self) -> (bool, Option<DateTime<Utc>>, i32) {
+        (self.date.is_none(), self.date, self.id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SmartDefault)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// IANA name, e.g. "Europe/Moscow"
+    pub timezone: Option<String>,
+    pub language: Option<Lang>,
+    /// Forwarded solutions are credited to their original author unless set
+    pub skip_forwards: bool,
+    /// `/bursts` reports this many distinct katas posted within `burst_minutes`
+    #[default = 10]
+    pub burst_katas: usize,
+    #[default = 5]
+    pub burst_minutes: i64,
+    pub acknowledge: Acknowledge,
+    /// Shared outputs and exports show pseudonyms instead of names
+    pub anonymize: bool,
+    /// `/kata` records solutions without a paste, members are trusted
+    pub allow_linkless: bool,
+    /// Set by /startseason, season scoped commands ignore older messages
+    pub season_start: Option<DateTime<Utc>>,
+    /// Replies without HTML for clients that render it poorly
+    pub plain_text: bool,
+    /// Messages older than this many days are deleted, none are if unset
+    pub retention_days: Option<u32>,
+    /// Retention deletes messages without a timestamp too instead of keeping them
+    pub purge_untimestamped: bool,
+    /// Solutions of easier katas aren't stored, every kyu is if unset. There's no mode
+    /// storing them flagged instead, every stat would have to learn to skip the flag
+    pub min_kyu: Option<u8>,
+}
+
+/// How the bot confirms a stored solution
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+pub enum Acknowledge {
+    #[default]
+    React,
+    Reply,
+    Silent,
+}
+
+impl Acknowledge {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "react" => Some(Acknowledge::React),
+            "reply" => Some(Acknowledge::Reply),
+            "silent" => Some(Acknowledge::Silent),
+            _ => None,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+            .as_ref()
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(Tz::UTC)
+    }
+
+    /// Whether `min_kyu` lets a solution of this kyu in, one without a kyu always gets in
+    pub fn tracks_kyu(&self, kyu: Option<u8>) -> bool {
+        match (self.min_kyu, kyu) {
+            (Some(min_kyu), Some(kyu)) => kyu <= min_kyu,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct UserSettings {
+    /// Number of distinct katas the user wants to solve
+    pub goal: Option<usize>,
+    /// Overrides the chat's `anonymize`, `None` follows it
+    pub anonymize: Option<bool>,
+    /// Ids of the badges already announced in the chat
+    pub announced_badges: Vec<String>,
+    /// Set with /nudgeme off, /nudge never tags the user
+    pub no_nudge: bool,
+}
+
+impl UserSettings {
+    pub fn anonymized(&self, chat: &ChatSettings) -> bool {
+        self.anonymize.unwrap_or(chat.anonymize)
+    }
+}
+
+/// Everything stored about one user in one chat
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserExport {
+    pub user: Option<CodeUser>,
+    pub settings: UserSettings,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Chat totals next to the caller's own, see `Persist::chat_counts`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChatCounts {
+    /// Every stored solution, reposts of a kata included
+    pub solutions: usize,
+    /// Katas solved by anyone in the chat
+    pub distinct: usize,
+    /// Katas solved by the caller
+    pub own: usize,
+    /// Shared by users with the same count, `None` before the first solution
+    pub rank: Option<usize>,
+    /// Users with at least one solved kata
+    pub solvers: usize,
+}
+
+/// What one export file of the startup import brought in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportedFile {
+    pub name: String,
+    pub added: usize,
+    /// Already imported from an earlier file of the same run
+    pub duplicates: usize,
+    /// Not solutions or without an author
+    pub skipped: usize,
+}
+
+/// The last startup import that found exports of the chat, shown by its `/importstatus`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportReport {
+    pub finished: DateTime<Utc>,
+    pub files: Vec<ImportedFile>,
+}
+
+/// `/export json`, what `/restore` reads back
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ChatExport {
+    pub users: Vec<CodeUser>,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl From<UserExport> for ChatExport {
+    fn from(export: UserExport) -> Self {
+        ChatExport {
+            users: export.user.into_iter().collect(),
+            messages: export.messages,
+        }
+    }
+}
+
+/// What `restore` added, whatever was already there is skipped
+#[derive(Debug, Default, PartialEq)]
+pub struct Restored {
+    pub users: usize,
+    pub messages: usize,
+}
+
+/// What `rebuild_indexes` had to fix
+#[derive(Debug, Default, PartialEq)]
+pub struct Recomputed {
+    /// Kata index entries that were missing, stale or wrong
+    pub katas: usize,
+    /// Users whose solved counter was wrong
+    pub counters: usize,
+    pub message_count: bool,
+}
+
+/// `(user, message id)` of every solution of one kata
+pub type KataSolutions = Vec<(UserId, i32)>;
+
+/// Kata only one user of the chat has posted
+#[derive(Debug, PartialEq)]
+pub struct LoneSolve {
+    /// As first posted, without the kyu
+    pub name: String,
+    pub kyu: Option<u8>,
+    pub solver: UserId,
+}
+
+/// Kata of `/topkata` with everyone who solved it
+#[derive(Debug, PartialEq)]
+pub struct TopKata {
+    /// As first posted, without the kyu
+    pub name: String,
+    pub kyu: u8,
+    /// In the order they first solved it
+    pub solvers: Vec<UserId>,
+}
+
+/// The claimed kyu isn't part of the key so one kata posted with different kyus stays together
+fn kata_index_key(name: &str) -> String {
+    normalize_kata_name(strip_kyu(name))
+}
+
+/// Solutions grouped by kata the way the kata index stores them
+pub fn index_katas(messages: &[ChatMessage]) -> HashMap<String, KataSolutions> {
+    let mut index: HashMap<String, KataSolutions> = HashMap::new();
+    for msg in messages {
+        if let Some(key) = msg.kata_key() {
+            index.entry(key).or_default().push((msg.from, msg.id));
+        }
+    }
+    index
+}
+
+/// Katas among `messages` as the kata index counts them, reposts and other kyus aren't new
+pub fn distinct_katas_of(messages: &[ChatMessage]) -> usize {
+    index_katas(messages).len()
+}
+
+/// Parses `text` of every message again, returns how many came out different
+fn reparse(
+    messages: &mut [ChatMessage],
+    detector: &dyn SolutionDetector,
+    users: &HashMap<UserId, CodeUser>,
+) -> usize {
+    let mut changed = 0;
+    // a /kata command has nothing to parse, its solution is all there is
+    for msg in messages
+        .iter_mut()
+        .filter(|msg| !matches!(msg.solution, Some(ParsedSolution { linkless: true, .. })))
+    {
+        // a language missing from the text is the poster's default, as when it was posted
+        let inherited = users
+            .get(&msg.from)
+            .and_then(|user| user.default_language.as_deref());
+        let solution = detector
+            .parse_solution(&msg.text)
+            .map(|solution| solution.with_default_language(inherited));
+        if solution != msg.solution {
+            let name = |solution: &Option<ParsedSolution>| {
+                solution
+                    .as_ref()
+                    .map(|solution| kata_index_key(&solution.kata_name))
+            };
+            // the id was resolved from the old name
+            if name(&solution) != name(&msg.solution) {
+                msg.kata_id = None;
+            }
+            msg.solution = solution;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Retries `f` while it fails with a sled io error, the others (corruption, bad
+/// data) fail the same way again. `f` runs again in full so it should be a single
+/// insert. Writes run on the executor's threads, so the retries only yield
+/// instead of sleeping
+fn retry<T>(mut f: impl FnMut() -> Result<T, MainError>) -> Result<T, MainError> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(MainError::Sled(sled::Error::Io(e))) if attempt < WRITE_ATTEMPTS => {
+                log::warn!("sled io error {} on attempt {}, retrying", e, attempt);
+                std::thread::yield_now();
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Chats being written and the thread writing each. A thread can take its chat
+/// again, so the writes inside a transaction don't wait for the transaction
+#[derive(Default)]
+struct ChatLocks {
+    held: Mutex<HashMap<ChatId, ThreadId>>,
+    released: Condvar,
+}
+
+impl ChatLocks {
+    /// Waits until no other thread writes the chat, `None` if this one already does
+    fn lock(&self, chat_id: ChatId) -> Option<ChatGuard<'_>> {
+        let me = std::thread::current().id();
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            match held.get(&chat_id) {
+                Some(owner) if *owner == me => return None,
+                Some(_) => {
+                    held = self
+                        .released
+                        .wait(held)
+                        .unwrap_or_else(PoisonError::into_inner)
+                }
+                None => {
+                    held.insert(chat_id, me);
+                    return Some(ChatGuard {
+                        locks: self,
+                        chat_id,
+                    });
+                }
+            }
+        }
+    }
+}
+
+struct ChatGuard<'a> {
+    locks: &'a ChatLocks,
+    chat_id: ChatId,
+}
+
+impl Drop for ChatGuard<'_> {
+    fn drop(&mut self) {
+        self.locks
+            .held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.chat_id);
+        self.locks.released.notify_all();
+    }
 }
 
 pub struct Persist {
     users: TypedDb<ChatId, HashMap<UserId, CodeUser>>,
+    /// `users` of the chats read so far, only written together with `users`
+    user_cache: Arc<RwLock<HashMap<ChatId, HashMap<UserId, CodeUser>>>>,
+    /// Taken by every write of a chat's users and messages
+    chat_locks: ChatLocks,
     messages: TypedDb<ChatId, Vec<ChatMessage>>,
     imported_messages: TypedDb<ChatName, Vec<ChatMessage>>,
     was_chat_imported: TypedDb<ChatName, bool>,
+    settings: TypedDb<ChatId, ChatSettings>,
+    kata_kyus: TypedDb<String, u8>,
+    /// Codewars id of every kata name resolved so far, keyed like the kata index
+    kata_aliases: TypedDb<String, String>,
+    /// Kata names the API couldn't resolve, keyed like `kata_aliases` so they're asked about once
+    unresolved_katas: TypedDb<String, bool>,
+    message_counts: TypedDb<ChatId, usize>,
+    user_settings: TypedDb<(ChatId, UserId), UserSettings>,
+    kata_index: TypedDb<(ChatId, String), KataSolutions>,
+    solved_counts: TypedDb<ChatId, HashMap<UserId, usize>>,
+    migrations: TypedDb<String, bool>,
+    import_reports: TypedDb<ChatId, ImportReport>,
 }
 
 impl Persist {
-    pub fn new(
-        db: sled::Db,
-        msg_db: sled::Db,
-        imported_messages: sled::Db,
-        was_chat_imported: sled::Db,
-    ) -> Self {
-        Self {
-            users: TypedDb::new(db),
-            messages: TypedDb::new(msg_db),
-            imported_messages: TypedDb::new(imported_messages),
-            was_chat_imported: TypedDb::new(was_chat_imported),
+    /// Without `migrate` the stores are left as found, missing parsed fields and indexes too
+    pub fn open(dir: &Path, cache_size: u64, migrate: bool) -> Result<Self, MainError> {
+        let persist = Self::open_stores(|path| {
+            sled::Config::new()
+                .cache_capacity(cache_size)
+                .path(dir.join(path))
+                .open()
+        })?;
+        if migrate {
+            persist.migrate()?;
+        }
+        Ok(persist)
+    }
+
+    /// Every store in memory, gone when dropped
+    #[cfg(test)]
+    pub fn temporary() -> Self {
+        let persist = Self::open_stores(|_| sled::Config::new().temporary(true).open()).unwrap();
+        persist.migrate().unwrap();
+        persist
+    }
+
+    /// Each store lives in its own sled database named by `path`
+    fn open_stores(open: impl Fn(&str) -> sled::Result<sled::Db>) -> Result<Self, MainError> {
+        Ok(Self {
+            users: TypedDb::new(open("users")?),
+            user_cache: Arc::default(),
+            chat_locks: ChatLocks::default(),
+            messages: TypedDb::new(open("messages")?),
+            imported_messages: TypedDb::new(open("imported_msgs")?),
+            was_chat_imported: TypedDb::new(open("was_imported")?),
+            settings: TypedDb::new(open("settings")?),
+            kata_kyus: TypedDb::new(open("kata_kyus")?),
+            kata_aliases: TypedDb::new(open("kata_aliases")?),
+            unresolved_katas: TypedDb::new(open("unresolved_katas")?),
+            message_counts: TypedDb::new(open("message_counts")?),
+            user_settings: TypedDb::new(open("user_settings")?),
+            kata_index: TypedDb::new(open("kata_index")?),
+            solved_counts: TypedDb::new(open("solved_counts")?),
+            migrations: TypedDb::new(open("migrations")?),
+            import_reports: TypedDb::new(open("import_reports")?),
+        })
+    }
+
+    fn migrate(&self) -> Result<(), MainError> {
+        // the index is built from the parsed fields so they have to be there first
+        if self.migrations.get(&PARSED_FIELDS.to_owned())?.is_none() {
+            self.migrate_parsed_fields()?;
+        }
+        if (self.kata_index.is_empty() || self.solved_counts.is_empty())
+            && !self.messages.is_empty()
+        {
+            self.rebuild_all_indexes()?;
+        }
+        Ok(())
+    }
+
+    /// Every write to `messages` goes through here to keep the counters and the kata index in sync.
+    /// Callers that read the messages first hold the chat lock across both. Each
+    /// insert is retried alone, a retry of the whole write would diff the index
+    /// against messages it already stored
+    fn store_messages(&self, chat_id: ChatId, messages: Vec<ChatMessage>) -> Result<(), MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let old = match self.get_messages(chat_id) {
+            Ok(old) => index_katas(&old),
+            Err(e) => {
+                log::warn!(
+                    "kata index of chat {:?} may keep stale katas: {}",
+                    &chat_id,
+                    e
+                );
+                HashMap::new()
+            }
+        };
+        let new = index_katas(&messages);
+
+        let count = messages.len();
+        retry(|| self.messages.insert(&chat_id, messages.clone()))?;
+        if let Err(e) = retry(|| self.message_counts.insert(&chat_id, count)) {
+            log::warn!(
+                "message counter in chat {:?} is out of sync: {}",
+                &chat_id,
+                e
+            );
+            return Err(e);
+        }
+        if let Err(e) = self.update_kata_index(chat_id, old, new) {
+            log::warn!("kata index of chat {:?} is out of sync: {}", &chat_id, e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Only katas whose solutions changed are written, and the solved counters
+    /// only change for users who gained or lost a kata
+    fn update_kata_index(
+        &self,
+        chat_id: ChatId,
+        old: HashMap<String, KataSolutions>,
+        new: HashMap<String, KataSolutions>,
+    ) -> Result<(), MainError> {
+        fn solvers(solutions: Option<&KataSolutions>) -> HashSet<UserId> {
+            solutions
+                .into_iter()
+                .flatten()
+                .map(|(user, _)| *user)
+                .collect()
+        }
+
+        let changed: HashSet<&String> = old
+            .keys()
+            .chain(new.keys())
+            .filter(|key| old.get(*key) != new.get(*key))
+            .collect();
+        let mut deltas: HashMap<UserId, i64> = HashMap::new();
+        for key in &changed {
+            let (before, after) = (solvers(old.get(*key)), solvers(new.get(*key)));
+            for user in after.difference(&before) {
+                *deltas.entry(*user).or_insert(0) += 1;
+            }
+            for user in before.difference(&after) {
+                *deltas.entry(*user).or_insert(0) -= 1;
+            }
+        }
+        // a kata merged under another name touches two keys, both change or neither
+        retry(|| {
+            self.kata_index.transaction(|tx| {
+                for key in &changed {
+                    match new.get(*key) {
+                        Some(solutions) => {
+                            tx.insert(&(chat_id, (*key).clone()), solutions.clone())?
+                        }
+                        None => tx.remove(&(chat_id, (*key).clone()))?,
+                    }
+                }
+                Ok(())
+            })
+        })?;
+
+        if deltas.values().any(|delta| *delta != 0) {
+            let mut counts = self
+                .solved_counts
+                .get(&chat_id)?
+                .map_or(HashMap::new(), identity);
+            for (user, delta) in deltas {
+                let count = *counts.get(&user).unwrap_or(&0) as i64 + delta;
+                if count > 0 {
+                    counts.insert(user, count as usize);
+                } else {
+                    counts.remove(&user);
+                }
+            }
+            retry(|| self.solved_counts.insert(&chat_id, counts.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Messages stored before the parsed fields existed are parsed once, codewars
+    /// was the only solution source back then
+    fn migrate_parsed_fields(&self) -> Result<(), MainError> {
+        let mut parsed = 0;
+        for entry in self.messages.iter() {
+            let (chat_id, mut messages) = entry?;
+            parsed += reparse(&mut messages, &Codewars, &self.get_users(chat_id)?);
+            self.messages.insert(&chat_id, messages)?;
+        }
+        for entry in self.imported_messages.iter() {
+            let (chat_name, mut messages) = entry?;
+            parsed += reparse(&mut messages, &Codewars, &HashMap::new());
+            self.imported_messages.insert(&chat_name, messages)?;
+        }
+        if parsed > 0 {
+            self.rebuild_all_indexes()?;
+        }
+        self.migrations.insert(&PARSED_FIELDS.to_owned(), true)?;
+        log::info!("parsed fields filled in for {} stored messages", parsed);
+        Ok(())
+    }
+
+    /// Runs the parser over the stored messages of the chat again, the only place
+    /// parsing is redone after a message is stored, returns how many changed
+    pub fn reparse_messages(
+        &self,
+        chat_id: ChatId,
+        detector: &dyn SolutionDetector,
+    ) -> Result<usize, MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let mut messages = self.get_messages(chat_id)?;
+        let changed = reparse(&mut messages, detector, &self.get_users(chat_id)?);
+        if changed > 0 {
+            self.alias_kata_ids(&mut messages)?;
+            self.store_messages(chat_id, messages)?;
+        }
+        log::info!("{} messages reparsed in chat {:?}", changed, &chat_id);
+        Ok(changed)
+    }
+
+    /// Rebuilds the derived stores of every chat, for data written before they existed
+    pub fn rebuild_all_indexes(&self) -> Result<(), MainError> {
+        for entry in self.messages.iter() {
+            let (chat_id, _) = entry?;
+            self.rebuild_indexes(chat_id)?;
+        }
+        log::info!("kata index and solved counters rebuilt");
+        Ok(())
+    }
+
+    /// Throws away the kata index, the solved counters and the message counter
+    /// of the chat and fills them again from the stored messages. Writes of the
+    /// chat wait until it's done, and the kata index is swapped in one sled transaction
+    pub fn rebuild_indexes(&self, chat_id: ChatId) -> Result<Recomputed, MainError> {
+        self.transaction(chat_id, |db| {
+            let messages = db.get_messages(chat_id)?;
+            let index = index_katas(&messages);
+            let mut counts: HashMap<UserId, usize> = HashMap::new();
+            for solutions in index.values() {
+                for user in solutions.iter().map(|(user, _)| *user).unique() {
+                    *counts.entry(user).or_insert(0) += 1;
+                }
+            }
+
+            let old_index: HashMap<_, _> = db.katas_of_chat(chat_id)?.into_iter().collect();
+            let old_counts = db
+                .solved_counts
+                .get(&chat_id)?
+                .map_or(HashMap::new(), identity);
+            let old_count = db.message_counts.get(&chat_id)?;
+            let recomputed = Recomputed {
+                katas: old_index
+                    .keys()
+                    .chain(index.keys())
+                    .unique()
+                    .filter(|key| old_index.get(*key) != index.get(*key))
+                    .count(),
+                counters: old_counts
+                    .keys()
+                    .chain(counts.keys())
+                    .unique()
+                    .filter(|user| old_counts.get(*user) != counts.get(*user))
+                    .count(),
+                message_count: old_count != Some(messages.len()),
+            };
+
+            retry(|| {
+                db.kata_index.transaction(|tx| {
+                    for key in old_index.keys().filter(|key| !index.contains_key(*key)) {
+                        tx.remove(&(chat_id, key.clone()))?;
+                    }
+                    for (key, solutions) in &index {
+                        tx.insert(&(chat_id, key.clone()), solutions.clone())?;
+                    }
+                    Ok(())
+                })
+            })?;
+            retry(|| db.solved_counts.insert(&chat_id, counts.clone()))?;
+            retry(|| db.message_counts.insert(&chat_id, messages.len()))?;
+            log::info!(
+                "derived stores of chat {:?} rebuilt: {:?}",
+                &chat_id,
+                recomputed
+            );
+            Ok(recomputed)
+        })
+    }
+
+    /// Distinct katas the user has posted in the chat, read from a maintained counter
+    pub fn user_solved_count(&self, chat_id: ChatId, user: UserId) -> Result<usize, MainError> {
+        Ok(self
+            .solved_counts
+            .get(&chat_id)?
+            .and_then(|counts| counts.get(&user).copied())
+            .unwrap_or(0))
+    }
+
+    /// Every indexed kata of the chat with its solutions
+    pub fn katas_of_chat(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Vec<(String, KataSolutions)>, MainError> {
+        let mut katas = Vec::new();
+        for entry in self.kata_index.iter_first(&chat_id)? {
+            let ((_, name), solutions) = entry?;
+            katas.push((name, solutions));
+        }
+        Ok(katas)
+    }
+
+    /// Solutions of the kata, with or without the kyu in `name`
+    pub fn messages_for_kata(
+        &self,
+        chat_id: ChatId,
+        name: &str,
+    ) -> Result<KataSolutions, MainError> {
+        Ok(self
+            .kata_index
+            .get(&(chat_id, self.lookup_key(name)?))?
+            .map_or(Vec::new(), identity))
+    }
+
+    /// Index key of a kata typed by a member, its id if any variant of the name was resolved
+    fn lookup_key(&self, name: &str) -> Result<String, MainError> {
+        Ok(self
+            .kata_alias(name)?
+            .unwrap_or_else(|| kata_index_key(name)))
+    }
+
+    /// Codewars id the name was resolved to, with or without the kyu in `name`
+    pub fn kata_alias(&self, name: &str) -> Result<Option<String>, MainError> {
+        self.kata_aliases.get(&kata_index_key(name))
+    }
+
+    pub fn set_kata_alias(&self, name: &str, kata_id: &str) -> Result<(), MainError> {
+        self.kata_aliases
+            .insert(&kata_index_key(name), kata_id.to_owned())
+    }
+
+    /// Whether the API already failed to resolve the name
+    pub fn is_kata_unresolved(&self, name: &str) -> Result<bool, MainError> {
+        Ok(self
+            .unresolved_katas
+            .get(&kata_index_key(name))?
+            .unwrap_or(false))
+    }
+
+    pub fn set_kata_unresolved(&self, name: &str) -> Result<(), MainError> {
+        self.unresolved_katas.insert(&kata_index_key(name), true)
+    }
+
+    /// Gives the messages without an id the one their name was resolved to, returns how many
+    fn alias_kata_ids(&self, messages: &mut [ChatMessage]) -> Result<usize, MainError> {
+        let mut aliased = 0;
+        for msg in messages.iter_mut().filter(|msg| msg.kata_id.is_none()) {
+            if let Some(solution) = &msg.solution {
+                msg.kata_id = self.kata_alias(&solution.kata_name)?;
+                aliased += msg.kata_id.is_some() as usize;
+            }
+        }
+        Ok(aliased)
+    }
+
+    /// Whether the messages stored before katas were resolved still need their ids
+    pub fn needs_kata_ids(&self) -> Result<bool, MainError> {
+        Ok(self.migrations.get(&KATA_IDS.to_owned())?.is_none())
+    }
+
+    /// Names of the stored solutions that have no id yet and weren't asked about
+    pub fn unresolved_kata_names(&self) -> Result<Vec<String>, MainError> {
+        let mut names = HashMap::new();
+        let stored = self
+            .messages
+            .iter()
+            .map(|entry| entry.map(|(_, msgs)| msgs));
+        let imported = self
+            .imported_messages
+            .iter()
+            .map(|entry| entry.map(|(_, msgs)| msgs));
+        for messages in stored.chain(imported) {
+            for msg in messages?.into_iter().filter(|msg| msg.kata_id.is_none()) {
+                if let Some(solution) = msg.solution {
+                    names
+                        .entry(kata_index_key(&solution.kata_name))
+                        .or_insert(solution.kata_name);
+                }
+            }
+        }
+        let mut unresolved = Vec::new();
+        for name in names.values() {
+            if self.kata_alias(name)?.is_none() && !self.is_kata_unresolved(name)? {
+                unresolved.push(name.clone());
+            }
+        }
+        Ok(unresolved)
+    }
+
+    /// Fills in the ids of the messages stored before katas were resolved from the
+    /// aliases and rebuilds the indexes, so an old kata and its next post are one kata
+    pub fn backfill_kata_ids(&self) -> Result<usize, MainError> {
+        let mut aliased = 0;
+        for entry in self.messages.iter() {
+            let (chat_id, mut messages) = entry?;
+            aliased += self.alias_kata_ids(&mut messages)?;
+            self.messages.insert(&chat_id, messages)?;
         }
+        for entry in self.imported_messages.iter() {
+            let (chat_name, mut messages) = entry?;
+            aliased += self.alias_kata_ids(&mut messages)?;
+            self.imported_messages.insert(&chat_name, messages)?;
+        }
+        if aliased > 0 {
+            self.rebuild_all_indexes()?;
+        }
+        self.migrations.insert(&KATA_IDS.to_owned(), true)?;
+        log::info!("kata ids filled in for {} stored messages", aliased);
+        Ok(aliased)
     }
 
     pub fn add_message(&self, chat_id: ChatId, msg: ChatMessage) -> Result<(), MainError> {
-        let mut messages = self.messages.get(&chat_id)?.map_or(Vec::new(), identity);
+        let _guard = self.chat_locks.lock(chat_id);
+        let mut messages = self.get_messages(chat_id)?;
         messages.push(msg.clone());
-        self.messages.insert(&chat_id, messages)?;
+        self.store_messages(chat_id, messages)?;
         log::info!("message {:?} added to chat {:?}", &msg, &chat_id);
         Ok(())
     }
 
-    pub fn add_imported_message(
+    /// Writes all messages at once, messages with an already stored id are skipped
+    /// so running the same import twice doesn't duplicate them
+    pub fn add_imported_messages_batch(
         &self,
         chat_name: ChatName,
-        msg: ChatMessage,
-    ) -> Result<(), MainError> {
+        batch: Vec<ChatMessage>,
+    ) -> Result<usize, MainError> {
         let mut messages = self
             .imported_messages
             .get(&chat_name)?
             .map_or(Vec::new(), identity);
-        messages.push(msg.clone());
-        self.imported_messages.insert(&chat_name, messages)?;
-        log::info!("imported message {:?} added to chat {:?}", &msg, &chat_name);
-        Ok(())
+        let mut ids: HashSet<_> = messages.iter().map(|msg| msg.id).collect();
+        let before = messages.len();
+        messages.extend(batch.into_iter().filter(|msg| ids.insert(msg.id)));
+        let added = messages.len() - before;
+        retry(|| self.imported_messages.insert(&chat_name, messages.clone()))?;
+        log::info!("{} imported messages added to chat {:?}", added, &chat_name);
+        Ok(added)
     }
 
     pub fn clear_messages(&self, chat_id: ChatId) -> Result<(), MainError> {
-        self.messages.insert(&chat_id, Vec::<ChatMessage>::new())?;
+        self.store_messages(chat_id, Vec::new())?;
         log::info!("messages cleared in chat {:?}", &chat_id);
         Ok(())
     }
 
     pub fn clear_imported_messages(&self, chat: ChatName) -> Result<(), MainError> {
-        self.imported_messages
-            .insert(&chat, Vec::<ChatMessage>::new())?;
+        retry(|| {
+            self.imported_messages
+                .insert(&chat, Vec::<ChatMessage>::new())
+        })?;
         log::info!("imported messages cleared in chat {:?}", &chat);
         Ok(())
     }
@@ -90,6 +876,281 @@ impl Persist {
         Ok(self.messages.get(&chat_id)?.map_or(Vec::new(), identity))
     }
 
+    /// Katas with a single solver from the kata index, the hardest first and
+    /// katas posted without a kyu last
+    pub fn lone_solves(&self, chat_id: ChatId) -> Result<Vec<LoneSolve>, MainError> {
+        let messages: HashMap<i32, ChatMessage> = self
+            .get_messages(chat_id)?
+            .into_iter()
+            .map(|msg| (msg.id, msg))
+            .collect();
+        let mut lone: Vec<_> = self
+            .katas_of_chat(chat_id)?
+            .into_iter()
+            .filter(|(_, solutions)| solutions.iter().map(|(user, _)| user).all_equal())
+            .filter_map(|(_, solutions)| {
+                let msg = solutions
+                    .iter()
+                    .filter_map(|(_, id)| messages.get(id))
+                    .min_by_key(|msg| msg.posted_order())?;
+                let (solver, solution) = (msg.from, msg.solution.as_ref()?);
+                Some(LoneSolve {
+                    name: strip_kyu(&solution.kata_name).to_owned(),
+                    kyu: solution.kyu,
+                    solver,
+                })
+            })
+            .collect();
+        lone.sort_by_key(|solve| {
+            (
+                solve.kyu.is_none(),
+                solve.kyu,
+                normalize_kata_name(&solve.name),
+            )
+        });
+        Ok(lone)
+    }
+
+    /// Katas with a known kyu, the hardest first. The kyu is the one of the
+    /// earliest post naming one, reposts with another kyu don't move the kata
+    pub fn top_katas(&self, chat_id: ChatId) -> Result<Vec<TopKata>, MainError> {
+        let messages: HashMap<i32, ChatMessage> = self
+            .get_messages(chat_id)?
+            .into_iter()
+            .map(|msg| (msg.id, msg))
+            .collect();
+        let mut top: Vec<_> = self
+            .katas_of_chat(chat_id)?
+            .into_iter()
+            .filter_map(|(_, solutions)| {
+                let posts: Vec<_> = solutions
+                    .iter()
+                    .filter_map(|(_, id)| messages.get(id))
+                    .sorted_by_key(|msg| msg.posted_order())
+                    .collect();
+                let kyu = posts.iter().find_map(|msg| msg.solution.as_ref()?.kyu)?;
+                Some(TopKata {
+                    name: strip_kyu(&posts.first()?.solution.as_ref()?.kata_name).to_owned(),
+                    kyu,
+                    solvers: posts.iter().map(|msg| msg.from).unique().collect(),
+                })
+            })
+            .collect();
+        top.sort_by_key(|kata| (kata.kyu, normalize_kata_name(&kata.name)));
+        Ok(top)
+    }
+
+    /// Distinct katas of the chat read from the kata index, unlike `count_messages` reposts don't count
+    pub fn distinct_kata_count(&self, chat_id: ChatId) -> Result<usize, MainError> {
+        self.kata_index.count_first(&chat_id)
+    }
+
+    /// The numbers of `/count`, only the maintained counters and the kata index are read
+    pub fn chat_counts(&self, chat_id: ChatId, user: UserId) -> Result<ChatCounts, MainError> {
+        let counts = self.solved_counts.get(&chat_id)?.unwrap_or_default();
+        let own = counts.get(&user).copied().unwrap_or(0);
+        Ok(ChatCounts {
+            solutions: self.count_messages(chat_id)?,
+            distinct: self.distinct_kata_count(chat_id)?,
+            own,
+            rank: Some(1 + counts.values().filter(|&&count| count > own).count())
+                .filter(|_| own > 0),
+            solvers: counts.values().filter(|&&count| count > 0).count(),
+        })
+    }
+
+    /// Reads a maintained counter instead of deserializing the messages.
+    /// Chats stored before the counter existed are counted once and cached
+    pub fn count_messages(&self, chat_id: ChatId) -> Result<usize, MainError> {
+        match self.message_counts.get(&chat_id)? {
+            Some(count) => Ok(count),
+            None => {
+                let count = self.get_messages(chat_id)?.len();
+                self.message_counts.insert(&chat_id, count)?;
+                Ok(count)
+            }
+        }
+    }
+
+    /// Removes the stored copy of one telegram message, `false` if there was none.
+    /// The bot API doesn't report deletions in groups so this is only called on request
+    pub fn remove_message(&self, chat_id: ChatId, id: i32) -> Result<bool, MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let messages = self.get_messages(chat_id)?;
+        let before = messages.len();
+        let kept: Vec<_> = messages.into_iter().filter(|msg| msg.id != id).collect();
+        let removed = kept.len() < before;
+        if removed {
+            self.store_messages(chat_id, kept)?;
+            log::info!("message {} removed in chat {:?}", id, &chat_id);
+        }
+        Ok(removed)
+    }
+
+    /// Deletes the messages that are out of the chat's retention window at `now`,
+    /// returns how many went
+    pub fn prune_expired(&self, chat_id: ChatId, now: DateTime<Utc>) -> Result<usize, MainError> {
+        let settings = self.get_settings(chat_id)?;
+        let cutoff = match settings.retention_days {
+            Some(days) => now - chrono::Duration::days(i64::from(days)),
+            None => return Ok(0),
+        };
+        let _guard = self.chat_locks.lock(chat_id);
+        let messages = self.get_messages(chat_id)?;
+        let before = messages.len();
+        let kept: Vec<_> = messages
+            .into_iter()
+            .filter(|msg| match msg.date {
+                Some(date) => date >= cutoff,
+                None => !settings.purge_untimestamped,
+            })
+            .collect();
+        let removed = before - kept.len();
+        if removed > 0 {
+            self.store_messages(chat_id, kept)?;
+        }
+        Ok(removed)
+    }
+
+    /// `prune_expired` over every chat with a retention window
+    pub fn prune_all_expired(&self, now: DateTime<Utc>) -> Result<usize, MainError> {
+        let mut total = 0;
+        for entry in self.settings.iter() {
+            let (chat_id, settings) = entry?;
+            if settings.retention_days.is_some() {
+                let removed = self.prune_expired(chat_id, now)?;
+                if removed > 0 {
+                    log::info!("{} expired messages pruned in chat {:?}", removed, &chat_id);
+                }
+                total += removed;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Removes every solution of the kata in the chat, with or without the kyu in `name`
+    pub fn delete_kata(&self, chat_id: ChatId, name: &str) -> Result<usize, MainError> {
+        let (key, name_key) = (self.lookup_key(name)?, kata_index_key(name));
+        let _guard = self.chat_locks.lock(chat_id);
+        let messages = self.get_messages(chat_id)?;
+        let before = messages.len();
+        let kept: Vec<_> = messages
+            .into_iter()
+            .filter(|msg| match msg.solution {
+                Some(ref solution) => {
+                    msg.kata_key() != Some(key.clone())
+                        && kata_index_key(&solution.kata_name) != name_key
+                }
+                None => true,
+            })
+            .collect();
+        let removed = before - kept.len();
+        if removed > 0 {
+            self.store_messages(chat_id, kept)?;
+        }
+        log::info!(
+            "{} solutions of kata {} deleted in chat {:?}",
+            removed,
+            name,
+            &chat_id
+        );
+        Ok(removed)
+    }
+
+    /// Moves messages of `from` to `to` dropping katas `to` has already posted.
+    /// Everything is written in a single insert so a failure leaves the chat untouched
+    pub fn reassign_messages(
+        &self,
+        chat_id: ChatId,
+        from: UserId,
+        to: UserId,
+    ) -> Result<usize, MainError> {
+        fn kata(msg: &ChatMessage) -> String {
+            msg.kata_key().unwrap_or_else(|| msg.text.clone())
+        }
+
+        let _guard = self.chat_locks.lock(chat_id);
+        let messages = self.get_messages(chat_id)?;
+        let mut solved: HashSet<String> = messages
+            .iter()
+            .filter(|msg| msg.from == to)
+            .map(kata)
+            .collect();
+        let mut merged = Vec::with_capacity(messages.len());
+        for mut msg in messages {
+            if msg.from == from {
+                if !solved.insert(kata(&msg)) {
+                    continue;
+                }
+                msg.from = to;
+            }
+            merged.push(msg);
+        }
+        let total = merged.iter().filter(|msg| msg.from == to).count();
+        self.store_messages(chat_id, merged)?;
+        log::info!(
+            "messages of user {:?} reassigned to user {:?} in chat {:?}",
+            &from,
+            &to,
+            &chat_id
+        );
+        Ok(total)
+    }
+
+    /// Deletes every message of the user in the chat, returns how many went
+    pub fn remove_messages_of(&self, chat_id: ChatId, user: UserId) -> Result<usize, MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let messages = self.get_messages(chat_id)?;
+        let before = messages.len();
+        let kept: Vec<_> = messages
+            .into_iter()
+            .filter(|msg| msg.from != user)
+            .collect();
+        let removed = before - kept.len();
+        if removed > 0 {
+            self.store_messages(chat_id, kept)?;
+            log::info!(
+                "{} messages of user {:?} removed in chat {:?}",
+                removed,
+                &user,
+                &chat_id
+            );
+        }
+        Ok(removed)
+    }
+
+    /// `/removeuser`, the messages go too with `purge` in the same `transaction`
+    pub fn remove_member(
+        &self,
+        chat_id: ChatId,
+        user: UserId,
+        purge: bool,
+    ) -> Result<usize, MainError> {
+        self.transaction(chat_id, |db| {
+            let removed = if purge {
+                db.remove_messages_of(chat_id, user)?
+            } else {
+                0
+            };
+            db.remove_user(chat_id, user)?;
+            Ok(removed)
+        })
+    }
+
+    /// `reassign_messages` and the removal of `from` as one `transaction`
+    pub fn merge_users(
+        &self,
+        chat_id: ChatId,
+        from: UserId,
+        to: UserId,
+    ) -> Result<usize, MainError> {
+        self.transaction(chat_id, |db| {
+            let total = db.reassign_messages(chat_id, from, to)?;
+            db.remove_user(chat_id, from)?;
+            Ok(total)
+        })
+    }
+
     //pub fn get_imported_messages(
     //    &self,
     //    chat_name: ChatName,
@@ -107,7 +1168,7 @@ impl Persist {
     ) -> Result<(), MainError> {
         let imported = self.imported_messages.get(&chat_name)?;
         match imported {
-            Some(v) => self.messages.insert(&chat_id, v)?,
+            Some(v) => self.store_messages(chat_id, v)?,
             None => (),
         };
         self.was_chat_imported.insert(&chat_name, true)?;
@@ -131,35 +1192,1426 @@ impl Persist {
         Ok(self.was_chat_imported.insert(&chat_name, false)?)
     }
 
-    pub fn add_user(&self, chat_id: ChatId, user: CodeUser) -> Result<(), MainError> {
-        let mut map = match self.users.get(&chat_id)? {
-            None => HashMap::new(),
-            Some(val) => val,
-        };
-        let user1 = user.clone();
-        map.insert(user1.telegram_id, user1);
-        self.users.insert(&chat_id, map)?;
-        log::info!("user {:?} added in chat {:?}", &user, &chat_id);
+    /// Replaces the chat's previous import report
+    pub fn set_import_report(
+        &self,
+        chat_id: ChatId,
+        report: &ImportReport,
+    ) -> Result<(), MainError> {
+        retry(|| self.import_reports.insert(&chat_id, report.clone()))?;
+        Ok(())
+    }
+
+    /// `None` until an import has run
+    pub fn last_import_report(&self, chat_id: ChatId) -> Result<Option<ImportReport>, MainError> {
+        self.import_reports.get(&chat_id)
+    }
+
+    /// Every write to `users` goes through here. The cache's write lock is held
+    /// until both the disk and the cache have the new users, so no read sees one
+    /// without the other
+    fn update_users(
+        &self,
+        chat_id: ChatId,
+        mut f: impl FnMut(&mut HashMap<UserId, CodeUser>),
+    ) -> Result<(), MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let mut cache = self
+            .user_cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // dropped first so a failed write can't leave the cache ahead of the disk
+        cache.remove(&chat_id);
+        let users = retry(|| {
+            let mut users = self.users.get(&chat_id)?.map_or(HashMap::new(), identity);
+            f(&mut users);
+            self.users.insert(&chat_id, users.clone())?;
+            Ok(users)
+        })?;
+        cache.insert(chat_id, users);
         Ok(())
     }
 
+    /// Replaces the user's registration in the chat, returns the one it replaced
+    pub fn add_user(&self, chat_id: ChatId, user: CodeUser) -> Result<Option<CodeUser>, MainError> {
+        let mut previous = None;
+        self.update_users(chat_id, |users| {
+            previous = users.insert(user.telegram_id, user.clone());
+        })?;
+        log::info!("user {:?} added in chat {:?}", &user, &chat_id);
+        Ok(previous)
+    }
+
     pub fn remove_user(&self, chat_id: ChatId, user_to_remove: UserId) -> Result<(), MainError> {
-        let mut users: HashMap<UserId, CodeUser> =
-            self.users.get(&chat_id)?.map_or(HashMap::new(), identity);
-        users.remove(&user_to_remove);
-        self.users.insert(&chat_id, users)?;
+        self.update_users(chat_id, |users| {
+            users.remove(&user_to_remove);
+        })?;
         log::info!("user {:?} removed in chat {:?}", &user_to_remove, &chat_id);
         Ok(())
     }
 
     pub fn clear_users(&self, chat_id: ChatId) -> Result<(), MainError> {
-        self.users
-            .insert(&chat_id, HashMap::<UserId, CodeUser>::new())?;
+        self.update_users(chat_id, HashMap::clear)?;
         log::info!("users cleared in chat {:?}", &chat_id);
         Ok(())
     }
 
+    /// Runs `f` and puts the chat's users and messages back, and with them the
+    /// counters and the kata index, if it fails. Other writes of the chat wait
+    /// until it's done so the rollback can't drop them. The stores are separate
+    /// sled databases so a crash in the middle isn't covered, only errors
+    pub fn transaction<A>(
+        &self,
+        chat_id: ChatId,
+        f: impl FnOnce(&Self) -> Result<A, MainError>,
+    ) -> Result<A, MainError> {
+        let _guard = self.chat_locks.lock(chat_id);
+        let users = self.get_users(chat_id)?;
+        let messages = self.get_messages(chat_id)?;
+        let e = match f(self) {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        log::warn!("rolling back chat {:?} after {}", &chat_id, e);
+        let restored = self
+            .update_users(chat_id, |current| *current = users.clone())
+            .and_then(|_| self.store_messages(chat_id, messages));
+        if let Err(restore) = restored {
+            log::error!("chat {:?} couldn't be rolled back: {}", &chat_id, restore);
+        }
+        Err(e)
+    }
+
+    /// Wipes both users and their messages, neither is lost alone
+    pub fn clear_chat(&self, chat_id: ChatId) -> Result<(), MainError> {
+        self.transaction(chat_id, |db| {
+            db.clear_messages(chat_id)?;
+            db.clear_users(chat_id)
+        })
+    }
+
+    /// Chats where the user is registered
+    pub fn chats_of_user(&self, user: UserId) -> Result<Vec<ChatId>, MainError> {
+        let mut chats = Vec::new();
+        for entry in self.users.iter() {
+            let (chat_id, users) = entry?;
+            if users.contains_key(&user) {
+                chats.push(chat_id);
+            }
+        }
+        Ok(chats)
+    }
+
+    /// From the cache once the chat has been read
     pub fn get_users(&self, chat_id: ChatId) -> Result<HashMap<UserId, CodeUser>, MainError> {
-        Ok(self.users.get(&chat_id)?.map_or(HashMap::new(), identity))
+        let cache = self
+            .user_cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(users) = cache.get(&chat_id) {
+            return Ok(users.clone());
+        }
+        drop(cache);
+        let mut cache = self
+            .user_cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // another thread may have filled it while this one waited for the lock
+        if let Some(users) = cache.get(&chat_id) {
+            return Ok(users.clone());
+        }
+        let users = self.users.get(&chat_id)?.map_or(HashMap::new(), identity);
+        cache.insert(chat_id, users.clone());
+        Ok(users)
+    }
+
+    /// Users as shared outputs may show them, pseudonyms for the anonymized ones
+    pub fn get_shared_users(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<HashMap<UserId, CodeUser>, MainError> {
+        let settings = self.get_settings(chat_id)?;
+        self.get_users(chat_id)?
+            .into_iter()
+            .map(|(id, user)| {
+                let user_settings = self.get_user_settings(chat_id, id)?;
+                if user_settings.anonymized(&settings) {
+                    Ok((id, user.anonymized()))
+                } else {
+                    Ok((id, user))
+                }
+            })
+            .collect()
+    }
+
+    /// Whether shared outputs show the user under a pseudonym, mentioning them would link the account
+    pub fn is_anonymized(&self, chat_id: ChatId, user: UserId) -> Result<bool, MainError> {
+        let settings = self.get_settings(chat_id)?;
+        Ok(self.get_user_settings(chat_id, user)?.anonymized(&settings))
+    }
+
+    pub fn get_settings(&self, chat_id: ChatId) -> Result<ChatSettings, MainError> {
+        Ok(self
+            .settings
+            .get(&chat_id)?
+            .map_or(ChatSettings::default(), identity))
+    }
+
+    pub fn set_settings(&self, chat_id: ChatId, settings: ChatSettings) -> Result<(), MainError> {
+        self.settings.insert(&chat_id, settings.clone())?;
+        log::info!("settings {:?} set in chat {:?}", &settings, &chat_id);
+        Ok(())
+    }
+
+    pub fn get_user_settings(
+        &self,
+        chat_id: ChatId,
+        user: UserId,
+    ) -> Result<UserSettings, MainError> {
+        Ok(self
+            .user_settings
+            .get(&(chat_id, user))?
+            .map_or(UserSettings::default(), identity))
+    }
+
+    pub fn set_user_settings(
+        &self,
+        chat_id: ChatId,
+        user: UserId,
+        settings: UserSettings,
+    ) -> Result<(), MainError> {
+        self.user_settings
+            .insert(&(chat_id, user), settings.clone())?;
+        log::info!(
+            "user settings {:?} set for user {:?} in chat {:?}",
+            &settings,
+            &user,
+            &chat_id
+        );
+        Ok(())
+    }
+
+    /// The user's own data, real names even when shared outputs anonymize them
+    pub fn export_user(&self, chat_id: ChatId, user: UserId) -> Result<UserExport, MainError> {
+        Ok(UserExport {
+            user: self.get_users(chat_id)?.remove(&user),
+            settings: self.get_user_settings(chat_id, user)?,
+            messages: self
+                .get_messages(chat_id)?
+                .into_iter()
+                .filter(|msg| msg.from == user)
+                .collect(),
+        })
+    }
+
+    pub fn export_chat(&self, chat_id: ChatId) -> Result<ChatExport, MainError> {
+        let mut users: Vec<_> = self.get_users(chat_id)?.values().cloned().collect();
+        users.sort_by_key(|user| user.telegram_id.0);
+        Ok(ChatExport {
+            users,
+            messages: self.get_messages(chat_id)?,
+        })
+    }
+
+    /// Adds the users and messages of an export the chat doesn't have yet,
+    /// matched by telegram id and message id, so restoring twice changes nothing
+    pub fn restore(&self, chat_id: ChatId, export: ChatExport) -> Result<Restored, MainError> {
+        self.transaction(chat_id, |db| {
+            let mut restored = Restored::default();
+            let users = db.get_users(chat_id)?;
+            for user in export.users {
+                if !users.contains_key(&user.telegram_id) {
+                    db.add_user(chat_id, user)?;
+                    restored.users += 1;
+                }
+            }
+            let mut messages = db.get_messages(chat_id)?;
+            let mut known: HashSet<i32> = messages.iter().map(|msg| msg.id).collect();
+            for msg in export.messages {
+                if known.insert(msg.id) {
+                    messages.push(msg);
+                    restored.messages += 1;
+                }
+            }
+            if restored.messages > 0 {
+                db.store_messages(chat_id, messages)?;
+            }
+            log::info!("restored {:?} in chat {:?}", &restored, &chat_id);
+            Ok(restored)
+        })
+    }
+
+    /// Official kyu cached by codewars kata id
+    pub fn get_kata_kyu(&self, kata_id: &str) -> Result<Option<u8>, MainError> {
+        self.kata_kyus.get(&kata_id.to_owned())
+    }
+
+    pub fn set_kata_kyu(&self, kata_id: &str, kyu: u8) -> Result<(), MainError> {
+        self.kata_kyus.insert(&kata_id.to_owned(), kyu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn temp_persist() -> Persist {
+        Persist::temporary()
+    }
+
+    fn message(id: i32, from: i32, text: &str) -> ChatMessage {
+        ChatMessage {
+            id,
+            solution: Codewars.parse_solution(text),
+            text: text.to_owned(),
+            from: UserId(from),
+            kata_id: None,
+            date: None,
+        }
+    }
+
+    fn solution(id: i32, from: i32, name: &str) -> ChatMessage {
+        message(
+            id,
+            from,
+            &format!("7\n{}\nhttps://pastebin.com/abc{}", name, id),
+        )
+    }
+
+    fn user(id: i32) -> CodeUser {
+        CodeUser {
+            username: None,
+            firstname: format!("user{}", id),
+            telegram_id: UserId(id),
+            codewars_name: format!("cw{}", id),
+            default_language: None,
+        }
+    }
+
+    #[test]
+    fn old_chat_settings_get_defaults() {
+        let settings: ChatSettings =
+            serde_json::from_str(r#"{"timezone":"Europe/Moscow","skip_forwards":true}"#).unwrap();
+        assert!(settings.skip_forwards);
+        assert_eq!(settings.acknowledge, Acknowledge::React);
+        assert_eq!((settings.burst_katas, settings.burst_minutes), (10, 5));
+
+        let settings: ChatSettings = serde_json::from_str(r#"{"acknowledge":"silent"}"#).unwrap();
+        assert_eq!(settings.acknowledge, Acknowledge::Silent);
+        assert_eq!(Acknowledge::parse("reply"), Some(Acknowledge::Reply));
+        assert_eq!(Acknowledge::parse("loud"), None);
+    }
+
+    #[test]
+    fn min_kyu_drops_easier_katas() {
+        let settings = ChatSettings {
+            min_kyu: Some(6),
+            ..ChatSettings::default()
+        };
+        let tracked: Vec<_> = (1..=8)
+            .filter(|&kyu| settings.tracks_kyu(Some(kyu)))
+            .collect();
+        assert_eq!(tracked, vec![1, 2, 3, 4, 5, 6]);
+        assert!(settings.tracks_kyu(None));
+        assert!(ChatSettings::default().tracks_kyu(Some(8)));
+    }
+
+    #[test]
+    fn count_messages_reads_counter_only() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for id in 0..100 {
+            db.add_message(chat, solution(id, id % 3, "Robinson Crusoe"))
+                .unwrap();
+        }
+        assert_eq!(db.count_messages(chat).unwrap(), 100);
+
+        // the count must not depend on deserializing the messages
+        db.messages
+            .inner()
+            .insert(serde_json::to_vec(&chat).unwrap(), b"not json".to_vec())
+            .unwrap();
+        assert!(db.get_messages(chat).is_err());
+        assert_eq!(db.count_messages(chat).unwrap(), 100);
+
+        db.clear_messages(chat).unwrap();
+        assert_eq!(db.count_messages(chat).unwrap(), 0);
+    }
+
+    #[test]
+    fn reposts_count_as_solutions_but_not_as_katas() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let posts = [
+            (1, 10, "Robinson Crusoe"),
+            (2, 10, "Robinson Crusoe"),
+            (3, 20, "robinson  crusoe"),
+            (4, 20, "Create Phone Number"),
+        ];
+        for &(id, from, name) in posts.iter() {
+            db.add_message(chat, solution(id, from, name)).unwrap();
+        }
+        assert_eq!(db.count_messages(chat).unwrap(), 4);
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+        assert_eq!(distinct_katas_of(&db.get_messages(chat).unwrap()), 2);
+        assert_eq!(db.distinct_kata_count(ChatId(2)).unwrap(), 0);
+    }
+
+    #[test]
+    fn remove_message_updates_the_counters() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(2, 10, "Create Phone Number"))
+            .unwrap();
+        db.add_message(chat, solution(3, 20, "Robinson Crusoe"))
+            .unwrap();
+
+        assert!(db.remove_message(chat, 2).unwrap());
+        assert!(!db.remove_message(chat, 2).unwrap());
+        assert!(!db.remove_message(ChatId(2), 1).unwrap());
+        assert_eq!(db.count_messages(chat).unwrap(), 2);
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert!(db
+            .messages_for_kata(chat, "Create Phone Number")
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+    }
+
+    #[test]
+    fn retention_prunes_only_expired_messages() {
+        use chrono::TimeZone;
+
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let now = Utc.ymd(2020, 4, 30).and_hms(12, 0, 0);
+        let dated = |id, from, name, days_ago| ChatMessage {
+            date: Some(now - chrono::Duration::days(days_ago)),
+            ..solution(id, from, name)
+        };
+        db.add_message(chat, dated(1, 10, "Robinson Crusoe", 31))
+            .unwrap();
+        db.add_message(chat, dated(2, 10, "Create Phone Number", 30))
+            .unwrap();
+        db.add_message(chat, dated(3, 20, "Robinson Crusoe", 1))
+            .unwrap();
+        db.add_message(chat, solution(4, 20, "Valid Braces"))
+            .unwrap();
+        db.add_message(ChatId(2), dated(5, 10, "Robinson Crusoe", 100))
+            .unwrap();
+
+        assert_eq!(db.prune_all_expired(now).unwrap(), 0);
+        db.set_settings(
+            chat,
+            ChatSettings {
+                retention_days: Some(30),
+                ..ChatSettings::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.prune_all_expired(now).unwrap(), 1);
+        let ids = |chat| {
+            db.get_messages(chat)
+                .unwrap()
+                .iter()
+                .map(|msg| msg.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(chat), vec![2, 3, 4]);
+        assert_eq!(ids(ChatId(2)), vec![5]);
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+
+        db.set_settings(
+            chat,
+            ChatSettings {
+                retention_days: Some(30),
+                purge_untimestamped: true,
+                ..ChatSettings::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.prune_expired(chat, now).unwrap(), 1);
+        assert_eq!(ids(chat), vec![2, 3]);
+    }
+
+    #[test]
+    fn failed_transaction_rolls_everything_back() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from, name) in [(1, 10, "Robinson Crusoe"), (2, 20, "Valid Braces")].iter() {
+            db.add_message(chat, solution(*id, *from, name)).unwrap();
+        }
+        for id in [10, 20].iter() {
+            db.add_user(chat, user(*id)).unwrap();
+        }
+
+        let res = db.transaction(chat, |db| {
+            db.reassign_messages(chat, UserId(20), UserId(10))?;
+            db.remove_user(chat, UserId(20))?;
+            // a restore file that doesn't parse halfway through
+            Ok(serde_json::from_str::<ChatExport>("{")?)
+        });
+        assert!(res.is_err());
+        assert_eq!(db.get_users(chat).unwrap().len(), 2);
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 1);
+        assert_eq!(
+            db.messages_for_kata(chat, "Valid Braces").unwrap(),
+            vec![(UserId(20), 2)]
+        );
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+
+        assert_eq!(db.merge_users(chat, UserId(20), UserId(10)).unwrap(), 2);
+        assert_eq!(db.get_users(chat).unwrap().len(), 1);
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 2);
+    }
+
+    #[test]
+    fn rollback_keeps_writes_made_meanwhile() {
+        let db = Arc::new(temp_persist());
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+
+        let (started, writing) = std::sync::mpsc::channel();
+        let mut writer = None;
+        let res = db.transaction(chat, |tx| {
+            tx.clear_messages(chat)?;
+            let db = db.clone();
+            writer = Some(std::thread::spawn(move || {
+                started.send(()).unwrap();
+                db.add_message(chat, solution(2, 20, "Valid Braces"))
+                    .unwrap();
+            }));
+            writing.recv().unwrap();
+            // gives the writer time to block on the chat
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            Ok(serde_json::from_str::<ChatExport>("{")?)
+        });
+        assert!(res.is_err());
+        // the writer waited for the rollback instead of being overwritten by it
+        writer.unwrap().join().unwrap();
+        let ids: Vec<_> = db
+            .get_messages(chat)
+            .unwrap()
+            .iter()
+            .map(|msg| msg.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+    }
+
+    #[test]
+    fn rebuilds_wait_for_the_chat() {
+        let db = Arc::new(temp_persist());
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+
+        let mut rebuild = None;
+        db.transaction(chat, |tx| {
+            let db = db.clone();
+            rebuild = Some(std::thread::spawn(move || {
+                db.rebuild_indexes(chat).unwrap()
+            }));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!rebuild.as_ref().unwrap().is_finished());
+            tx.add_message(chat, solution(2, 20, "Valid Braces"))
+        })
+        .unwrap();
+        // it ran after the write, so there was nothing stale to fix
+        assert_eq!(rebuild.unwrap().join().unwrap(), Recomputed::default());
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 1);
+    }
+
+    #[test]
+    fn cached_users_follow_every_write() {
+        let db = Arc::new(temp_persist());
+        let chat = ChatId(1);
+        assert!(db.get_users(chat).unwrap().is_empty());
+
+        let writers: Vec<_> = (0..4)
+            .map(|id| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    db.add_user(chat, user(id)).unwrap();
+                    db.get_users(chat).unwrap().len()
+                })
+            })
+            .collect();
+        for writer in writers {
+            assert!(writer.join().unwrap() >= 1);
+        }
+        assert_eq!(db.get_users(chat).unwrap().len(), 4);
+        assert_eq!(db.users.get(&chat).unwrap().unwrap().len(), 4);
+
+        db.remove_user(chat, UserId(0)).unwrap();
+        assert!(!db.get_users(chat).unwrap().contains_key(&UserId(0)));
+        db.clear_users(chat).unwrap();
+        assert!(db.get_users(chat).unwrap().is_empty());
+        assert!(db.users.get(&chat).unwrap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn removed_members_leave_the_user_list() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from, name) in [(1, 10, "Robinson Crusoe"), (2, 20, "Valid Braces")].iter() {
+            db.add_message(chat, solution(*id, *from, name)).unwrap();
+        }
+        for id in [10, 20].iter() {
+            db.add_user(chat, user(*id)).unwrap();
+        }
+
+        assert_eq!(db.remove_member(chat, UserId(10), false).unwrap(), 0);
+        assert!(!db.get_users(chat).unwrap().contains_key(&UserId(10)));
+        assert_eq!(db.count_messages(chat).unwrap(), 2);
+
+        assert_eq!(db.remove_member(chat, UserId(20), true).unwrap(), 1);
+        assert!(db.get_users(chat).unwrap().is_empty());
+        assert_eq!(db.count_messages(chat).unwrap(), 1);
+        assert!(db
+            .messages_for_kata(chat, "Valid Braces")
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 0);
+    }
+
+    #[test]
+    fn restoring_an_export_twice_adds_it_once() {
+        let source = temp_persist();
+        let chat = ChatId(1);
+        for (id, from, name) in [(1, 10, "Robinson Crusoe"), (2, 10, "Valid Braces")].iter() {
+            source
+                .add_message(chat, solution(*id, *from, name))
+                .unwrap();
+        }
+        source.add_user(chat, user(10)).unwrap();
+        let json = serde_json::to_vec(&source.export_chat(chat).unwrap()).unwrap();
+
+        let db = temp_persist();
+        db.add_message(chat, solution(2, 10, "Valid Braces"))
+            .unwrap();
+        let export: ChatExport = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            db.restore(chat, export).unwrap(),
+            Restored {
+                users: 1,
+                messages: 1
+            }
+        );
+        let export: ChatExport = serde_json::from_slice(&json).unwrap();
+        assert_eq!(db.restore(chat, export).unwrap(), Restored::default());
+        assert_eq!(db.count_messages(chat).unwrap(), 2);
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 2);
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+
+        assert!(serde_json::from_slice::<ChatExport>(b"{\"users\": []}").is_err());
+    }
+
+    #[test]
+    fn only_the_last_import_is_reported() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        assert_eq!(db.last_import_report(chat).unwrap(), None);
+
+        let file = |name: &str, added| ImportedFile {
+            name: name.to_owned(),
+            added,
+            duplicates: 0,
+            skipped: 1,
+        };
+        let first = ImportReport {
+            finished: Utc::now(),
+            files: vec![file("result.json", 3)],
+        };
+        db.set_import_report(chat, &first).unwrap();
+        let second = ImportReport {
+            finished: Utc::now(),
+            files: vec![file("a.json", 2), file("b.json", 0)],
+        };
+        db.set_import_report(chat, &second).unwrap();
+        assert_eq!(db.last_import_report(chat).unwrap(), Some(second));
+        // other chats don't see the files of this one
+        assert_eq!(db.last_import_report(ChatId(2)).unwrap(), None);
+    }
+
+    #[test]
+    fn top_katas_take_the_first_known_kyu() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let posts = [
+            (1, 10, "8 Valid Braces"),
+            (2, 20, "4 kyu Valid Braces"),
+            (3, 10, "2 kyu Valid Braces"),
+            (4, 30, "6 Create Phone Number"),
+            (5, 20, "4 Sudoku Solver"),
+            (6, 30, "8 Unranked Kata"),
+        ];
+        for &(id, from, name) in posts.iter() {
+            let mut msg = message(
+                id,
+                from,
+                &format!("{}\nhttps://pastebin.com/abc{}", name, id),
+            );
+            if id == 1 || id == 6 {
+                let solution = msg.solution.as_mut().unwrap();
+                // as if posted without a kyu
+                solution.kata_name = strip_kyu(&solution.kata_name).to_owned();
+                solution.kyu = None;
+            }
+            db.add_message(chat, msg).unwrap();
+        }
+
+        let top = db.top_katas(chat).unwrap();
+        assert_eq!(
+            top.iter()
+                .map(|kata| (kata.name.as_str(), kata.kyu, kata.solvers.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Sudoku Solver", 4, vec![UserId(20)]),
+                ("Valid Braces", 4, vec![UserId(10), UserId(20)]),
+                ("Create Phone Number", 6, vec![UserId(30)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_solves_are_sorted_hardest_first() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let posts = [
+            (1, 10, "7\nRobinson Crusoe"),
+            (2, 20, "7 kyu Robinson Crusoe"),
+            (3, 10, "6 Create Phone Number"),
+            (4, 10, "6 Create Phone Number"),
+            (5, 20, "1 Valid Braces"),
+            (6, 30, "2 Sudoku Solver"),
+        ];
+        for &(id, from, name) in posts.iter() {
+            let mut msg = message(
+                id,
+                from,
+                &format!("{}\nhttps://pastebin.com/abc{}", name, id),
+            );
+            if id == 5 {
+                let solution = msg.solution.as_mut().unwrap();
+                // as if posted without a kyu
+                solution.kata_name = "Valid Braces".to_owned();
+                solution.kyu = None;
+            }
+            db.add_message(chat, msg).unwrap();
+        }
+
+        let lone = db.lone_solves(chat).unwrap();
+        assert_eq!(
+            lone,
+            vec![
+                LoneSolve {
+                    name: "Sudoku Solver".to_owned(),
+                    kyu: Some(2),
+                    solver: UserId(30),
+                },
+                LoneSolve {
+                    name: "Create Phone Number".to_owned(),
+                    kyu: Some(6),
+                    solver: UserId(10),
+                },
+                LoneSolve {
+                    name: "Valid Braces".to_owned(),
+                    kyu: None,
+                    solver: UserId(20),
+                },
+            ]
+        );
+        assert!(db.lone_solves(ChatId(2)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn export_user_contains_only_their_data() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from) in [(1, 10), (2, 20), (3, 10), (4, 30)].iter() {
+            db.add_message(chat, solution(*id, *from, "Robinson Crusoe"))
+                .unwrap();
+        }
+        db.add_message(ChatId(2), solution(5, 10, "Robinson Crusoe"))
+            .unwrap();
+        for id in [10, 20].iter() {
+            db.add_user(chat, user(*id)).unwrap();
+        }
+
+        let export = db.export_user(chat, UserId(10)).unwrap();
+        assert_eq!(export.user.unwrap().codewars_name, "cw10");
+        assert_eq!(
+            export.messages.iter().map(|msg| msg.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert!(export.messages.iter().all(|msg| msg.from == UserId(10)));
+
+        let export = db.export_user(chat, UserId(30)).unwrap();
+        assert!(export.user.is_none());
+        assert_eq!(export.messages.len(), 1);
+    }
+
+    #[test]
+    fn anonymized_output_keeps_aggregates() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from) in [(1, 10), (2, 20), (3, 10), (4, 30)].iter() {
+            db.add_message(chat, solution(*id, *from, &format!("Kata {}", id)))
+                .unwrap();
+        }
+        for id in [10, 20].iter() {
+            db.add_user(
+                chat,
+                CodeUser {
+                    username: Some(format!("handle{}", id)),
+                    firstname: format!("Name{}", id),
+                    ..user(*id)
+                },
+            )
+            .unwrap();
+        }
+        let messages = db.get_messages(chat).unwrap();
+        let named =
+            crate::stats::leaderboard_rows(Lang::En, &db.get_users(chat).unwrap(), &messages);
+        let counts = |rows: &[(String, usize)]| rows.iter().map(|row| row.1).collect::<Vec<_>>();
+        assert_eq!(
+            db.get_shared_users(chat).unwrap()[&UserId(10)].firstname,
+            "Name10"
+        );
+
+        let settings = ChatSettings {
+            anonymize: true,
+            ..db.get_settings(chat).unwrap()
+        };
+        db.set_settings(chat, settings).unwrap();
+        let users = db.get_shared_users(chat).unwrap();
+        let rows = crate::stats::leaderboard_rows(Lang::En, &users, &messages);
+        let text = format!("{:?}", rows);
+        for name in ["Name10", "Name20", "handle10", "handle20"].iter() {
+            assert!(!text.contains(name), "{} leaked", name);
+        }
+        assert_eq!(counts(&rows), counts(&named));
+        assert!(db.is_anonymized(chat, UserId(10)).unwrap());
+        // the user's own export isn't shared
+        let export = db.export_user(chat, UserId(10)).unwrap();
+        assert_eq!(export.user.unwrap().firstname, "Name10");
+        assert_eq!(users[&UserId(10)].firstname, pseudonym(UserId(10)));
+        assert_eq!(users[&UserId(10)].codewars_name, pseudonym(UserId(10)));
+        assert_eq!(pseudonym(UserId(10)), pseudonym(UserId(10)));
+        assert_ne!(pseudonym(UserId(10)), pseudonym(UserId(20)));
+
+        // a user can opt out of the chat's choice either way
+        let user_settings = UserSettings {
+            anonymize: Some(false),
+            ..db.get_user_settings(chat, UserId(20)).unwrap()
+        };
+        db.set_user_settings(chat, UserId(20), user_settings)
+            .unwrap();
+        let users = db.get_shared_users(chat).unwrap();
+        assert_eq!(users[&UserId(20)].firstname, "Name20");
+        assert_ne!(users[&UserId(10)].firstname, "Name10");
+        assert!(!db.is_anonymized(chat, UserId(20)).unwrap());
+    }
+
+    #[test]
+    fn clear_chat_empties_users_and_messages() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_user(chat, user(10)).unwrap();
+        db.add_message(ChatId(2), solution(2, 10, "Robinson Crusoe"))
+            .unwrap();
+
+        db.clear_chat(chat).unwrap();
+        assert!(db.get_users(chat).unwrap().is_empty());
+        assert!(db.get_messages(chat).unwrap().is_empty());
+        assert_eq!(db.count_messages(chat).unwrap(), 0);
+        assert_eq!(db.get_messages(ChatId(2)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn imported_batch_matches_per_message_path() {
+        let db = temp_persist();
+        let messages: Vec<_> = (0..500)
+            .map(|id| solution(id, id % 5, "Robinson Crusoe"))
+            .collect();
+
+        for msg in messages.iter().cloned() {
+            db.add_imported_messages_batch(ChatName("one by one".to_owned()), vec![msg])
+                .unwrap();
+        }
+        let added = db
+            .add_imported_messages_batch(ChatName("batch".to_owned()), messages.clone())
+            .unwrap();
+        assert_eq!(added, 500);
+
+        // importing again must not duplicate anything
+        let added = db
+            .add_imported_messages_batch(ChatName("batch".to_owned()), messages[..10].to_vec())
+            .unwrap();
+        assert_eq!(added, 0);
+
+        let ids = |name: &str| -> Vec<i32> {
+            db.imported_messages
+                .get(&ChatName(name.to_owned()))
+                .unwrap()
+                .unwrap()
+                .iter()
+                .map(|msg| msg.id)
+                .collect()
+        };
+        assert_eq!(ids("batch"), ids("one by one"));
+    }
+
+    #[test]
+    fn kata_index_follows_writes() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(2, 20, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(3, 20, "Create Phone Number"))
+            .unwrap();
+        assert_eq!(
+            db.messages_for_kata(chat, "7 robinson  crusoe").unwrap(),
+            vec![(UserId(10), 1), (UserId(20), 2)]
+        );
+        assert!(db
+            .messages_for_kata(ChatId(2), "Robinson Crusoe")
+            .unwrap()
+            .is_empty());
+
+        db.reassign_messages(chat, UserId(20), UserId(10)).unwrap();
+        assert_eq!(
+            db.messages_for_kata(chat, "Robinson Crusoe").unwrap(),
+            vec![(UserId(10), 1)]
+        );
+        assert_eq!(
+            db.messages_for_kata(chat, "Create Phone Number").unwrap(),
+            vec![(UserId(10), 3)]
+        );
+
+        db.clear_messages(chat).unwrap();
+        assert!(db
+            .messages_for_kata(chat, "Robinson Crusoe")
+            .unwrap()
+            .is_empty());
+        assert!(db.kata_index.is_empty());
+    }
+
+    #[test]
+    fn kata_index_rebuilds_from_messages() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.kata_index.inner().clear().unwrap();
+        assert!(db
+            .messages_for_kata(chat, "Robinson Crusoe")
+            .unwrap()
+            .is_empty());
+
+        db.rebuild_all_indexes().unwrap();
+        assert_eq!(
+            db.messages_for_kata(chat, "Robinson Crusoe").unwrap(),
+            vec![(UserId(10), 1)]
+        );
+    }
+
+    #[test]
+    fn reads_use_stored_parsed_fields() {
+        use crate::stats::solved_katas;
+
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let mut msg = solution(1, 10, "Robinson Crusoe");
+        // as if the parser had read it differently when the message was stored
+        if let Some(ref mut solution) = msg.solution {
+            solution.kata_name = "7 Create Phone Number".to_owned();
+        }
+        db.add_message(chat, msg).unwrap();
+
+        let stored = db.get_messages(chat).unwrap();
+        assert_eq!(
+            solved_katas(&stored).0,
+            vec![(
+                "7 Create Phone Number".to_owned(),
+                "https://pastebin.com/abc1".to_owned()
+            )]
+        );
+        assert_eq!(
+            db.messages_for_kata(chat, "Create Phone Number").unwrap(),
+            vec![(UserId(10), 1)]
+        );
+        assert!(db
+            .messages_for_kata(chat, "Robinson Crusoe")
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 1);
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 0);
+        assert_eq!(
+            db.messages_for_kata(chat, "Robinson Crusoe").unwrap(),
+            vec![(UserId(10), 1)]
+        );
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+    }
+
+    #[test]
+    fn linkless_solutions_survive_reparse() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(
+            chat,
+            ChatMessage {
+                solution: Some(ParsedSolution::linkless(6, "Create Phone Number")),
+                ..message(2, 10, "/kata 6 Create Phone Number")
+            },
+        )
+        .unwrap();
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 2);
+
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 0);
+        let stored = db.get_messages(chat).unwrap();
+        let solution = stored[1].solution.as_ref().unwrap();
+        assert!(solution.linkless);
+        assert_eq!(solution.kyu, Some(6));
+        assert!(solution.link.is_empty());
+        assert!(!stored[0].solution.as_ref().unwrap().linkless);
+        assert_eq!(
+            db.messages_for_kata(chat, "Create Phone Number").unwrap(),
+            vec![(UserId(10), 2)]
+        );
+    }
+
+    #[test]
+    fn chat_katas_are_read_by_prefix() {
+        let db = temp_persist();
+        for (chat, id) in [(1, 1), (12, 2), (-1, 3), (1, 4)].iter() {
+            db.add_message(ChatId(*chat), solution(*id, 10, &format!("Kata {}", id)))
+                .unwrap();
+        }
+        assert_eq!(db.distinct_kata_count(ChatId(1)).unwrap(), 2);
+        assert_eq!(db.distinct_kata_count(ChatId(12)).unwrap(), 1);
+        assert_eq!(db.distinct_kata_count(ChatId(-1)).unwrap(), 1);
+        assert_eq!(db.distinct_kata_count(ChatId(2)).unwrap(), 0);
+        let mut names: Vec<_> = db
+            .katas_of_chat(ChatId(1))
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["kata 1", "kata 4"]);
+        assert_eq!(db.chat_counts(ChatId(1), UserId(10)).unwrap().distinct, 2);
+    }
+
+    #[test]
+    fn kata_key_agrees_with_the_kata_index() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from, kyu) in [(1, 10, 7), (2, 20, 6), (3, 20, 7)].iter() {
+            let text = format!("{}\nValid Braces\nhttps://pastebin.com/abc{}", kyu, id);
+            db.add_message(chat, message(*id, *from, &text)).unwrap();
+        }
+        db.add_message(chat, solution(4, 10, "Robinson Crusoe"))
+            .unwrap();
+        let messages = db.get_messages(chat).unwrap();
+        assert_eq!(crate::stats::distinct_katas(&messages), 2);
+        assert_eq!(distinct_katas_of(&messages), 2);
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+        let keys: HashSet<_> = messages.iter().filter_map(ChatMessage::kata_key).collect();
+        assert_eq!(keys, index_katas(&messages).keys().cloned().collect());
+    }
+
+    #[test]
+    fn old_katas_join_their_resolved_posts() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        for (id, from) in [(1, 10), (2, 20)].iter() {
+            db.add_message(chat, solution(*id, *from, "Robinson Crusoe"))
+                .unwrap();
+        }
+        let imported = ChatName("imported".to_owned());
+        db.add_imported_messages_batch(imported.clone(), vec![solution(3, 30, "Robinson Crusoe")])
+            .unwrap();
+        db.add_message(
+            chat,
+            ChatMessage {
+                kata_id: Some("id".to_owned()),
+                ..solution(4, 30, "Robinson Crusoe")
+            },
+        )
+        .unwrap();
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+        assert_eq!(db.unresolved_kata_names().unwrap().len(), 1);
+
+        db.set_kata_alias("Robinson Crusoe", "id").unwrap();
+        assert!(db.unresolved_kata_names().unwrap().is_empty());
+        assert!(db.needs_kata_ids().unwrap());
+        assert_eq!(db.backfill_kata_ids().unwrap(), 3);
+        assert!(!db.needs_kata_ids().unwrap());
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 1);
+        assert_eq!(
+            db.messages_for_kata(chat, "robinson crusoe").unwrap().len(),
+            3
+        );
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+
+        db.messages_imported_to_regular(imported, ChatId(2))
+            .unwrap();
+        assert_eq!(
+            db.get_messages(ChatId(2)).unwrap()[0].kata_id.as_deref(),
+            Some("id")
+        );
+    }
+
+    #[test]
+    fn reparse_drops_the_id_of_a_renamed_kata() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(
+            chat,
+            ChatMessage {
+                kata_id: Some("old".to_owned()),
+                solution: Codewars.parse_solution("7\nRobinsn Crusoe\nhttps://pastebin.com/abc1"),
+                ..solution(1, 10, "Robinson Crusoe")
+            },
+        )
+        .unwrap();
+        db.add_message(
+            chat,
+            ChatMessage {
+                kata_id: Some("phone".to_owned()),
+                ..solution(2, 10, "Create Phone Number")
+            },
+        )
+        .unwrap();
+        db.set_kata_alias("Robinson Crusoe", "id").unwrap();
+
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 1);
+        let stored = db.get_messages(chat).unwrap();
+        assert_eq!(stored[0].kata_id.as_deref(), Some("id"));
+        assert_eq!(stored[1].kata_id.as_deref(), Some("phone"));
+    }
+
+    #[test]
+    fn untagged_solutions_inherit_the_default_language() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let untagged = solution(1, 10, "Robinson Crusoe");
+        let mut tagged = solution(2, 10, "Create Phone Number");
+        tagged.text.push_str(" python");
+        tagged.solution = Codewars.parse_solution(&tagged.text);
+        assert_eq!(untagged.solution.as_ref().unwrap().language, None);
+        db.add_user(
+            chat,
+            CodeUser {
+                default_language: Some("rust".to_owned()),
+                ..user(10)
+            },
+        )
+        .unwrap();
+
+        for mut msg in [untagged, tagged].iter().cloned() {
+            msg.solution = msg
+                .solution
+                .map(|solution| solution.with_default_language(Some("rust")));
+            db.add_message(chat, msg).unwrap();
+        }
+        let languages = || {
+            db.get_messages(chat)
+                .unwrap()
+                .into_iter()
+                .map(|msg| msg.solution.unwrap().language)
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![Some("rust".to_owned()), Some("python".to_owned())];
+        assert_eq!(languages(), expected);
+
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 0);
+        assert_eq!(languages(), expected);
+
+        // a language parsed wrong isn't kept as if it were inherited
+        let mut misparsed = solution(3, 10, "Valid Braces");
+        misparsed.solution.as_mut().unwrap().language = Some("cobol".to_owned());
+        db.add_message(chat, misparsed).unwrap();
+        assert_eq!(db.reparse_messages(chat, &Codewars).unwrap(), 1);
+        assert_eq!(languages()[2].as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn messages_without_parsed_fields_are_migrated() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let old = r#"[{"id":1,"text":"7\nRobinson Crusoe\nhttps://pastebin.com/abc1 rust","from":10,"paste_id":"abc1"}]"#;
+        db.messages
+            .inner()
+            .insert(serde_json::to_vec(&chat).unwrap(), old.as_bytes())
+            .unwrap();
+        assert!(db.get_messages(chat).unwrap()[0].solution.is_none());
+
+        db.migrate_parsed_fields().unwrap();
+        let solution = db.get_messages(chat).unwrap()[0].solution.clone().unwrap();
+        assert_eq!(solution.kata_name, "7 Robinson Crusoe");
+        assert_eq!(solution.kyu, Some(7));
+        assert_eq!(solution.language.as_deref(), Some("rust"));
+        assert_eq!(solution.paste_id.as_deref(), Some("abc1"));
+        assert_eq!(
+            db.messages_for_kata(chat, "Robinson Crusoe").unwrap(),
+            vec![(UserId(10), 1)]
+        );
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert_eq!(
+            db.migrations.get(&PARSED_FIELDS.to_owned()).unwrap(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn recompute_restores_a_corrupted_chat() {
+        let db = temp_persist();
+        let (chat, other) = (ChatId(1), ChatId(2));
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(2, 20, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(3, 20, "Create Phone Number"))
+            .unwrap();
+        db.add_message(other, solution(4, 10, "Create Phone Number"))
+            .unwrap();
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+
+        db.kata_index
+            .remove(&(chat, "create phone number".to_owned()))
+            .unwrap();
+        db.kata_index
+            .insert(&(chat, "ghost kata".to_owned()), vec![(UserId(30), 9)])
+            .unwrap();
+        db.solved_counts
+            .insert(&chat, vec![(UserId(10), 5)].into_iter().collect())
+            .unwrap();
+        db.message_counts.insert(&chat, 42).unwrap();
+
+        assert_eq!(
+            db.rebuild_indexes(chat).unwrap(),
+            Recomputed {
+                katas: 2,
+                counters: 2,
+                message_count: true,
+            }
+        );
+        assert_eq!(
+            db.messages_for_kata(chat, "Create Phone Number").unwrap(),
+            vec![(UserId(20), 3)]
+        );
+        assert!(db.messages_for_kata(chat, "Ghost Kata").unwrap().is_empty());
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 2);
+        assert_eq!(db.count_messages(chat).unwrap(), 3);
+        assert_eq!(db.user_solved_count(other, UserId(10)).unwrap(), 1);
+    }
+
+    #[test]
+    fn deleted_kata_disappears_from_solved() {
+        use crate::stats::solved_katas;
+
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(2, 20, "Robinson  crusoe."))
+            .unwrap();
+        db.add_message(chat, solution(3, 20, "Create Phone Number"))
+            .unwrap();
+
+        assert_eq!(db.delete_kata(chat, "robinson crusoe").unwrap(), 2);
+        let names: Vec<_> = solved_katas(&db.get_messages(chat).unwrap())
+            .0
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["7 Create Phone Number".to_owned()]);
+        assert!(db
+            .messages_for_kata(chat, "Robinson Crusoe")
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.count_messages(chat).unwrap(), 1);
+        assert_eq!(db.delete_kata(chat, "Robinson Crusoe").unwrap(), 0);
+    }
+
+    #[test]
+    fn solved_counts_match_recomputation() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let recomputed = |user: i32| {
+            index_katas(&db.get_messages(chat).unwrap())
+                .values()
+                .filter(|solutions| solutions.iter().any(|(u, _)| *u == UserId(user)))
+                .count()
+        };
+        let check = || {
+            for user in [10, 20, 30].iter() {
+                assert_eq!(
+                    db.user_solved_count(chat, UserId(*user)).unwrap(),
+                    recomputed(*user),
+                    "user {}",
+                    user
+                );
+            }
+        };
+
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(2, 10, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(3, 10, "Robinson  crusoe."))
+            .unwrap();
+        db.add_message(chat, solution(4, 20, "Robinson Crusoe"))
+            .unwrap();
+        db.add_message(chat, solution(5, 20, "Create Phone Number"))
+            .unwrap();
+        db.add_message(chat, solution(6, 30, "Directions Reduction"))
+            .unwrap();
+        check();
+        assert_eq!(db.user_solved_count(chat, UserId(10)).unwrap(), 1);
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 2);
+
+        db.delete_kata(chat, "Robinson Crusoe").unwrap();
+        check();
+        db.reassign_messages(chat, UserId(30), UserId(20)).unwrap();
+        check();
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 2);
+
+        db.solved_counts.inner().clear().unwrap();
+        db.rebuild_all_indexes().unwrap();
+        check();
+
+        db.clear_messages(chat).unwrap();
+        check();
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 0);
+    }
+
+    #[test]
+    fn chat_counts_tell_reposts_from_distinct_katas() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        let posts = [
+            (1, 10, "Robinson Crusoe"),
+            (2, 10, "Robinson Crusoe"),
+            (3, 10, "Create Phone Number"),
+            (4, 20, "Robinson Crusoe"),
+            (5, 20, "Directions Reduction"),
+            (6, 30, "Robinson Crusoe"),
+        ];
+        for (id, from, name) in posts.iter() {
+            db.add_message(chat, solution(*id, *from, name)).unwrap();
+        }
+
+        let first = db.chat_counts(chat, UserId(10)).unwrap();
+        let tied = db.chat_counts(chat, UserId(20)).unwrap();
+        let last = db.chat_counts(chat, UserId(30)).unwrap();
+        let newcomer = db.chat_counts(chat, UserId(40)).unwrap();
+        assert_eq!(
+            first,
+            ChatCounts {
+                solutions: 6,
+                distinct: 3,
+                own: 2,
+                rank: Some(1),
+                solvers: 3,
+            }
+        );
+        assert_eq!((tied.own, tied.rank), (2, Some(1)));
+        assert_eq!((last.own, last.rank), (1, Some(3)));
+        assert_eq!((newcomer.own, newcomer.rank), (0, None));
+        for counts in [first, tied, last, newcomer].iter() {
+            assert_eq!((counts.solutions, counts.distinct), (6, 3));
+            assert!(counts.own <= counts.distinct && counts.distinct <= counts.solutions);
+            assert!(counts.rank.unwrap_or(0) <= counts.solvers);
+        }
+    }
+
+    #[test]
+    fn retry_succeeds_on_third_attempt() {
+        let attempts = Cell::new(0);
+        let res = retry(|| {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(sled::Error::Io(std::io::ErrorKind::Interrupted.into()).into())
+            } else {
+                Ok(attempts.get())
+            }
+        });
+
+        assert_eq!(res.ok(), Some(3));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let attempts = Cell::new(0);
+        let res: Result<(), _> = retry(|| {
+            attempts.set(attempts.get() + 1);
+            Err(sled::Error::Io(std::io::ErrorKind::Interrupted.into()).into())
+        });
+
+        assert!(matches!(res, Err(MainError::Sled(_))));
+        assert_eq!(attempts.get(), WRITE_ATTEMPTS);
+    }
+
+    #[test]
+    fn failed_inserts_are_retried_alone() {
+        let db = temp_persist();
+        let chat = ChatId(1);
+        db.add_message(chat, solution(1, 10, "Robinson Crusoe"))
+            .unwrap();
+        // the messages are written, then the counter fails once
+        db.message_counts.fail_inserts(1);
+        db.add_message(chat, solution(2, 20, "Create Phone Number"))
+            .unwrap();
+        db.solved_counts.fail_inserts(1);
+        db.add_message(chat, solution(3, 20, "Robinson Crusoe"))
+            .unwrap();
+
+        let ids: Vec<_> = db
+            .get_messages(chat)
+            .unwrap()
+            .iter()
+            .map(|msg| msg.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(db.message_counts.get(&chat).unwrap(), Some(3));
+        assert_eq!(db.user_solved_count(chat, UserId(20)).unwrap(), 2);
+        assert_eq!(db.rebuild_indexes(chat).unwrap(), Recomputed::default());
+    }
+
+    #[test]
+    fn retry_skips_lasting_errors() {
+        let attempts = Cell::new(0);
+        let res: Result<(), _> = retry(|| {
+            attempts.set(attempts.get() + 1);
+            Err(sled::Error::Unsupported("injected".to_owned()).into())
+        });
+        assert!(matches!(res, Err(MainError::Sled(_))));
+
+        let res: Result<(), _> = retry(|| {
+            attempts.set(attempts.get() + 1);
+            Err(serde_json::from_str::<i32>("not json").unwrap_err().into())
+        });
+        assert!(matches!(res, Err(MainError::Serde(_))));
+        assert_eq!(attempts.get(), 2);
     }
 }
@@ -10,6 +10,11 @@ pub enum MainError {
     Serde(serde_json::Error),
     Network(reqwest::Error),
     CodewarsApi(CodewarsApiError),
+    Telegram(teloxide::RequestError),
+    Download(teloxide::DownloadError),
+    StatsRender(RenderError),
+    TelegramApi(TelegramApiError),
+    Config(ConfigError),
 }
 
 #[derive(Debug, Display)]
@@ -18,3 +23,45 @@ pub enum CodewarsApiError {
 }
 
 impl Error for CodewarsApiError {}
+
+/// A chart that couldn't be drawn, usually a font or the raster backend missing on the host
+#[derive(Debug, Display)]
+pub enum RenderError {
+    #[display(fmt = "couldn't build the chart: {}", _0)]
+    Chart(String),
+    #[display(fmt = "couldn't read the chart svg: {}", _0)]
+    Svg(String),
+    #[display(fmt = "the raster backend couldn't render the chart")]
+    Backend,
+    #[display(fmt = "couldn't save the chart to {:?}", _0)]
+    Save(std::path::PathBuf),
+}
+
+impl Error for RenderError {}
+
+/// Bot API calls made without teloxide
+#[derive(Debug, Display)]
+pub enum TelegramApiError {
+    Refused(String),
+}
+
+impl Error for TelegramApiError {}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Parse(toml::de::Error),
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Parse(e) => write!(f, "couldn't parse the config: {}", e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid config:\n  {}", problems.join("\n  "))
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
@@ -0,0 +1,104 @@
+use crate::db::{ChatSettings, UserId};
+use teloxide::types::ParseMode;
+use teloxide::utils::html;
+
+/// Markup of the formatted replies. Everything taken from users or katas goes
+/// through here so a name like `<b>` or `a & b` can't break the message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Format {
+    html: bool,
+}
+
+impl Format {
+    /// Telegram HTML unless the chat turned it off with /formatting or `DONT_SEND_HTML` is set
+    pub fn of(settings: &ChatSettings) -> Self {
+        Format {
+            html: !settings.plain_text && std::env::var("DONT_SEND_HTML").is_err(),
+        }
+    }
+
+    #[cfg(test)]
+    fn html() -> Self {
+        Format { html: true }
+    }
+
+    pub fn parse_mode(self) -> Option<ParseMode> {
+        if self.html {
+            Some(ParseMode::HTML)
+        } else {
+            None
+        }
+    }
+
+    /// Shown exactly as written
+    pub fn text(self, text: &str) -> String {
+        if self.html {
+            html::escape(text)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    pub fn bold(self, text: &str) -> String {
+        if self.html {
+            html::bold(&html::escape(text))
+        } else {
+            text.to_owned()
+        }
+    }
+
+    /// `text` leading to `url`, linkless solutions have an empty one
+    pub fn link(self, url: &str, text: &str) -> String {
+        match (self.html, url.is_empty()) {
+            (_, true) => self.text(text),
+            (true, false) => format!(
+                "<a href=\"{}\">{}</a>",
+                html::escape(url).replace('"', "&quot;"),
+                html::escape(text)
+            ),
+            (false, false) => format!("{} {}", text, url),
+        }
+    }
+
+    /// Tags the user, who gets notified
+    pub fn mention(self, user: UserId, name: &str) -> String {
+        if self.html {
+            html::user_mention(user.0, name)
+        } else {
+            name.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tricky_names_are_escaped() {
+        let html = Format::html();
+        assert_eq!(
+            html.text("<b>Bob</b> & co"),
+            "&lt;b&gt;Bob&lt;/b&gt; &amp; co"
+        );
+        assert_eq!(html.text("_snake_ *star* [x](y)"), "_snake_ *star* [x](y)");
+        assert_eq!(html.bold("a<b"), "<b>a&lt;b</b>");
+        assert_eq!(
+            html.link("https://pastebin.com/a\"b&c", "x > y"),
+            "<a href=\"https://pastebin.com/a&quot;b&amp;c\">x &gt; y</a>"
+        );
+        assert_eq!(html.link("", "<kata>"), "&lt;kata&gt;");
+        assert_eq!(
+            html.mention(UserId(7), "</a>"),
+            "<a href=\"tg://user?id=7\">&lt;/a&gt;</a>"
+        );
+
+        let plain = Format { html: false };
+        assert_eq!(plain.parse_mode(), None);
+        assert_eq!(plain.bold("<b>Bob</b>"), "<b>Bob</b>");
+        assert_eq!(
+            plain.link("https://pastebin.com/abc", "Kata"),
+            "Kata https://pastebin.com/abc"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::error::MainError;
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::Client;
+use std::time::Duration;
+
+const USER_AGENT: &str = concat!("func_cheater_stats/", env!("CARGO_PKG_VERSION"));
+
+/// Where outgoing requests go, `Http` in the bot and a canned one in tests
+pub trait Fetch: Send + Sync {
+    fn get_text<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, MainError>>;
+}
+
+/// One client for every outgoing request so connections are pooled and timeouts match
+pub struct Http {
+    client: Client,
+}
+
+impl Http {
+    pub fn new(timeout: Duration) -> Result<Self, MainError> {
+        let client = Client::builder()
+            .timeout(timeout)
+            .user_agent(USER_AGENT)
+            .build()?;
+        Ok(Http { client })
+    }
+}
+
+impl Fetch for Http {
+    fn get_text<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, MainError>> {
+        async move {
+            log::info!("Request: {}", url);
+            Ok(self.client.get(url).send().await?.text().await?)
+        }
+        .boxed()
+    }
+}
+
+/// Content of a paste as posted, without the pastebin page around it
+#[allow(dead_code)] // no command reads pastes yet
+pub async fn get_raw_paste(http: &dyn Fetch, paste_id: &str) -> Result<String, MainError> {
+    http.get_text(&format!("https://pastebin.com/raw/{}", paste_id))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codewars_requests::{get_honor, get_kata_kyu};
+    use std::sync::Mutex;
+
+    /// Answers every request with `body` and remembers the urls
+    struct Canned {
+        body: &'static str,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl Canned {
+        fn new(body: &'static str) -> Self {
+            Canned {
+                body,
+                urls: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Fetch for Canned {
+        fn get_text<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, MainError>> {
+            self.urls.lock().unwrap().push(url.to_owned());
+            futures::future::ready(Ok(self.body.to_owned())).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_injected_client() {
+        assert!(Http::new(Duration::from_secs(5)).is_ok());
+
+        let codewars = Canned::new(r#"{"honor":42}"#);
+        assert_eq!(get_honor(&codewars, "john").await.unwrap(), 42);
+        assert_eq!(
+            *codewars.urls.lock().unwrap(),
+            vec!["https://www.codewars.com/api/v1/users/john"]
+        );
+
+        let pastebin = Canned::new("fn main() {}");
+        assert_eq!(
+            get_raw_paste(&pastebin, "abc123").await.unwrap(),
+            "fn main() {}"
+        );
+        assert_eq!(
+            *pastebin.urls.lock().unwrap(),
+            vec!["https://pastebin.com/raw/abc123"]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_kyu_lookups_are_errors() {
+        let codewars = Canned::new(r#"{"success": false, "reason": "rate limited"}"#);
+        assert!(get_kata_kyu(&codewars, "abc").await.is_err());
+    }
+}
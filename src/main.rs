@@ -1,45 +1,196 @@
-use crate::db::{ChatId, ChatMessage, ChatName, CodeUser, Persist, UserId};
-use crate::error::{CodewarsApiError, MainError};
-use crate::message_parse::{is_codewars_solution, kata_name_link};
-use crate::parsing_types::{Text, TextData};
-use crate::stats::{compute_honor, compute_stats};
+use crate::badges::compute_badges;
+use crate::codewars_requests::get_kata;
+use crate::config::Config;
+use crate::dates::{
+    calendar, compute_bursts, compute_period_diff, compute_streaks, inactive_users, next_target,
+    project_pace, season_messages, solves_per_day, today, Window,
+};
+use crate::db::{
+    distinct_katas_of, Acknowledge, ChatCounts, ChatExport, ChatId, ChatMessage, ChatName,
+    ChatSettings, CodeUser, ImportReport, ImportedFile, Persist, UserExport, UserId, UserSettings,
+};
+use crate::error::{CodewarsApiError, MainError, TelegramApiError};
+use crate::format::Format;
+use crate::http::{Fetch, Http};
+use crate::message_parse::{
+    detector_for, kata_slug, normalize_kata_name, normalize_language, strip_kyu, ParsedSolution,
+    SolutionDetector,
+};
+use crate::messages::{command_help, t, Key, Lang};
+use crate::metrics::Metrics;
+use crate::parsing_types::{ChatData, Text};
+use crate::stats::{
+    aggregate_stats, compute_compare_chart, compute_honor, compute_kyu_mismatches,
+    compute_leaderboard_chart, compute_polyglots, compute_rank, compute_stats, compute_unsolved,
+    count_at_kyu, count_first_solves, distinct_katas, first_solver, language_leaderboard_rows,
+    leaderboard_rows, markdown_digest, solved_by_kyu, solved_katas, user_label,
+};
+use chrono::{TimeZone, Utc};
 use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::iter::once;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use teloxide::prelude::*;
-use teloxide::types::{ChatKind, InputFile, MessageKind, ParseMode};
-use teloxide::utils::{command::BotCommand, html::link};
+use teloxide::types::{
+    ChatKind, ChatMemberStatus, ForwardedFrom, InlineQuery, InlineQueryResult,
+    InlineQueryResultArticle, InputFile, InputMessageContent, MessageKind, ParseMode, User,
+};
+use teloxide::utils::{command::BotCommand, html};
 
+mod badges;
 mod codewars_requests;
+mod config;
+mod dates;
 mod db;
 mod error;
+mod format;
+mod http;
 mod message_parse;
+mod messages;
+mod metrics;
 mod parsing_types;
 mod stats;
 mod typed_db;
 mod utils;
 
-#[derive(BotCommand)]
+#[derive(BotCommand, Debug)]
 #[command(rename = "lowercase", description = "These commands are supported:")]
 enum Command {
-    #[command(description = "display help.")]
+    #[command(description = "display help, /help <command> explains one command")]
     Help,
     #[command(description = "add a user")]
     AddMe,
     #[command(description = "delete me")]
     DeleteMe,
-    #[command(description = "clear users")]
+    #[command(description = "clear users and their solutions")]
     Clear,
-    #[command(description = "show stats")]
+    #[command(description = "show stats of the chat or of one user: /showstats [user]")]
     ShowStats,
-    #[command(description = "show solved")]
+    #[command(description = "show solved, /showsolved verbose names who solved each first")]
     ShowSolved,
     #[command(description = "show honor")]
     ShowHonor,
+    #[command(description = "show solving streak")]
+    Streak,
+    #[command(description = "set chat timezone, e.g. Europe/Moscow")]
+    SetTimezone,
+    #[command(description = "merge the second user into the first (admins only)")]
+    Merge,
+    #[command(description = "remove another member, admins only: /removeuser <user> [purge]")]
+    RemoveUser,
+    #[command(description = "show solutions posted with a wrong kyu")]
+    KyuCheck,
+    #[command(description = "count the chat's solutions and katas and your own")]
+    Count,
+    #[command(description = "show the chat's key numbers as text, quicker than /showstats")]
+    Summary,
+    #[command(description = "show your registration")]
+    WhoAmI,
+    #[command(description = "compare this week with the last one")]
+    Progress,
+    #[command(description = "list your solutions of each of the last 14 days")]
+    Calendar,
+    #[command(description = "set bot language: en or ru")]
+    SetLang,
+    #[command(description = "set or show your kata goal")]
+    Goal,
+    #[command(description = "project when you reach your goal at your recent pace: /pace [days]")]
+    Pace,
+    #[command(description = "suggest a kata someone in the chat solved and you didn't")]
+    RandomKata,
+    #[command(description = "get everything stored about you in a private message")]
+    MyData,
+    #[command(
+        description = "get solved katas as a markdown study log: /export markdown [me], or /export json"
+    )]
+    Export,
+    #[command(description = "load a file from /export json or /mydata back, admins only")]
+    Restore,
+    #[command(
+        description = "show what the last startup import brought into this chat, admins only"
+    )]
+    ImportStatus,
+    #[command(description = "show your or a user's hardest solved kata")]
+    Hardest,
+    #[command(description = "show your or a user's easiest solved kata")]
+    Easiest,
+    #[command(description = "rank users by katas solved at a kyu: /topkyu <1-8>")]
+    TopKyu,
+    #[command(description = "credit forwarded solutions to their author or skip them")]
+    Forwards,
+    #[command(description = "confirm solutions with a reaction, a reply or not at all")]
+    Acknowledge,
+    #[command(description = "list who solved a kata: /solvedby <kata name>")]
+    SolvedBy,
+    #[command(description = "rank users by katas they were the first to solve")]
+    FirstSolveBoard,
+    #[command(
+        description = "rank users by distinct katas, /leaderboard image for a chart, /leaderboard rust for one language"
+    )]
+    Leaderboard,
+    #[command(description = "show your or a user's place and percentile in the chat")]
+    Rank,
+    #[command(description = "show pseudonyms instead of names in stats and exports, admins only")]
+    Anonymize,
+    #[command(description = "show yourself by a pseudonym or by name: on, off or chat")]
+    AnonymizeMe,
+    #[command(description = "record a solved kata without a link: /kata <kyu> <kata name>")]
+    Kata,
+    #[command(
+        description = "allow /kata to record solutions without a link: on or off, admins only"
+    )]
+    Linkless,
+    #[command(description = "ignore katas easier than a kyu, admins only: /minkyu <1-8|off>")]
+    MinKyu,
+    #[command(description = "bold, links and mentions in replies: on or off")]
+    Formatting,
+    #[command(description = "list your badges")]
+    Badges,
+    #[command(description = "set or show the language of your untagged solutions")]
+    Language,
+    #[command(description = "start a new season for /leaderboard season (admins only)")]
+    StartSeason,
+    #[command(
+        description = "delete messages older than some days, admins only: /retention <days|off>"
+    )]
+    Retention,
+    #[command(description = "tag members who solved nothing lately, admins only: /nudge [days]")]
+    Nudge,
+    #[command(description = "let /nudge tag you or not: on or off")]
+    NudgeMe,
+    #[command(description = "list katas solved by exactly one member, the hardest first")]
+    LoneSolves,
+    #[command(description = "rank users by the languages they solved katas in")]
+    Polyglots,
+    #[command(description = "list the hardest katas anyone in the chat has solved")]
+    TopKata,
+    #[command(description = "compare the chat's last week or month with the one before")]
+    Diff,
+    #[command(description = "remove deleted solutions by message id or link (admins only)")]
+    Prune,
+    #[command(description = "remove a kata for everyone, admins only: /deletekata <kata name>")]
+    DeleteKata,
+    #[command(description = "chart two users head-to-head: /compare <user> [other user]")]
+    Compare,
+    #[command(description = "parse stored solutions again after a parser fix (admins only)")]
+    Reparse,
+    #[command(description = "rebuild the chat's indexes and counters (admins only)")]
+    Recompute,
+    #[command(
+        description = "show suspiciously fast solving, admins set it with /bursts <katas> <minutes>"
+    )]
+    Bursts,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), MainError> {
+    let config = Arc::new(Config::load()?);
     fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -50,29 +201,23 @@ async fn main() -> Result<(), MainError> {
                 message
             ))
         })
-        .level(log::LevelFilter::Info)
+        .level(config.log_level().unwrap_or(log::LevelFilter::Info))
         .chain(std::io::stdout())
-        .chain(fern::log_file("logs.log")?)
+        .chain(fern::log_file(&config.log_file)?)
         .apply()?;
 
-    let cache_size = 1024 * 1024 * 256;
-    let was_imported = sled::Config::new()
-        .cache_capacity(cache_size)
-        .path("was_imported")
-        .open()?;
-    let imported = sled::Config::new()
-        .cache_capacity(cache_size)
-        .path("imported_msgs")
-        .open()?;
-    let messages = sled::Config::new()
-        .cache_capacity(cache_size)
-        .path("messages")
-        .open()?;
-    let db = sled::Config::new()
-        .cache_capacity(cache_size)
-        .path("users")
-        .open()?;
-    let persist = Arc::new(Persist::new(db, messages, imported, was_imported));
+    if config.safe_mode {
+        log::warn!("SAFE MODE: the database is read-only, nothing is imported or stored");
+    }
+    // with --dry-run only report what would be imported, migrations are skipped too
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+    let persist = Arc::new(Persist::open(
+        &config.data_dir,
+        config.cache_size,
+        !config.safe_mode && !dry_run,
+    )?);
+    let detector = detector_for(&config.solution_source);
+    let http = Arc::new(Http::new(Duration::from_secs(config.http_timeout_secs))?);
 
     // remove tmp dir
     let tmp = Path::new("tmp/");
@@ -81,84 +226,630 @@ async fn main() -> Result<(), MainError> {
     }
 
     // import messages
-    let data_path = Path::new("exported_messages.json");
-    if data_path.exists() {
+    let import_dir = if config.import_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        config.import_dir.as_path()
+    };
+    let export_files = if config.safe_mode {
+        log::warn!("SAFE MODE: exports in {:?} aren't imported", import_dir);
+        Vec::new()
+    } else {
+        export_files(import_dir)?
+    };
+    let start = std::time::Instant::now();
+    // ids imported per chat during this run, a chat is cleared the first time it's seen
+    // so the same chat split over several exports is merged instead of overwritten
+    let mut imported: HashMap<i64, HashSet<i32>> = HashMap::new();
+    let mut reports: HashMap<i64, ImportReport> = HashMap::new();
+    // name, added, duplicates and skipped of every chat over all its exports
+    let mut counts: HashMap<i64, (String, usize, usize, usize)> = HashMap::new();
+    for data_path in export_files.iter() {
         use parsing_types::ExportedData;
-        let messages = std::fs::read_to_string(data_path).unwrap();
-        let data: ExportedData = serde_json::from_str(messages.as_str()).unwrap();
+        let messages = std::fs::read_to_string(data_path)?;
+        let data: ExportedData = serde_json::from_str(messages.as_str())?;
         for chat in data.chats.list.iter() {
             if let Some(ref chat_name) = chat.name {
-                persist.clear_messages(ChatId(chat.id)).unwrap();
-                persist
-                    .clear_imported_messages(ChatName(chat_name.clone()))
-                    .unwrap();
-                persist.reset_imported(ChatName(chat_name.clone())).unwrap();
-                for msg in chat.messages.iter().filter(|msg| msg.msg_type == "message") {
-                    let msg_text = match msg.text.as_ref().unwrap() {
-                        Text::String(s) => s.clone(),
-                        Text::Links(vec) => vec
-                            .iter()
-                            .map(|t| {
-                                match t {
-                                    TextData::String(s) => s,
-                                    TextData::Typed { text, .. } => text,
-                                }
-                                .clone()
-                            })
-                            .collect::<Vec<_>>()
-                            .join(""),
-                    };
-
-                    if is_codewars_solution(msg_text.as_str()) {
-                        persist
-                            .add_imported_message(
-                                ChatName(chat_name.clone()),
-                                ChatMessage {
-                                    id: msg.id,
-                                    from: UserId(msg.from_id.unwrap()),
-                                    text: msg_text,
-                                },
-                            )
-                            .unwrap();
-                    }
+                let ExportedSolutions {
+                    solutions: mut batch,
+                    non_solutions,
+                    no_author,
+                } = exported_solutions(chat, detector.as_ref());
+                if no_author > 0 {
+                    log::warn!(
+                        "chat {}: skipped {} solutions without an author",
+                        chat_name,
+                        no_author
+                    );
+                }
+                let first_seen = !imported.contains_key(&chat.id);
+                let ids = imported.entry(chat.id).or_default();
+                let unique = batch.iter().filter(|msg| ids.insert(msg.id)).count();
+                let file = ImportedFile {
+                    name: data_path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned(),
+                    added: unique,
+                    duplicates: batch.len() - unique,
+                    skipped: non_solutions + no_author,
+                };
+                let counts = counts
+                    .entry(chat.id)
+                    .or_insert_with(|| (chat_name.clone(), 0, 0, 0));
+                counts.1 += file.added;
+                counts.2 += file.duplicates;
+                counts.3 += file.skipped;
+                reports
+                    .entry(chat.id)
+                    .or_insert_with(|| ImportReport {
+                        finished: Utc::now(),
+                        files: Vec::new(),
+                    })
+                    .files
+                    .push(file);
+                if dry_run {
+                    continue;
+                }
+                batch = with_kata_ids(&persist, http.as_ref(), batch).await;
+                if first_seen {
+                    persist.clear_messages(ChatId(chat.id))?;
+                    persist.clear_imported_messages(ChatName(chat_name.clone()))?;
+                    persist.reset_imported(ChatName(chat_name.clone()))?;
                 }
+                persist.add_imported_messages_batch(ChatName(chat_name.clone()), batch)?;
             }
         }
-        std::fs::rename(
-            data_path,
-            format!("used_{}", data_path.file_name().unwrap().to_str().unwrap()),
-        )
-        .unwrap();
+        if !dry_run {
+            std::fs::rename(
+                data_path,
+                data_path.with_file_name(format!(
+                    "used_{}",
+                    data_path.file_name().unwrap().to_str().unwrap()
+                )),
+            )?;
+        }
+    }
+    for (chat_name, added, duplicates, skipped) in counts.values() {
+        log::info!(
+            "{}chat {}: {} added, {} duplicates, {} skipped",
+            if dry_run { "dry run, " } else { "" },
+            chat_name,
+            added,
+            duplicates,
+            skipped
+        );
+    }
+    if dry_run {
+        if export_files.is_empty() {
+            log::info!("dry run: no exports in {:?}, nothing to import", import_dir);
+        } else {
+            log::info!(
+                "dry run finished in {:?}, nothing was written",
+                start.elapsed()
+            );
+        }
+        return Ok(());
+    }
+    if !export_files.is_empty() {
+        log::info!(
+            "imported {} files in {:?}",
+            export_files.len(),
+            start.elapsed()
+        );
+        for (chat_id, mut report) in reports {
+            report.finished = Utc::now();
+            persist.set_import_report(ChatId(chat_id), &report)?;
+        }
+    }
+    if !config.safe_mode && persist.needs_kata_ids()? {
+        backfill_kata_ids(&persist, http.as_ref()).await?;
+    }
+
+    let bot = Bot::new(config.token());
+    match bot.get_me().send().await {
+        Ok(me) => log::info!("Logged in as @{}", me.user.username.unwrap_or_default()),
+        Err(RequestError::ApiError { status_code, .. })
+            if status_code == reqwest::StatusCode::UNAUTHORIZED
+                || status_code == reqwest::StatusCode::NOT_FOUND =>
+        {
+            log::error!("Invalid TELEGRAM_TOKEN — check your bot token");
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    }
+    if !config.safe_mode {
+        tokio::spawn(prune_expired_messages(persist.clone()));
+    }
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = config.metrics_addr.clone() {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&addr, metrics).await {
+                log::error!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+    // polling only stops when the listener dies, start it again instead of going silent
+    let mut failures = 0;
+    loop {
+        let started = std::time::Instant::now();
+        let (persist, detector, config) = (persist.clone(), detector.clone(), config.clone());
+        let (inline_persist, http, metrics) = (persist.clone(), http.clone(), metrics.clone());
+        Dispatcher::new(bot.clone())
+            .messages_handler(move |rx| {
+                let (persist, detector) = (persist.clone(), detector.clone());
+                let (config, http, metrics) = (config.clone(), http.clone(), metrics.clone());
+                handle_messages(rx, persist, detector, config, http, metrics)
+            })
+            .inline_queries_handler(move |rx| handle_inline_queries(rx, inline_persist.clone()))
+            .dispatch()
+            .await;
+        if started.elapsed() > RECONNECT_MAX {
+            failures = 0;
+        }
+        let delay = reconnect_delay(failures);
+        log::warn!("Dispatcher stopped, reconnecting in {:?}", delay);
+        tokio::time::delay_for(delay).await;
+        failures += 1;
+    }
+}
+
+impl Command {
+    /// Whether the command changes anything stored, these are refused in safe mode
+    fn writes(&self, args: &[&str]) -> bool {
+        match self {
+            // without arguments these only show the current values
+            Command::Goal | Command::Bursts => !args.is_empty(),
+            Command::AddMe
+            | Command::DeleteMe
+            | Command::Clear
+            | Command::SetTimezone
+            | Command::Merge
+            | Command::RemoveUser
+            | Command::SetLang
+            | Command::Restore
+            | Command::Forwards
+            | Command::Acknowledge
+            | Command::Anonymize
+            | Command::AnonymizeMe
+            | Command::Kata
+            | Command::Linkless
+            | Command::MinKyu
+            | Command::Formatting
+            | Command::Language
+            | Command::StartSeason
+            | Command::Retention
+            | Command::NudgeMe
+            | Command::Prune
+            | Command::DeleteKata
+            | Command::Reparse
+            | Command::Recompute
+            // the actual kyus it looks up are cached
+            | Command::KyuCheck => true,
+            _ => false,
+        }
+    }
+
+    /// Name of the command's latency histogram, as typed without the slash
+    fn label(&self) -> String {
+        let debug = format!("{:?}", self);
+        let name = debug.split(|c: char| !c.is_alphanumeric()).next();
+        name.unwrap_or_default().to_lowercase()
+    }
+}
+
+/// Window of `/nudge` without an argument
+const NUDGE_DAYS: i64 = 14;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Applies the chats' retention windows every `PRUNE_INTERVAL` for as long as the bot runs
+async fn prune_expired_messages(db: Arc<Persist>) {
+    loop {
+        match db.prune_all_expired(Utc::now()) {
+            Ok(total) => log::info!("retention run pruned {} messages", total),
+            Err(e) => log::warn!("Error while pruning expired messages {}", e),
+        }
+        tokio::time::delay_for(PRUNE_INTERVAL).await;
+    }
+}
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(300);
+
+/// Doubles from `RECONNECT_MIN` with every failure in a row up to `RECONNECT_MAX`
+fn reconnect_delay(failures: u32) -> Duration {
+    RECONNECT_MIN
+        .checked_mul(2u32.saturating_pow(failures))
+        .map_or(RECONNECT_MAX, |delay| delay.min(RECONNECT_MAX))
+}
+
+/// Whether the command was scoped to the current season with a leading `season`
+fn season_scope<'a, 'b>(args: &'a [&'b str]) -> (bool, &'a [&'b str]) {
+    match args.split_first() {
+        Some((&"season", rest)) => (true, rest),
+        _ => (false, args),
+    }
+}
+
+/// Message id from a bare id or a message link like https://t.me/c/1234567890/42
+fn message_id(arg: &str) -> Option<i32> {
+    arg.trim_end_matches('/').rsplit('/').next()?.parse().ok()
+}
+
+/// `exported_messages*.json` files of the directory in filename order
+fn export_files(dir: &Path) -> Result<Vec<PathBuf>, MainError> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if path.is_file() && name.starts_with("exported_messages") && name.ends_with(".json") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+struct ExportedSolutions {
+    solutions: Vec<ChatMessage>,
+    non_solutions: usize,
+    no_author: usize,
+}
+
+fn exported_solutions(chat: &ChatData, detector: &dyn SolutionDetector) -> ExportedSolutions {
+    let mut exported = ExportedSolutions {
+        solutions: Vec::new(),
+        non_solutions: 0,
+        no_author: 0,
+    };
+    for msg in chat.messages.iter().filter(|msg| msg.msg_type == "message") {
+        // a message with media has an empty text when its caption is kept apart
+        let msg_text = msg
+            .text
+            .iter()
+            .chain(msg.caption.iter())
+            .map(Text::flatten)
+            .find(|text| !text.is_empty())
+            .unwrap_or_default();
+
+        match (detector.parse_solution(msg_text.as_str()), msg.from_id) {
+            (Some(solution), Some(from_id)) => exported.solutions.push(ChatMessage {
+                id: msg.id,
+                from: UserId(from_id),
+                kata_id: None,
+                text: msg_text,
+                date: msg.date(),
+                solution: Some(solution),
+            }),
+            (Some(_), None) => exported.no_author += 1,
+            (None, _) => exported.non_solutions += 1,
+        }
+    }
+    exported
+}
+
+/// Who gets the credit for a solution, `None` means it's skipped.
+/// `forward` is `Some(None)` for forwards with a hidden or channel author
+fn solution_author<'a>(
+    from: &'a User,
+    forward: Option<Option<&'a User>>,
+    settings: &ChatSettings,
+) -> Option<&'a User> {
+    match forward {
+        None => Some(from),
+        Some(original) if !settings.skip_forwards => original,
+        Some(_) => None,
+    }
+}
+
+/// Users on the `/leaderboard image` chart
+const LEADERBOARD_TOP: usize = 10;
+
+const SEND_ATTEMPTS: u32 = 3;
+
+/// Sends again after as long as flood control asks, other errors are returned right away
+async fn send_with_retry<T, F, Fut>(mut send: F) -> ResponseResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ResponseResult<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Err(RequestError::RetryAfter(secs)) if attempt < SEND_ATTEMPTS => {
+                log::info!("flood control, retrying in {} seconds", secs);
+                tokio::time::delay_for(Duration::from_secs(secs.max(0) as u64)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Reaction on stored solutions, ✅ isn't among the emoji bots are allowed to react with
+const ACK_REACTION: &str = "👍";
+
+/// teloxide doesn't know `setMessageReaction` yet so the Bot API is called directly
+async fn react(cx: &DispatcherHandlerCx<Message>, emoji: &str) -> Result<(), MainError> {
+    #[derive(serde::Deserialize)]
+    struct Response {
+        ok: bool,
+        description: Option<String>,
+    }
+
+    let body = serde_json::json!({
+        "chat_id": cx.chat_id(),
+        "message_id": cx.update.id,
+        "reaction": [{ "type": "emoji", "emoji": emoji }],
+    });
+    let text = cx
+        .bot
+        .client()
+        .post(&format!(
+            "https://api.telegram.org/bot{}/setMessageReaction",
+            cx.bot.token()
+        ))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let response: Response = serde_json::from_str(&text)?;
+    if response.ok {
+        Ok(())
+    } else {
+        Err(TelegramApiError::Refused(response.description.unwrap_or_default()).into())
+    }
+}
+
+/// Congratulates on badges earned since the last announcement and remembers them
+async fn announce_badges(
+    cx: &DispatcherHandlerCx<Message>,
+    db: &Persist,
+    user: UserId,
+    name: &str,
+    lang: Lang,
+) -> Result<(), MainError> {
+    let chat_id = ChatId(cx.chat_id());
+    let mut user_settings = db.get_user_settings(chat_id, user)?;
+    let new: Vec<_> = compute_badges(user, &db.get_messages(chat_id)?)
+        .into_iter()
+        .filter(|badge| {
+            !user_settings
+                .announced_badges
+                .iter()
+                .any(|id| id == badge.id)
+        })
+        .collect();
+    if new.is_empty() {
+        return Ok(());
+    }
+    user_settings
+        .announced_badges
+        .extend(new.iter().map(|badge| badge.id.to_owned()));
+    db.set_user_settings(chat_id, user, user_settings)?;
+    let badges = new.iter().map(|badge| badge.name(lang)).join(", ");
+    cx.answer(t(
+        lang,
+        Key::BadgesEarned {
+            name,
+            badges: &badges,
+        },
+    ))
+    .send()
+    .await?;
+    Ok(())
+}
+
+/// The command in the message, a document can carry /restore as its caption while any
+/// other caption may be a solution
+fn command_of<'a>(msg: &'a Message, bot_name: &str) -> Option<(Command, Vec<&'a str>)> {
+    match msg.text() {
+        Some(text) => Command::parse(text, bot_name),
+        None => msg
+            .caption()
+            .and_then(|caption| Command::parse(caption, bot_name))
+            .filter(|(command, _)| matches!(command, Command::Restore)),
+    }
+}
+
+/// The reply in safe mode when the message is a command that would write
+fn safe_mode_refusal(msg: &Message, db: &Persist, bot_name: &str) -> Option<String> {
+    let (command, args) = command_of(msg, bot_name)?;
+    if !command.writes(&args) {
+        return None;
+    }
+    let settings = db.get_settings(ChatId(msg.chat_id())).unwrap_or_default();
+    let lang = match msg.from() {
+        Some(from) => language(&settings, from),
+        None => settings.language.unwrap_or(Lang::En),
+    };
+    Some(t(lang, Key::SafeMode))
+}
+
+/// What a solution is looked for in, the caption of a screenshot when there's no text
+fn solution_text(msg: &Message) -> Option<&str> {
+    msg.text().or_else(|| msg.caption())
+}
+
+/// Codewars id of the posted kata, `None` when the API can't tell and the name has to do.
+/// Every name a kata is resolved under is kept so the API is asked once per variant,
+/// and so is every name it failed on
+async fn resolve_kata(db: &Persist, http: &dyn Fetch, kata_name: &str) -> Option<String> {
+    match db.kata_alias(kata_name) {
+        Ok(Some(kata_id)) => return Some(kata_id),
+        Ok(None) => (),
+        Err(e) => log::warn!("Error while getting a kata alias {}", e),
+    }
+    match db.is_kata_unresolved(kata_name) {
+        Ok(true) => return None,
+        Ok(false) => (),
+        Err(e) => log::warn!("Error while checking an unresolved kata {}", e),
+    }
+    match get_kata(http, &kata_slug(strip_kyu(kata_name))).await {
+        Ok(kata) => {
+            for name in [kata_name, &kata.name, &kata.slug].iter() {
+                if let Err(e) = db.set_kata_alias(name, &kata.id) {
+                    log::warn!("Error while storing a kata alias {}", e);
+                }
+            }
+            Some(kata.id)
+        }
+        Err(e) => {
+            log::info!("kata {} left unresolved: {}", kata_name, e);
+            if let Err(e) = db.set_kata_unresolved(kata_name) {
+                log::warn!("Error while storing an unresolved kata {}", e);
+            }
+            None
+        }
     }
+}
 
-    let token = std::env::var("TELEGRAM_TOKEN")
-        .expect("TELEGRAM_TOKEN env variable expected but wasn't found");
-    let bot = Bot::new(token);
-    Dispatcher::new(bot)
-        .messages_handler(move |rx| handle_messages(rx, persist.clone()))
-        .dispatch()
-        .await;
+/// The messages with the ids of their katas, for solutions that didn't come in as a post
+async fn with_kata_ids(
+    db: &Persist,
+    http: &dyn Fetch,
+    mut messages: Vec<ChatMessage>,
+) -> Vec<ChatMessage> {
+    for msg in messages.iter_mut().filter(|msg| msg.kata_id.is_none()) {
+        if let Some(solution) = &msg.solution {
+            msg.kata_id = resolve_kata(db, http, &solution.kata_name).await;
+        }
+    }
+    messages
+}
 
+/// Resolves the katas of messages stored before they were resolved, once
+async fn backfill_kata_ids(db: &Persist, http: &dyn Fetch) -> Result<(), MainError> {
+    let names = db.unresolved_kata_names()?;
+    log::info!("resolving {} kata names of stored messages", names.len());
+    for name in names {
+        resolve_kata(db, http, &name).await;
+    }
+    db.backfill_kata_ids()?;
     Ok(())
 }
 
-async fn store_message(cx: DispatcherHandlerCx<Message>, db: Arc<Persist>) -> ResponseResult<()> {
-    if let (Some(text), Some(from)) = (cx.update.text(), cx.update.from()) {
-        if is_codewars_solution(text) {
-            log::info!("{} ----- is a codewars solution", text);
+async fn store_message(
+    cx: DispatcherHandlerCx<Message>,
+    db: Arc<Persist>,
+    detector: Arc<dyn SolutionDetector>,
+    http: &dyn Fetch,
+) -> ResponseResult<()> {
+    if let (Some(text), Some(from)) = (solution_text(&cx.update), cx.update.from()) {
+        if let Some(solution) = detector.parse_solution(text) {
+            log::info!(
+                "{} {} ----- is a solution",
+                solution.kata_name,
+                solution.link
+            );
+            let chat_id = ChatId(cx.chat_id());
+            let settings = db.get_settings(chat_id).unwrap_or_default();
+            let forward = match (cx.update.forward_from(), cx.update.forward_from_chat()) {
+                (Some(ForwardedFrom::User(original)), _) => Some(Some(original)),
+                (Some(ForwardedFrom::SenderName(_)), _) | (None, Some(_)) => Some(None),
+                (None, None) => None,
+            };
+            let author = match solution_author(from, forward, &settings) {
+                Some(author) => author,
+                None => {
+                    log::info!("skipped a forwarded solution from {}", from.first_name);
+                    return Ok(());
+                }
+            };
+            if !settings.tracks_kyu(solution.kyu) {
+                log::info!("skipped a solution easier than {:?} kyu", settings.min_kyu);
+                if settings.acknowledge != Acknowledge::Silent {
+                    let lang = language(&settings, from);
+                    let key = Key::BelowMinKyu {
+                        kyu: solution.kyu.unwrap_or_default(),
+                        min_kyu: settings.min_kyu.unwrap_or_default(),
+                    };
+                    cx.answer(t(lang, key)).send().await?;
+                }
+                return Ok(());
+            }
+            let user = UserId(author.id);
+            let default_language = db
+                .get_users(chat_id)
+                .map(|mut users| users.remove(&user)?.default_language)
+                .unwrap_or_else(|e| {
+                    log::warn!("Error while getting the default language {}", e);
+                    None
+                });
+            let solution = solution.with_default_language(default_language.as_deref());
+            let kata_id = resolve_kata(&db, http, &solution.kata_name).await;
+            let solved = || db.user_solved_count(chat_id, user);
+            let before = solved();
             match db.add_message(
-                ChatId(cx.chat_id()),
+                chat_id,
                 ChatMessage {
-                    from: UserId(from.id),
+                    from: user,
+                    kata_id,
                     text: text.to_owned(),
                     id: cx.update.id,
+                    date: Some(Utc.timestamp(cx.update.date as i64, 0)),
+                    solution: Some(solution),
                 },
             ) {
-                Ok(_) => (),
+                Ok(_) => {
+                    let lang = language(&settings, from);
+                    match settings.acknowledge {
+                        Acknowledge::React => {
+                            if let Err(e) = react(&cx, ACK_REACTION).await {
+                                log::info!("Couldn't react to a solution, staying silent: {}", e);
+                            }
+                        }
+                        Acknowledge::Reply => {
+                            // the solution is stored already, a slow reply mustn't hold the handler
+                            let (bot, text) = (cx.bot.clone(), t(lang, Key::Registered));
+                            tokio::spawn(async move {
+                                let sent = send_with_retry(|| {
+                                    let request = bot.send_message(chat_id.0, text.clone());
+                                    async move { request.send().await }
+                                })
+                                .await;
+                                if let Err(e) = sent {
+                                    log::warn!("Couldn't acknowledge a solution: {}", e);
+                                }
+                            });
+                        }
+                        Acknowledge::Silent => (),
+                    }
+                    let goal = db.get_user_settings(chat_id, user).map(|s| s.goal);
+                    match (before, solved(), goal) {
+                        (Ok(before), Ok(after), Ok(Some(goal)))
+                            if before < goal && goal <= after =>
+                        {
+                            cx.answer(t(
+                                lang,
+                                Key::GoalReached {
+                                    name: &author.first_name,
+                                    goal,
+                                },
+                            ))
+                            .send()
+                            .await?;
+                        }
+                        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                            log::warn!("Error while checking the goal: {}", e)
+                        }
+                        _ => (),
+                    }
+                    if let Err(e) = announce_badges(&cx, &db, user, &author.first_name, lang).await
+                    {
+                        log::warn!("Error while checking badges: {}", e);
+                    }
+                }
                 Err(e) => log::warn!("Error while processing messages: {}", e),
             }
-
-        //cx.answer("Registered!").send().await?;
         } else {
             log::info!("{} ----- isn't a codewars solution", text);
         }
@@ -166,74 +857,457 @@ async fn store_message(cx: DispatcherHandlerCx<Message>, db: Arc<Persist>) -> Re
     Ok(())
 }
 
-async fn handle_messages(rx: DispatcherHandlerRx<Message>, db: Arc<Persist>) {
+async fn handle_messages(
+    rx: DispatcherHandlerRx<Message>,
+    db: Arc<Persist>,
+    detector: Arc<dyn SolutionDetector>,
+    config: Arc<Config>,
+    http: Arc<Http>,
+    metrics: Arc<Metrics>,
+) {
     rx.for_each_concurrent(None, |cx| async {
-        async {
-            if let Some(text) = cx.update.text() {
-                // import messages for this chat
-                match match cx.update.chat.kind.clone() {
-                    ChatKind::NonPrivate {
-                        title: Some(title), ..
-                    } => Some(title),
-                    ChatKind::Private {
-                        first_name: Some(first_name),
-                        ..
-                    } => Some(first_name),
-                    _ => None,
-                } {
-                    Some(chat_name) => {
-                        if !db.is_chat_imported(ChatName(chat_name.clone()))? {
-                            db.messages_imported_to_regular(
-                                ChatName(chat_name),
-                                ChatId(cx.chat_id()),
-                            )?
-                        }
-                    }
-                    None => (),
-                };
+        let handled = handle_message(cx, &db, &detector, &config, http.as_ref(), &metrics);
+        handled.await.log_on_error().await;
+    })
+    .await;
+}
 
-                // handle message
-                if let Some((command, args)) = Command::parse(text, "CodeWarsCheatStats_bot") {
-                    // handle commands
-                    answer_command(&cx, command, db.clone(), args)
-                        .await
-                        .log_on_error()
-                        .await;
-                } else {
-                    // handle messages
-                    store_message(cx, db.clone()).await.log_on_error().await;
+/// One update: a command is answered, a solution stored, each timed under its label
+async fn handle_message(
+    cx: DispatcherHandlerCx<Message>,
+    db: &Arc<Persist>,
+    detector: &Arc<dyn SolutionDetector>,
+    config: &Config,
+    http: &dyn Fetch,
+    metrics: &Metrics,
+) -> Result<(), MainError> {
+    if solution_text(&cx.update).is_some() && !config.safe_mode {
+        // import messages for this chat
+        match match cx.update.chat.kind.clone() {
+            ChatKind::NonPrivate {
+                title: Some(title), ..
+            } => Some(title),
+            ChatKind::Private {
+                first_name: Some(first_name),
+                ..
+            } => Some(first_name),
+            _ => None,
+        } {
+            Some(chat_name) => {
+                if !db.is_chat_imported(ChatName(chat_name.clone()))? {
+                    db.messages_imported_to_regular(ChatName(chat_name), ChatId(cx.chat_id()))?
                 }
             }
-            Result::<_, MainError>::Ok(())
+            None => (),
+        };
+    }
+    if config.safe_mode {
+        if let Some(refusal) = safe_mode_refusal(&cx.update, db, &config.bot_name) {
+            cx.answer(refusal).send().await.log_on_error().await;
+            return Ok(());
         }
-        .await
-        .log_on_error()
-        .await;
+    }
+    match command_of(&cx.update, &config.bot_name) {
+        Some((command, args)) => {
+            let (detector, label) = (detector.as_ref(), command.label());
+            let answered = answer_command(&cx, command, db.clone(), detector, http, args);
+            metrics.time(&label, answered).await.log_on_error().await;
+        }
+        None if solution_text(&cx.update).is_none() => (),
+        None if config.safe_mode => {
+            log::info!("safe mode, message {} not stored", cx.update.id)
+        }
+        None => {
+            let stored = store_message(cx, db.clone(), detector.clone(), http);
+            metrics.time("message", stored).await.log_on_error().await;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_inline_queries(rx: DispatcherHandlerRx<InlineQuery>, db: Arc<Persist>) {
+    rx.for_each_concurrent(None, |cx| async {
+        answer_inline_query(cx, db.clone())
+            .await
+            .log_on_error()
+            .await;
     })
     .await;
 }
 
+/// Searches katas solved in all chats the user is registered in,
+/// an empty query lists the most recent ones
+async fn answer_inline_query(
+    cx: DispatcherHandlerCx<InlineQuery>,
+    db: Arc<Persist>,
+) -> Result<(), MainError> {
+    const MAX_RESULTS: usize = 50;
+
+    struct Kata {
+        name: String,
+        link: String,
+        solvers: Vec<String>,
+        latest: (Option<chrono::DateTime<Utc>>, i32),
+        first: (bool, Option<chrono::DateTime<Utc>>, i32),
+    }
+
+    let mut katas: HashMap<String, Kata> = HashMap::new();
+    for chat_id in db.chats_of_user(UserId(cx.update.from.id))? {
+        let users = db.get_shared_users(chat_id)?;
+        let lang = language(&db.get_settings(chat_id)?, &cx.update.from);
+        for msg in db.get_messages(chat_id)? {
+            let solution = match msg.solution {
+                Some(ref solution) => solution,
+                None => continue,
+            };
+            let kata = katas
+                .entry(normalize_kata_name(&solution.kata_name))
+                .or_insert_with(|| Kata {
+                    name: solution.kata_name.clone(),
+                    link: solution.link.clone(),
+                    solvers: Vec::new(),
+                    latest: (msg.date, msg.id),
+                    first: msg.posted_order(),
+                });
+            // chats come in any order, the earliest post names the kata wherever it is
+            if msg.posted_order() < kata.first {
+                kata.name = solution.kata_name.clone();
+                kata.link = solution.link.clone();
+                kata.first = msg.posted_order();
+            }
+            let solver = user_label(lang, &users, msg.from);
+            if !kata.solvers.contains(&solver) {
+                kata.solvers.push(solver);
+            }
+            kata.latest = kata.latest.max((msg.date, msg.id));
+        }
+    }
+
+    let lang = cx
+        .update
+        .from
+        .language_code
+        .as_deref()
+        .and_then(Lang::from_code)
+        .unwrap_or(Lang::En);
+    let query = normalize_kata_name(&cx.update.query);
+    let results: Vec<_> = katas
+        .into_iter()
+        .filter(|(key, _)| key.contains(&query))
+        .map(|(_, kata)| kata)
+        .sorted_by(|a, b| b.latest.cmp(&a.latest))
+        .take(MAX_RESULTS)
+        .enumerate()
+        .map(
+            |(
+                i,
+                Kata {
+                    name,
+                    link,
+                    solvers,
+                    ..
+                },
+            )| {
+                let solved_by = t(lang, Key::SolvedBy(&solvers.join(", ")));
+                InlineQueryResult::Article(InlineQueryResultArticle {
+                    id: i.to_string(),
+                    title: name.clone(),
+                    input_message_content: InputMessageContent::Text {
+                        message_text: format!("{}\n{}\n{}", name, link, solved_by),
+                        parse_mode: None,
+                        disable_web_page_preview: Some(true),
+                    },
+                    reply_markup: None,
+                    url: None,
+                    hide_url: None,
+                    description: Some(solved_by),
+                    thumb_url: None,
+                    thumb_width: None,
+                    thumb_height: None,
+                })
+            },
+        )
+        .collect();
+
+    cx.bot
+        .answer_inline_query(cx.update.id.clone(), results)
+        .is_personal(true)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Writes the user's data to a json file to be sent as a document
+fn export_user(db: &Persist, chat_id: ChatId, user: UserId) -> Result<PathBuf, MainError> {
+    let export = db.export_user(chat_id, user)?;
+    let path = PathBuf::from(format!("tmp/mydata_{}.json", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all("tmp")?;
+    std::fs::write(&path, serde_json::to_vec_pretty(&export)?)?;
+    Ok(path)
+}
+
+/// Writes the chat's users and messages to a json file `/restore` takes back
+fn export_chat(db: &Persist, chat_id: ChatId) -> Result<PathBuf, MainError> {
+    let export = db.export_chat(chat_id)?;
+    let path = PathBuf::from(format!("tmp/chat_{}.json", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all("tmp")?;
+    std::fs::write(&path, serde_json::to_vec_pretty(&export)?)?;
+    Ok(path)
+}
+
+/// Downloads a document sent to the bot
+async fn download_document(
+    cx: &DispatcherHandlerCx<Message>,
+    file_id: &str,
+) -> Result<Vec<u8>, MainError> {
+    let file = cx.bot.get_file(file_id).send().await?;
+    let mut bytes = Vec::new();
+    cx.bot.download_file(&file.file_path, &mut bytes).await?;
+    Ok(bytes)
+}
+
+/// `/export json` or, for one user's data, `/mydata`
+fn parse_export(bytes: &[u8]) -> Result<ChatExport, serde_json::Error> {
+    serde_json::from_slice::<ChatExport>(bytes).or_else(|e| {
+        serde_json::from_slice::<UserExport>(bytes)
+            .map(ChatExport::from)
+            .map_err(|_| e)
+    })
+}
+
+/// Writes the markdown digest of the messages to a file to be sent as a document
+fn export_markdown(
+    lang: Lang,
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+) -> Result<PathBuf, MainError> {
+    let path = PathBuf::from(format!("tmp/katas_{}.md", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all("tmp")?;
+    std::fs::write(&path, markdown_digest(lang, users, messages))?;
+    Ok(path)
+}
+
+/// Tells a changed or repeated /addme apart from the first one
+fn registered_key<'a>(
+    name: &'a str,
+    previous: Option<&'a CodeUser>,
+    codewars_name: &'a str,
+) -> Key<'a> {
+    match previous {
+        None => Key::Added(name, codewars_name),
+        Some(old) if old.codewars_name == codewars_name => {
+            Key::AlreadyRegistered(name, codewars_name)
+        }
+        Some(old) => Key::CodewarsNameUpdated {
+            name,
+            from: &old.codewars_name,
+            to: codewars_name,
+        },
+    }
+}
+
+async fn is_admin(cx: &DispatcherHandlerCx<Message>, user: UserId) -> ResponseResult<bool> {
+    if cx.update.chat.is_private() {
+        return Ok(true);
+    }
+    let member = cx.bot.get_chat_member(cx.chat_id(), user.0).send().await?;
+    Ok(matches!(
+        member.status,
+        ChatMemberStatus::Creator | ChatMemberStatus::Administrator
+    ))
+}
+
+/// Longer names can't be codewars usernames and would overflow the chart labels
+const CODEWARS_NAME_MAX: usize = 32;
+
+#[derive(Debug, PartialEq)]
+enum CodewarsNameError<'a> {
+    Empty,
+    Spaces(&'a str),
+    TooLong,
+    /// Anything but latin letters, digits, `_` and `-`, the name ends up in API urls
+    InvalidChars,
+}
+
+/// Everything after `/addme` is the name, surrounding whitespace and a leading @ are dropped
+fn parse_codewars_name(arg: &str) -> Result<&str, CodewarsNameError<'_>> {
+    let name = arg.trim();
+    let name = name.strip_prefix('@').unwrap_or(name);
+    if name.is_empty() {
+        Err(CodewarsNameError::Empty)
+    } else if name.chars().count() > CODEWARS_NAME_MAX {
+        Err(CodewarsNameError::TooLong)
+    } else if name.contains(char::is_whitespace) {
+        Err(CodewarsNameError::Spaces(name))
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Err(CodewarsNameError::InvalidChars)
+    } else {
+        Ok(name)
+    }
+}
+
+/// Accepts a telegram id, a @username or a codewars name if it's unambiguous
+fn find_user<'a>(users: &'a HashMap<UserId, CodeUser>, query: &str) -> Option<&'a CodeUser> {
+    let by_telegram = users.values().find(|u| {
+        u.telegram_id.0.to_string() == query
+            || u.username.as_deref() == Some(query.trim_start_matches('@'))
+    });
+    let mut by_codewars = users.values().filter(|u| u.codewars_name == query);
+    by_telegram.or_else(|| match (by_codewars.next(), by_codewars.next()) {
+        (Some(u), None) => Some(u),
+        _ => None,
+    })
+}
+
+enum UserMatch<'a> {
+    Found(&'a CodeUser),
+    Ambiguous(Vec<&'a CodeUser>),
+    Missing,
+}
+
+impl<'a> UserMatch<'a> {
+    /// The user or the reply for a name nobody or several users match
+    fn or_reply(self, lang: Lang, query: &str) -> Result<&'a CodeUser, String> {
+        match self {
+            UserMatch::Found(user) => Ok(user),
+            UserMatch::Ambiguous(users) => {
+                let names = users
+                    .iter()
+                    .map(|u| format!("{} ({})", u.codewars_name, u.firstname))
+                    .sorted()
+                    .join(", ");
+                Err(t(lang, Key::DidYouMean(&names)))
+            }
+            UserMatch::Missing => Err(t(lang, Key::NotRegistered(query))),
+        }
+    }
+}
+
+/// `find_user`, then codewars and first names ignoring case, then the closest of them
+/// within a few typos. Commands that change data should stick to `find_user`
+fn resolve_user<'a>(users: &'a HashMap<UserId, CodeUser>, query: &str) -> UserMatch<'a> {
+    fn single(mut found: Vec<&CodeUser>) -> Option<UserMatch<'_>> {
+        match found.len() {
+            0 => None,
+            1 => found.pop().map(UserMatch::Found),
+            _ => Some(UserMatch::Ambiguous(found)),
+        }
+    }
+
+    if let Some(user) = find_user(users, query) {
+        return UserMatch::Found(user);
+    }
+    let query = query.trim_start_matches('@').to_lowercase();
+    let names = |u: &CodeUser| vec![u.codewars_name.to_lowercase(), u.firstname.to_lowercase()];
+    let same_case = users
+        .values()
+        .filter(|u| names(u).contains(&query))
+        .collect();
+    if let Some(found) = single(same_case) {
+        return found;
+    }
+    let max_distance = (query.chars().count() / 3).clamp(1, 3);
+    let close: Vec<_> = users
+        .values()
+        .filter_map(|u| {
+            let distance = names(u)
+                .iter()
+                .map(|name| utils::edit_distance(name, &query))
+                .min()?;
+            (distance <= max_distance).then_some((distance, u))
+        })
+        .collect();
+    let closest = close.iter().map(|(distance, _)| *distance).min();
+    let closest = close
+        .into_iter()
+        .filter(|(distance, _)| Some(*distance) == closest)
+        .map(|(_, u)| u)
+        .collect();
+    single(closest).unwrap_or(UserMatch::Missing)
+}
+
+/// Chat settings win over the telegram client language
+fn language(settings: &ChatSettings, user: &User) -> Lang {
+    settings
+        .language
+        .or_else(|| user.language_code.as_deref().and_then(Lang::from_code))
+        .unwrap_or(Lang::En)
+}
+
 async fn answer_command(
     cx: &DispatcherHandlerCx<Message>,
     command: Command,
     db: Arc<Persist>,
+    detector: &dyn SolutionDetector,
+    http: &dyn Fetch,
     args: Vec<&str>,
 ) -> ResponseResult<()> {
     async fn answer_image(
         cx: &DispatcherHandlerCx<Message>,
+        lang: Lang,
+        img_path: Result<PathBuf, MainError>,
+    ) -> ResponseResult<()> {
+        answer_captioned_image(cx, lang, img_path, None, None).await
+    }
+
+    /// Chunks of `text` sent with the chat's formatting, without link previews
+    async fn answer_formatted(
+        cx: &DispatcherHandlerCx<Message>,
+        format: Format,
+        text: &str,
+    ) -> ResponseResult<()> {
+        for chunk in utils::chunk_with_size(text) {
+            let mut answer = cx.answer(chunk).disable_web_page_preview(true);
+            if let Some(mode) = format.parse_mode() {
+                answer = answer.parse_mode(mode);
+            }
+            answer.send().await?;
+        }
+        Ok(())
+    }
+
+    /// `fallback` is sent as text when the host can't draw the chart
+    async fn answer_captioned_image(
+        cx: &DispatcherHandlerCx<Message>,
+        lang: Lang,
         img_path: Result<PathBuf, MainError>,
+        caption: Option<String>,
+        fallback: Option<String>,
     ) -> ResponseResult<()> {
         match img_path {
             Ok(path) => {
-                cx.answer_photo(InputFile::file(path)).send().await?;
+                let photo = cx.answer_photo(InputFile::file(&path));
+                let sent = match caption {
+                    Some(caption) => photo.caption(caption),
+                    None => photo,
+                }
+                .send()
+                .await;
+                if let Err(e) = std::fs::remove_file(&path) {
+                    log::warn!("Couldn't remove image {:?}: {}", path, e);
+                }
+                sent?;
             }
             Err(MainError::CodewarsApi(CodewarsApiError::NotFound(name))) => {
-                cx.answer(format!("User not found in Codewars API: {}", name))
+                cx.answer(t(lang, Key::UserNotFoundInApi(&name)))
                     .send()
                     .await?;
             }
+            Err(MainError::StatsRender(e)) => {
+                log::error!("Couldn't render a chart: {:?}", e);
+                let answer = match fallback {
+                    Some(numbers) => format!("{}\n{}", t(lang, Key::RenderFailed), numbers),
+                    None => t(lang, Key::RenderFailed),
+                };
+                for chunk in utils::chunk_with_size(&answer) {
+                    cx.answer(chunk).send().await?;
+                }
+            }
             Err(e) => {
-                cx.answer(format!("Error while getting stats: {}", e))
+                cx.answer(t(lang, Key::StatsError(e.to_string())))
                     .send()
                     .await?;
             }
@@ -241,11 +1315,43 @@ async fn answer_command(
         Ok(())
     }
 
+    /// The numbers of /showstats without the chart
+    fn stats_text(
+        lang: Lang,
+        caption: &str,
+        users: &HashMap<UserId, CodeUser>,
+        messages: &[ChatMessage],
+    ) -> String {
+        once(caption.to_owned())
+            .chain(
+                leaderboard_rows(lang, users, messages)
+                    .into_iter()
+                    .map(|(name, distinct)| format!("{}: {}", name, distinct)),
+            )
+            .join("\n")
+    }
+
     if let MessageKind::Common { ref from, .. } = cx.update.kind {
         if let Some(from) = from {
+            let settings = db.get_settings(ChatId(cx.chat_id())).unwrap_or_else(|e| {
+                log::warn!("Error while getting settings {}", e);
+                ChatSettings::default()
+            });
+            let lang = language(&settings, from);
+            let format = Format::of(&settings);
             match command {
                 Command::Help => {
-                    cx.answer(Command::descriptions()).send().await?;
+                    let answer = match args.first() {
+                        Some(name) => {
+                            let name = name.trim_start_matches('/').to_lowercase();
+                            match command_help(lang, &name) {
+                                Some(help) => help.to_owned(),
+                                None => t(lang, Key::UnknownCommand(&name)),
+                            }
+                        }
+                        None => Command::descriptions(),
+                    };
+                    cx.answer(answer).send().await?;
                 }
                 Command::DeleteMe => {
                     let answer_text;
@@ -257,116 +1363,2589 @@ async fn answer_command(
                         })
                         .is_ok()
                     {
-                        answer_text = format!(
-                            "Couldn't remove user {} due to a serialization error",
-                            from.first_name
-                        );
+                        answer_text = t(lang, Key::RemoveFailed(&from.first_name));
                     } else {
-                        answer_text = format!("Removed user {} successfully", from.first_name)
+                        answer_text = t(lang, Key::Removed(&from.first_name))
                     }
                     cx.answer(answer_text).send().await?;
                 }
                 Command::AddMe => {
-                    let answer_text;
-                    if args.len() == 1 {
-                        let codewars_name = args.first().unwrap().to_string();
-                        match db.add_user(
+                    let arg = args.join(" ");
+                    let answer_text = match parse_codewars_name(&arg) {
+                        Ok(codewars_name) => match db.add_user(
                             ChatId(cx.update.chat_id()),
                             CodeUser {
                                 telegram_id: UserId(from.id),
-                                codewars_name: codewars_name.clone(),
+                                codewars_name: codewars_name.to_owned(),
+                                // registering again keeps the /language choice
+                                default_language: db.get_users(ChatId(cx.chat_id())).ok().and_then(
+                                    |mut users| users.remove(&UserId(from.id))?.default_language,
+                                ),
                                 username: from.username.clone(),
                                 firstname: from.first_name.clone(),
                             },
                         ) {
                             Err(e) => {
-                                answer_text = format!(
-                                    "Couldn't add user {} with codewars username {} because of a serialization failure",
-                                    from.first_name,
-                                    &codewars_name
-                                );
                                 log::warn!("Error {} while adding a new user", e);
+                                t(lang, Key::AddFailed(&from.first_name, codewars_name))
                             }
-                            Ok(_) => {
-                                answer_text = format!(
-                                    "Added user {} with codewars username {}",
-                                    from.first_name, &codewars_name
-                                );
-                            }
+                            Ok(previous) => t(
+                                lang,
+                                registered_key(&from.first_name, previous.as_ref(), codewars_name),
+                            ),
+                        },
+                        Err(CodewarsNameError::Spaces(name)) => {
+                            t(lang, Key::CodewarsNameSpaces(&from.first_name, name))
                         }
-                    } else {
-                        answer_text = format!(
-                            "Couldn't add user {} because codewars username wasn't supplied",
-                            from.first_name,
-                        );
-                    }
+                        Err(CodewarsNameError::Empty) => {
+                            t(lang, Key::NoCodewarsName(&from.first_name))
+                        }
+                        Err(CodewarsNameError::TooLong) => t(
+                            lang,
+                            Key::CodewarsNameTooLong(&from.first_name, CODEWARS_NAME_MAX),
+                        ),
+                        Err(CodewarsNameError::InvalidChars) => {
+                            t(lang, Key::CodewarsNameInvalid(&from.first_name))
+                        }
+                    };
                     cx.answer(answer_text).send().await?;
                 }
                 Command::ShowStats => {
-                    if let Ok(us) = db.get_users(ChatId(cx.chat_id())) {
+                    let (season, args) = season_scope(&args);
+                    if season && settings.season_start.is_none() {
+                        cx.answer(t(lang, Key::NoSeason)).send().await?;
+                    } else if let Ok(us) = db.get_shared_users(ChatId(cx.chat_id())) {
                         if let Ok(msg) = db.get_messages(ChatId(cx.chat_id())) {
-                            answer_image(cx, compute_stats(us, msg).await).await?;
+                            let msg =
+                                season_messages(msg, settings.season_start.filter(|_| season));
+                            let name = args.join(" ");
+                            let caption = |msg: &[ChatMessage], distinct| {
+                                t(
+                                    lang,
+                                    Key::StatsCaption {
+                                        solutions: msg.len(),
+                                        distinct,
+                                    },
+                                )
+                            };
+                            if name.is_empty() {
+                                let distinct = if season {
+                                    distinct_katas_of(&msg)
+                                } else {
+                                    db.distinct_kata_count(ChatId(cx.chat_id()))
+                                        .unwrap_or_else(|_| distinct_katas_of(&msg))
+                                };
+                                let caption = caption(&msg, distinct);
+                                let text = stats_text(lang, &caption, &us, &msg);
+                                let chart = compute_stats(lang, http, us, msg).await;
+                                answer_captioned_image(cx, lang, chart, Some(caption), Some(text))
+                                    .await?;
+                            } else {
+                                match resolve_user(&us, &name).or_reply(lang, &name) {
+                                    Ok(user) => {
+                                        let user = user.clone();
+                                        let msg: Vec<_> = msg
+                                            .into_iter()
+                                            .filter(|m| m.from == user.telegram_id)
+                                            .collect();
+                                        let caption = caption(&msg, distinct_katas_of(&msg));
+                                        let us = once((user.telegram_id, user)).collect();
+                                        let text = stats_text(lang, &caption, &us, &msg);
+                                        let chart = compute_stats(lang, http, us, msg).await;
+                                        answer_captioned_image(
+                                            cx,
+                                            lang,
+                                            chart,
+                                            Some(caption),
+                                            Some(text),
+                                        )
+                                        .await?;
+                                    }
+                                    Err(reply) => {
+                                        cx.answer(reply).send().await?;
+                                    }
+                                }
+                            }
                         } else {
-                            cx.answer("Internal error 1").send().await?;
+                            cx.answer(t(lang, Key::DataError)).send().await?;
                         }
                     } else {
-                        cx.answer("Couldn't get user data due to an internal error")
-                            .send()
-                            .await?;
+                        cx.answer(t(lang, Key::UserDataError)).send().await?;
                     };
                 }
                 Command::Clear => {
-                    let mut answer = "Cleared all users for this chat";
-                    if !db.clear_users(ChatId(cx.update.chat_id())).is_ok() {
-                        answer = "Couldn't clear users due to a serialization failure"
+                    let mut answer = t(lang, Key::Cleared);
+                    if let Err(e) = db.clear_chat(ChatId(cx.update.chat_id())) {
+                        log::warn!("Error while clearing the chat {}", e);
+                        answer = t(lang, Key::ClearFailed)
                     }
                     cx.answer(answer).send().await?;
                 }
                 Command::ShowSolved => {
-                    let messages = match db.get_messages(ChatId(cx.chat_id())) {
+                    let chat_id = ChatId(cx.chat_id());
+                    let verbose = match args.as_slice() {
+                        [] => Some(false),
+                        ["verbose"] => Some(true),
+                        _ => None,
+                    };
+                    let messages = match db.get_messages(chat_id) {
                         Ok(msgs) => msgs,
                         Err(e) => {
                             log::warn!("Error while getting messages {}", e);
                             Vec::new()
                         }
                     };
-                    let answer = if messages.is_empty() {
-                        "No solved katas".to_owned()
+                    let (katas, skipped) = solved_katas(&messages);
+                    if skipped > 0 {
+                        log::warn!("Skipped {} stored messages that aren't solutions", skipped);
+                    }
+                    // names of the first solvers, whose posts come from the kata index
+                    let by_id: HashMap<_, _> = messages.iter().map(|msg| (msg.id, msg)).collect();
+                    let users = match verbose {
+                        Some(true) => match db.get_shared_users(chat_id) {
+                            Ok(users) => Some(users),
+                            Err(e) => {
+                                log::warn!("Error while getting users {}", e);
+                                None
+                            }
+                        },
+                        _ => None,
+                    };
+                    let answer = if verbose.is_none() {
+                        t(lang, Key::ShowSolvedUsage)
+                    } else if katas.is_empty() {
+                        t(lang, Key::NoSolvedKatas)
                     } else {
-                        let messages: Vec<_> = messages
-                            .into_iter()
-                            .map(|msg| kata_name_link(msg.text.as_str()))
-                            .unique()
-                            .sorted()
-                            .collect();
-
-                        format!(
-                            "The following katas were solved:\n{}",
-                            messages
-                                .into_iter()
-                                .map(|m| link(&m.1.replace("\"", ""), &m.0))
-                                .join("\n")
+                        t(
+                            lang,
+                            Key::SolvedKatas(
+                                katas
+                                    .into_iter()
+                                    .map(|(name, link)| {
+                                        let kata = format.link(&link, &name);
+                                        let first = users.as_ref().and_then(|users| {
+                                            let solutions = db
+                                                .messages_for_kata(chat_id, &name)
+                                                .map_err(|e| {
+                                                    log::warn!(
+                                                        "Error while reading the kata index {}",
+                                                        e
+                                                    )
+                                                })
+                                                .ok()?;
+                                            let posts = solutions
+                                                .iter()
+                                                .filter_map(|(_, id)| by_id.get(id).copied());
+                                            Some(user_label(lang, users, first_solver(posts)?))
+                                        });
+                                        match first {
+                                            Some(first) => {
+                                                format!("{} - {}", kata, format.text(&first))
+                                            }
+                                            None => kata,
+                                        }
+                                    })
+                                    .join("\n"),
+                            ),
                         )
                     };
-                    for answer in utils::chunk_with_size(answer.as_str()) {
-                        let mut m = cx.answer(answer);
-                        if std::env::var("DONT_SEND_HTML").map_or(true, |_| false) {
-                            m = m.parse_mode(ParseMode::HTML);
-                        }
-                        m.disable_web_page_preview(true).send().await?;
-                    }
+                    answer_formatted(cx, format, &answer).await?;
                 }
                 Command::ShowHonor => {
-                    if let Ok(us) = db.get_users(ChatId(cx.chat_id())) {
-                        answer_image(cx, compute_honor(us).await).await?;
-                    } else {
-                        cx.answer("Couldn't get user data due to an internal error")
-                            .send()
+                    if let (Ok(us), Ok(shared)) = (
+                        db.get_users(ChatId(cx.chat_id())),
+                        db.get_shared_users(ChatId(cx.chat_id())),
+                    ) {
+                        answer_image(cx, lang, compute_honor(lang, http, us, &shared).await)
                             .await?;
+                    } else {
+                        cx.answer(t(lang, Key::UserDataError)).send().await?;
                     };
                 }
-            }
-        }
-    }
-    Ok(())
+                Command::Streak => {
+                    let target = if args.is_empty() {
+                        Ok(Ok(UserId(from.id)))
+                    } else {
+                        let name = args.join(" ");
+                        db.get_shared_users(ChatId(cx.chat_id())).map(|us| {
+                            resolve_user(&us, &name)
+                                .or_reply(lang, &name)
+                                .map(|u| u.telegram_id)
+                        })
+                    };
+                    let answer = match (target, db.get_messages(ChatId(cx.chat_id()))) {
+                        (Ok(Ok(user)), Ok(messages)) => {
+                            let streaks = compute_streaks(
+                                messages.iter().filter(|msg| msg.from == user),
+                                settings.timezone(),
+                            );
+                            let mut answer = match streaks.longest {
+                                Some(longest) => t(
+                                    lang,
+                                    Key::Streak {
+                                        days: longest.days,
+                                        start: longest.start.to_string(),
+                                        end: longest.end.to_string(),
+                                        current: streaks.current,
+                                    },
+                                ),
+                                None => t(lang, Key::NoTimestamped),
+                            };
+                            if streaks.skipped > 0 {
+                                answer.push('\n');
+                                answer
+                                    .push_str(&t(lang, Key::SkippedUntimestamped(streaks.skipped)));
+                            }
+                            answer
+                        }
+                        (Ok(Err(reply)), _) => reply,
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while computing streak {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::SetTimezone => {
+                    let answer = match args.first().map(|tz| tz.parse::<chrono_tz::Tz>()) {
+                        Some(Ok(tz)) => {
+                            let settings = ChatSettings {
+                                timezone: Some(tz.name().to_owned()),
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::TimezoneSet(tz.name())),
+                                Err(e) => {
+                                    log::warn!("Error while setting timezone {}", e);
+                                    t(lang, Key::TimezoneFailed)
+                                }
+                            }
+                        }
+                        Some(Err(_)) => t(lang, Key::UnknownTimezone(&args.join(" "))),
+                        None => t(lang, Key::NoTimezone),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::SetLang => {
+                    let answer = match args.first().map(|code| Lang::from_code(code)) {
+                        Some(Some(new_lang)) => {
+                            let settings = ChatSettings {
+                                language: Some(new_lang),
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(new_lang, Key::LangSet),
+                                Err(e) => {
+                                    log::warn!("Error while setting language {}", e);
+                                    t(lang, Key::LangFailed)
+                                }
+                            }
+                        }
+                        _ => t(lang, Key::UnknownLang(&args.join(" "))),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Forwards => {
+                    let skip = match args.first().copied() {
+                        Some("attribute") => Some(false),
+                        Some("skip") => Some(true),
+                        _ => None,
+                    };
+                    let answer = match skip {
+                        Some(skip_forwards) => {
+                            let settings = ChatSettings {
+                                skip_forwards,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(
+                                    lang,
+                                    Key::ForwardsSet {
+                                        skip: skip_forwards,
+                                    },
+                                ),
+                                Err(e) => {
+                                    log::warn!("Error while setting forwards {}", e);
+                                    t(lang, Key::ForwardsFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::ForwardsUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Acknowledge => {
+                    let answer = match args.first().and_then(|mode| Acknowledge::parse(mode)) {
+                        Some(acknowledge) => {
+                            let settings = ChatSettings {
+                                acknowledge,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::AcknowledgeSet(args[0])),
+                                Err(e) => {
+                                    log::warn!("Error while setting acknowledgements {}", e);
+                                    t(lang, Key::AcknowledgeFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::AcknowledgeUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Anonymize => {
+                    let anonymize = match args.first().copied() {
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        _ => None,
+                    };
+                    let answer = match anonymize {
+                        _ if !is_admin(cx, UserId(from.id)).await? => t(lang, Key::AdminsOnly),
+                        Some(anonymize) => {
+                            let settings = ChatSettings {
+                                anonymize,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::AnonymizeSet(anonymize)),
+                                Err(e) => {
+                                    log::warn!("Error while setting anonymization {}", e);
+                                    t(lang, Key::AnonymizeFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::AnonymizeUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::AnonymizeMe => {
+                    let anonymize = match args.first().copied() {
+                        Some("on") => Some(Some(true)),
+                        Some("off") => Some(Some(false)),
+                        Some("chat") => Some(None),
+                        _ => None,
+                    };
+                    let answer = match anonymize {
+                        Some(anonymize) => {
+                            let user = UserId(from.id);
+                            let set = db.get_user_settings(ChatId(cx.chat_id()), user).and_then(
+                                |user_settings| {
+                                    let user_settings = UserSettings {
+                                        anonymize,
+                                        ..user_settings
+                                    };
+                                    db.set_user_settings(ChatId(cx.chat_id()), user, user_settings)
+                                },
+                            );
+                            match set {
+                                Ok(_) => t(lang, Key::AnonymizeMeSet(anonymize)),
+                                Err(e) => {
+                                    log::warn!("Error while setting anonymization {}", e);
+                                    t(lang, Key::AnonymizeFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::AnonymizeMeUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Nudge => {
+                    let days = match args.first() {
+                        Some(days) => days.parse::<i64>().ok().filter(|&days| days > 0),
+                        None => Some(NUDGE_DAYS),
+                    };
+                    let chat_id = ChatId(cx.chat_id());
+                    let nudge = match days {
+                        _ if !is_admin(cx, UserId(from.id)).await? => Err(t(lang, Key::AdminsOnly)),
+                        None => Err(t(lang, Key::NudgeUsage)),
+                        Some(days) => {
+                            match (db.get_shared_users(chat_id), db.get_messages(chat_id)) {
+                                (Ok(users), Ok(messages)) => {
+                                    let since = Utc::now() - chrono::Duration::days(days);
+                                    let mentions =
+                                        inactive_users(users.keys().copied(), &messages, since)
+                                            .into_iter()
+                                            // unreadable settings count as opted out
+                                            .filter(|&user| {
+                                                matches!(
+                                                    db.get_user_settings(chat_id, user),
+                                                    Ok(UserSettings {
+                                                        no_nudge: false,
+                                                        ..
+                                                    })
+                                                )
+                                            })
+                                            .map(|user| {
+                                                html::user_mention(
+                                                    user.0,
+                                                    &user_label(lang, &users, user),
+                                                )
+                                            })
+                                            .collect::<Vec<_>>();
+                                    if mentions.is_empty() {
+                                        Err(t(lang, Key::NobodyToNudge(days)))
+                                    } else {
+                                        Ok(format!(
+                                            "{}\n{}",
+                                            html::escape(&t(lang, Key::Nudge(days))),
+                                            mentions.join("\n")
+                                        ))
+                                    }
+                                }
+                                (Err(e), _) | (_, Err(e)) => {
+                                    log::warn!("Error while finding inactive users {}", e);
+                                    Err(t(lang, Key::DataError))
+                                }
+                            }
+                        }
+                    };
+                    match nudge {
+                        Ok(nudge) => {
+                            for nudge in utils::chunk_with_size(&nudge) {
+                                cx.answer(nudge).parse_mode(ParseMode::HTML).send().await?;
+                            }
+                        }
+                        Err(answer) => {
+                            cx.answer(answer).send().await?;
+                        }
+                    }
+                }
+                Command::NudgeMe => {
+                    let no_nudge = match args.first().copied() {
+                        Some("on") => Some(false),
+                        Some("off") => Some(true),
+                        _ => None,
+                    };
+                    let answer = match no_nudge {
+                        Some(no_nudge) => {
+                            let user = UserId(from.id);
+                            let set = db.get_user_settings(ChatId(cx.chat_id()), user).and_then(
+                                |user_settings| {
+                                    let user_settings = UserSettings {
+                                        no_nudge,
+                                        ..user_settings
+                                    };
+                                    db.set_user_settings(ChatId(cx.chat_id()), user, user_settings)
+                                },
+                            );
+                            match set {
+                                Ok(_) => t(lang, Key::NudgeMeSet(!no_nudge)),
+                                Err(e) => {
+                                    log::warn!("Error while setting nudges {}", e);
+                                    t(lang, Key::NudgeMeFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::NudgeMeUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Kata => {
+                    let name = args.iter().skip(1).join(" ");
+                    // a malformed command gets the usage before its kyu is checked
+                    let kyu = args
+                        .first()
+                        .and_then(|kyu| kyu.parse::<u8>().ok())
+                        .filter(|kyu| (1..=8).contains(kyu) && !name.is_empty());
+                    let answer = match kyu {
+                        _ if !settings.allow_linkless => t(lang, Key::LinklessDisabled),
+                        None => t(lang, Key::KataUsage),
+                        Some(kyu) if !settings.tracks_kyu(Some(kyu)) => t(
+                            lang,
+                            Key::BelowMinKyu {
+                                kyu,
+                                min_kyu: settings.min_kyu.unwrap_or_default(),
+                            },
+                        ),
+                        Some(kyu) => {
+                            let solution = ParsedSolution::linkless(kyu, &name);
+                            let kata_id = resolve_kata(&db, http, &name).await;
+                            let added = db.add_message(
+                                ChatId(cx.chat_id()),
+                                ChatMessage {
+                                    id: cx.update.id,
+                                    text: cx.update.text().unwrap_or_default().to_owned(),
+                                    from: UserId(from.id),
+                                    kata_id,
+                                    date: Some(Utc.timestamp(cx.update.date as i64, 0)),
+                                    solution: Some(solution),
+                                },
+                            );
+                            match added {
+                                Ok(_) => t(lang, Key::KataRecorded(&name)),
+                                Err(e) => {
+                                    log::warn!("Error while recording a kata {}", e);
+                                    t(lang, Key::KataFailed)
+                                }
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::MinKyu => {
+                    let min_kyu = match args.first().copied() {
+                        Some("off") => Some(None),
+                        Some(kyu) => kyu
+                            .parse::<u8>()
+                            .ok()
+                            .filter(|kyu| (1..=8).contains(kyu))
+                            .map(Some),
+                        None => None,
+                    };
+                    let answer = match min_kyu {
+                        _ if !is_admin(cx, UserId(from.id)).await? => t(lang, Key::AdminsOnly),
+                        Some(min_kyu) => {
+                            let settings = ChatSettings {
+                                min_kyu,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::MinKyuSet(min_kyu)),
+                                Err(e) => {
+                                    log::warn!("Error while setting the minimum kyu {}", e);
+                                    t(lang, Key::MinKyuFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::MinKyuUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Linkless => {
+                    let allow = match args.first().copied() {
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        _ => None,
+                    };
+                    let answer = match allow {
+                        _ if !is_admin(cx, UserId(from.id)).await? => t(lang, Key::AdminsOnly),
+                        Some(allow_linkless) => {
+                            let settings = ChatSettings {
+                                allow_linkless,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::LinklessSet(allow_linkless)),
+                                Err(e) => {
+                                    log::warn!("Error while setting linkless solutions {}", e);
+                                    t(lang, Key::LinklessFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::LinklessUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Retention => {
+                    let retention = match args.as_slice() {
+                        ["off"] => Some((None, false)),
+                        [days] => days
+                            .parse()
+                            .ok()
+                            .filter(|&d| d > 0)
+                            .map(|d| (Some(d), false)),
+                        [days, "all"] => days
+                            .parse()
+                            .ok()
+                            .filter(|&d| d > 0)
+                            .map(|d| (Some(d), true)),
+                        _ => None,
+                    };
+                    let answer = if args.is_empty() {
+                        t(
+                            lang,
+                            Key::Retention {
+                                days: settings.retention_days,
+                                untimestamped: settings.purge_untimestamped,
+                            },
+                        )
+                    } else if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        match retention {
+                            Some((retention_days, purge_untimestamped)) => {
+                                let settings = ChatSettings {
+                                    retention_days,
+                                    purge_untimestamped,
+                                    ..settings
+                                };
+                                match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                    Ok(_) => t(
+                                        lang,
+                                        Key::Retention {
+                                            days: retention_days,
+                                            untimestamped: purge_untimestamped,
+                                        },
+                                    ),
+                                    Err(e) => {
+                                        log::warn!("Error while setting retention {}", e);
+                                        t(lang, Key::RetentionFailed)
+                                    }
+                                }
+                            }
+                            None => t(lang, Key::RetentionUsage),
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Formatting => {
+                    let plain_text = match args.first().copied() {
+                        Some("on") => Some(false),
+                        Some("off") => Some(true),
+                        _ => None,
+                    };
+                    let answer = match plain_text {
+                        Some(plain_text) => {
+                            let settings = ChatSettings {
+                                plain_text,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(lang, Key::FormattingSet(!plain_text)),
+                                Err(e) => {
+                                    log::warn!("Error while setting formatting {}", e);
+                                    t(lang, Key::FormattingFailed)
+                                }
+                            }
+                        }
+                        None => t(lang, Key::FormattingUsage),
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Language => {
+                    let chat_id = ChatId(cx.chat_id());
+                    let user = db
+                        .get_users(chat_id)
+                        .map(|mut us| us.remove(&UserId(from.id)));
+                    let answer = match (user, args.first()) {
+                        (Ok(Some(user)), None) => {
+                            t(lang, Key::DefaultLanguage(user.default_language.as_deref()))
+                        }
+                        (Ok(Some(user)), Some(name)) => match normalize_language(name) {
+                            Some(language) => {
+                                let user = CodeUser {
+                                    default_language: Some(language.to_owned()),
+                                    ..user
+                                };
+                                match db.add_user(chat_id, user) {
+                                    Ok(_) => t(lang, Key::DefaultLanguageSet(language)),
+                                    Err(e) => {
+                                        log::warn!("Error while setting the language {}", e);
+                                        t(lang, Key::DefaultLanguageFailed)
+                                    }
+                                }
+                            }
+                            None => t(lang, Key::UnknownSolutionLanguage(name)),
+                        },
+                        (Ok(None), _) => t(lang, Key::NotRegisteredSelf),
+                        (Err(e), _) => {
+                            log::warn!("Error while getting users {}", e);
+                            t(lang, Key::UserDataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Polyglots => {
+                    let chat_id = ChatId(cx.chat_id());
+                    match (db.get_shared_users(chat_id), db.get_messages(chat_id)) {
+                        (Ok(users), Ok(messages)) => {
+                            let polyglots = compute_polyglots(&users, &messages);
+                            let rows: Vec<_> = polyglots
+                                .iter()
+                                .filter(|polyglot| !polyglot.languages.is_empty())
+                                .map(|polyglot| {
+                                    let name = format!(
+                                        "{} ({})",
+                                        user_label(lang, &users, polyglot.user),
+                                        polyglot.languages.join(", ")
+                                    );
+                                    (name, polyglot.languages.len())
+                                })
+                                .collect();
+                            // half or more of their solutions don't say the language
+                            let unknown = polyglots
+                                .iter()
+                                .filter(|polyglot| {
+                                    polyglot.unknown > 0
+                                        && polyglot.unknown * 2 >= polyglot.solutions
+                                })
+                                .map(|polyglot| {
+                                    format!(
+                                        "{} ({})",
+                                        format.text(&user_label(lang, &users, polyglot.user)),
+                                        polyglot.unknown
+                                    )
+                                })
+                                .join(", ");
+                            if rows.is_empty() {
+                                cx.answer(t(lang, Key::NoPolyglots)).send().await?;
+                            } else {
+                                let mut answer =
+                                    t(lang, Key::Polyglots(&utils::leaderboard(rows, format)));
+                                if !unknown.is_empty() {
+                                    answer.push('\n');
+                                    answer.push_str(&t(lang, Key::UnknownLanguages(&unknown)));
+                                }
+                                answer_formatted(cx, format, &answer).await?;
+                            }
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while ranking polyglots {}", e);
+                            cx.answer(t(lang, Key::DataError)).send().await?;
+                        }
+                    }
+                }
+                Command::LoneSolves => {
+                    let chat_id = ChatId(cx.chat_id());
+                    match (db.lone_solves(chat_id), db.get_shared_users(chat_id)) {
+                        (Ok(lone), _) if lone.is_empty() => {
+                            cx.answer(t(lang, Key::NoLoneSolves)).send().await?;
+                        }
+                        (Ok(lone), Ok(users)) => {
+                            let list = lone
+                                .iter()
+                                .map(|solve| {
+                                    let solver =
+                                        format.text(&user_label(lang, &users, solve.solver));
+                                    let name = format.text(&solve.name);
+                                    match solve.kyu {
+                                        Some(kyu) => format!("{} ({} kyu) - {}", name, kyu, solver),
+                                        None => format!("{} - {}", name, solver),
+                                    }
+                                })
+                                .join("\n");
+                            answer_formatted(cx, format, &t(lang, Key::LoneSolves(&list))).await?;
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while listing lone solves {}", e);
+                            cx.answer(t(lang, Key::DataError)).send().await?;
+                        }
+                    }
+                }
+                Command::TopKata => {
+                    const MAX_KATAS: usize = 10;
+
+                    let chat_id = ChatId(cx.chat_id());
+                    match (db.top_katas(chat_id), db.get_shared_users(chat_id)) {
+                        (Ok(top), _) if top.is_empty() => {
+                            cx.answer(t(lang, Key::NoKnownKyu)).send().await?;
+                        }
+                        (Ok(top), Ok(users)) => {
+                            let list = top
+                                .iter()
+                                .take(MAX_KATAS)
+                                .map(|kata| {
+                                    let solvers = kata
+                                        .solvers
+                                        .iter()
+                                        .map(|&solver| {
+                                            format.text(&user_label(lang, &users, solver))
+                                        })
+                                        .join(", ");
+                                    format!(
+                                        "{} ({} kyu) - {}",
+                                        format.text(&kata.name),
+                                        kata.kyu,
+                                        solvers
+                                    )
+                                })
+                                .join("\n");
+                            let more = top.len().saturating_sub(MAX_KATAS);
+                            let answer = t(lang, Key::TopKatas { list: &list, more });
+                            answer_formatted(cx, format, &answer).await?;
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while listing top katas {}", e);
+                            cx.answer(t(lang, Key::DataError)).send().await?;
+                        }
+                    }
+                }
+                Command::Badges => {
+                    let answer = match db.get_messages(ChatId(cx.chat_id())) {
+                        Ok(messages) => {
+                            let badges = compute_badges(UserId(from.id), &messages);
+                            if badges.is_empty() {
+                                t(lang, Key::NoBadges)
+                            } else {
+                                t(
+                                    lang,
+                                    Key::Badges(
+                                        &badges.iter().map(|badge| badge.name(lang)).join(", "),
+                                    ),
+                                )
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("Error while computing badges {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::SolvedBy => {
+                    let name = args.join(" ");
+                    let answer = if name.is_empty() {
+                        t(lang, Key::SolvedByUsage)
+                    } else {
+                        match (
+                            db.messages_for_kata(ChatId(cx.chat_id()), &name),
+                            db.get_shared_users(ChatId(cx.chat_id())),
+                        ) {
+                            (Ok(entries), Ok(users)) => {
+                                let solvers = entries
+                                    .iter()
+                                    .map(|(user, _)| *user)
+                                    .unique()
+                                    .map(|user| user_label(lang, &users, user))
+                                    .join(", ");
+                                if solvers.is_empty() {
+                                    t(lang, Key::NobodySolved(&name))
+                                } else {
+                                    t(lang, Key::SolvedBy(&solvers))
+                                }
+                            }
+                            (Err(e), _) | (_, Err(e)) => {
+                                log::warn!("Error while getting kata solvers {}", e);
+                                t(lang, Key::DataError)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Leaderboard => {
+                    let chat_id = ChatId(cx.chat_id());
+                    let (season, args) = season_scope(&args);
+                    let messages = db.get_messages(chat_id).map(|messages| {
+                        season_messages(messages, settings.season_start.filter(|_| season))
+                    });
+                    match (db.get_shared_users(chat_id), messages) {
+                        (Ok(users), Ok(messages)) if users.is_empty() && messages.is_empty() => {
+                            cx.answer(t(lang, Key::NoLeaderboard)).send().await?;
+                        }
+                        (Ok(_), Ok(_)) if season && settings.season_start.is_none() => {
+                            cx.answer(t(lang, Key::NoSeason)).send().await?;
+                        }
+                        (Ok(users), Ok(messages)) => match args {
+                            [] => {
+                                let board = utils::leaderboard(
+                                    leaderboard_rows(lang, &users, &messages),
+                                    format,
+                                );
+                                let answer = match settings.season_start {
+                                    Some(start) if season => t(
+                                        lang,
+                                        Key::SeasonLeaderboard {
+                                            since: &start
+                                                .with_timezone(&settings.timezone())
+                                                .format("%Y-%m-%d")
+                                                .to_string(),
+                                            board: &board,
+                                        },
+                                    ),
+                                    _ => t(lang, Key::Leaderboard(&board)),
+                                };
+                                answer_formatted(cx, format, &answer).await?;
+                            }
+                            ["image"] => {
+                                let chart = compute_leaderboard_chart(
+                                    lang,
+                                    &users,
+                                    &messages,
+                                    LEADERBOARD_TOP,
+                                );
+                                answer_image(cx, lang, chart).await?;
+                            }
+                            [language] => match normalize_language(language) {
+                                Some(language) => {
+                                    let rows = language_leaderboard_rows(
+                                        lang, &users, &messages, language,
+                                    );
+                                    let answer = if rows.is_empty() {
+                                        t(lang, Key::NoLanguageLeaderboard(language))
+                                    } else {
+                                        let board = utils::leaderboard(rows, format);
+                                        t(
+                                            lang,
+                                            Key::LanguageLeaderboard {
+                                                language,
+                                                board: &board,
+                                            },
+                                        )
+                                    };
+                                    answer_formatted(cx, format, &answer).await?;
+                                }
+                                None => {
+                                    cx.answer(t(lang, Key::LeaderboardUsage)).send().await?;
+                                }
+                            },
+                            _ => {
+                                cx.answer(t(lang, Key::LeaderboardUsage)).send().await?;
+                            }
+                        },
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while building the leaderboard {}", e);
+                            cx.answer(t(lang, Key::DataError)).send().await?;
+                        }
+                    }
+                }
+                Command::Rank => {
+                    let chat_id = ChatId(cx.chat_id());
+                    let answer = match (db.get_shared_users(chat_id), db.get_messages(chat_id)) {
+                        (Ok(users), Ok(messages)) => {
+                            let name = args.join(" ");
+                            let target = if name.is_empty() {
+                                users
+                                    .get(&UserId(from.id))
+                                    .ok_or_else(|| t(lang, Key::NotRegisteredSelf))
+                            } else {
+                                resolve_user(&users, &name).or_reply(lang, &name)
+                            };
+                            let counts = aggregate_stats(&users, &messages)
+                                .per_user
+                                .into_iter()
+                                .map(|(user, summary)| (user, summary.distinct))
+                                .collect();
+                            match target.map(|user| (user, compute_rank(&counts, user.telegram_id)))
+                            {
+                                Ok((user, Some(rank))) => {
+                                    // a mention would link the account behind a pseudonym
+                                    let name = match db.is_anonymized(chat_id, user.telegram_id) {
+                                        Ok(false) => {
+                                            format.mention(user.telegram_id, &user.firstname)
+                                        }
+                                        _ => format.text(&user.firstname),
+                                    };
+                                    let mut answer = t(
+                                        lang,
+                                        Key::Rank {
+                                            name: &name,
+                                            position: rank.position,
+                                            total: rank.total,
+                                        },
+                                    );
+                                    if let Some(percent) = rank.ahead_of_percent {
+                                        answer.push('\n');
+                                        answer.push_str(&t(lang, Key::RankPercentile(percent)));
+                                    }
+                                    answer
+                                }
+                                Ok(_) if name.is_empty() => {
+                                    format.text(&t(lang, Key::NotRegisteredSelf))
+                                }
+                                Ok((user, None)) => {
+                                    format.text(&t(lang, Key::NotRegistered(&user.firstname)))
+                                }
+                                Err(reply) => format.text(&reply),
+                            }
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while computing the rank {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    answer_formatted(cx, format, &answer).await?;
+                }
+                Command::FirstSolveBoard => {
+                    let answer = match (
+                        db.get_messages(ChatId(cx.chat_id())),
+                        db.get_shared_users(ChatId(cx.chat_id())),
+                    ) {
+                        (Ok(messages), Ok(users)) => {
+                            let firsts = count_first_solves(&messages);
+                            if firsts.is_empty() {
+                                t(lang, Key::NoFirstSolves)
+                            } else {
+                                let board = utils::leaderboard(
+                                    firsts.into_iter().map(|(user, count)| {
+                                        (user_label(lang, &users, user), count)
+                                    }),
+                                    format,
+                                );
+                                t(lang, Key::FirstSolveBoard(&board))
+                            }
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while counting first solves {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    answer_formatted(cx, format, &answer).await?;
+                }
+                Command::Goal => {
+                    let user = UserId(from.id);
+                    let answer = match args.first().map(|goal| goal.parse::<usize>()) {
+                        Some(Ok(goal)) if goal > 0 => {
+                            let set = db
+                                .get_user_settings(ChatId(cx.chat_id()), user)
+                                .map(|mut user_settings| {
+                                    user_settings.goal = Some(goal);
+                                    user_settings
+                                })
+                                .and_then(|user_settings| {
+                                    db.set_user_settings(ChatId(cx.chat_id()), user, user_settings)
+                                });
+                            match set {
+                                Ok(_) => t(lang, Key::GoalSet(goal)),
+                                Err(e) => {
+                                    log::warn!("Error while setting the goal {}", e);
+                                    t(lang, Key::GoalFailed)
+                                }
+                            }
+                        }
+                        Some(_) => t(lang, Key::GoalUsage),
+                        None => {
+                            let progress = db
+                                .get_user_settings(ChatId(cx.chat_id()), user)
+                                .and_then(|user_settings| {
+                                    let solved =
+                                        db.user_solved_count(ChatId(cx.chat_id()), user)?;
+                                    Ok(user_settings.goal.map(|goal| (goal, solved)))
+                                });
+                            match progress {
+                                Ok(Some((goal, solved))) => {
+                                    t(lang, Key::GoalProgress { goal, solved })
+                                }
+                                Ok(None) => t(lang, Key::NoGoal),
+                                Err(e) => {
+                                    log::warn!("Error while getting the goal {}", e);
+                                    t(lang, Key::DataError)
+                                }
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Pace => {
+                    const DEFAULT_DAYS: i64 = 30;
+
+                    let days = match args.first() {
+                        None => Some(DEFAULT_DAYS),
+                        Some(days) => days.parse().ok().filter(|days| (1..=365).contains(days)),
+                    };
+                    let (chat_id, user, tz) =
+                        (ChatId(cx.chat_id()), UserId(from.id), settings.timezone());
+                    let answer = match days {
+                        None => t(lang, Key::PaceUsage),
+                        Some(days) => {
+                            let pace = db.get_messages(chat_id).and_then(|messages| {
+                                let solved = db.user_solved_count(chat_id, user)?;
+                                let goal = db.get_user_settings(chat_id, user)?.goal;
+                                let own = messages.iter().filter(|msg| msg.from == user);
+                                let recent =
+                                    distinct_katas(Window::last_days(days, 0, tz).filter(own));
+                                Ok((solved, next_target(solved, goal), recent))
+                            });
+                            match pace {
+                                Ok((solved, target, recent)) => {
+                                    match project_pace(today(tz), solved, target, recent, days) {
+                                        Some(date) => t(
+                                            lang,
+                                            Key::Pace {
+                                                days,
+                                                per_week: recent as f64 * 7.0 / days as f64,
+                                                target,
+                                                date: &date.format("%Y-%m-%d").to_string(),
+                                            },
+                                        ),
+                                        None => t(lang, Key::NoRecentPace(days)),
+                                    }
+                                }
+                                Err(e) => {
+                                    log::warn!("Error while computing the pace {}", e);
+                                    t(lang, Key::DataError)
+                                }
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::RandomKata => {
+                    let unsolved = db.get_messages(ChatId(cx.chat_id())).and_then(|msgs| {
+                        Ok((
+                            compute_unsolved(&msgs, UserId(from.id)),
+                            db.get_shared_users(ChatId(cx.chat_id()))?,
+                        ))
+                    });
+                    let answer = match unsolved {
+                        Ok((unsolved, users)) => {
+                            match unsolved.choose(&mut StdRng::from_entropy()) {
+                                Some(kata) => t(
+                                    lang,
+                                    Key::RandomKata {
+                                        name: &format.bold(&kata.name),
+                                        link: &format.text(&kata.link),
+                                        solvers: &kata
+                                            .solvers
+                                            .iter()
+                                            .map(|&id| format.text(&user_label(lang, &users, id)))
+                                            .join(", "),
+                                    },
+                                ),
+                                None => t(lang, Key::SolvedAll),
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("Error while getting messages {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    answer_formatted(cx, format, &answer).await?;
+                }
+                Command::MyData => {
+                    let sent = match export_user(&db, ChatId(cx.chat_id()), UserId(from.id)) {
+                        Ok(path) => {
+                            let sent = cx
+                                .bot
+                                .send_document(i64::from(from.id), InputFile::file(&path))
+                                .send()
+                                .await;
+                            if let Err(e) = std::fs::remove_file(&path) {
+                                log::warn!("Couldn't remove export {:?}: {}", path, e);
+                            }
+                            sent.map_err(|e| log::warn!("Error while sending user data {}", e))
+                                .is_ok()
+                        }
+                        Err(e) => {
+                            log::warn!("Error while exporting user data {}", e);
+                            false
+                        }
+                    };
+                    // in a private chat the document itself is the answer
+                    if !sent || !cx.update.chat.is_private() {
+                        let key = if sent {
+                            Key::MyDataSent
+                        } else {
+                            Key::MyDataFailed
+                        };
+                        cx.answer(t(lang, key)).send().await?;
+                    }
+                }
+                Command::Export => {
+                    // (json, only the caller's katas)
+                    let export = match args.as_slice() {
+                        ["markdown"] => Some((false, false)),
+                        ["markdown", "me"] => Some((false, true)),
+                        ["json"] => Some((true, false)),
+                        _ => None,
+                    };
+                    let chat_id = ChatId(cx.chat_id());
+                    match export {
+                        None => {
+                            cx.answer(t(lang, Key::ExportUsage)).send().await?;
+                        }
+                        Some((_, false)) if !is_admin(cx, UserId(from.id)).await? => {
+                            cx.answer(t(lang, Key::AdminsOnly)).send().await?;
+                        }
+                        Some((true, _)) => match export_chat(&db, chat_id) {
+                            Ok(path) => {
+                                let sent = cx.answer_document(InputFile::file(&path)).send().await;
+                                if let Err(e) = std::fs::remove_file(&path) {
+                                    log::warn!("Couldn't remove export {:?}: {}", path, e);
+                                }
+                                sent?;
+                            }
+                            Err(e) => {
+                                log::warn!("Error while exporting the chat {}", e);
+                                cx.answer(t(lang, Key::DataError)).send().await?;
+                            }
+                        },
+                        Some((false, mine)) => {
+                            let path = db.get_shared_users(chat_id).and_then(|users| {
+                                let messages: Vec<_> = db
+                                    .get_messages(chat_id)?
+                                    .into_iter()
+                                    .filter(|msg| !mine || msg.from == UserId(from.id))
+                                    .collect();
+                                export_markdown(lang, &users, &messages)
+                            });
+                            match path {
+                                Ok(path) => {
+                                    let sent =
+                                        cx.answer_document(InputFile::file(&path)).send().await;
+                                    if let Err(e) = std::fs::remove_file(&path) {
+                                        log::warn!("Couldn't remove export {:?}: {}", path, e);
+                                    }
+                                    sent?;
+                                }
+                                Err(e) => {
+                                    log::warn!("Error while exporting katas {}", e);
+                                    cx.answer(t(lang, Key::DataError)).send().await?;
+                                }
+                            }
+                        }
+                    }
+                }
+                Command::ImportStatus => {
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        match db.last_import_report(ChatId(cx.chat_id())) {
+                            Ok(Some(report)) => {
+                                let files = report
+                                    .files
+                                    .iter()
+                                    .map(|file| {
+                                        t(
+                                            lang,
+                                            Key::ImportedFile {
+                                                name: &file.name,
+                                                added: file.added,
+                                                duplicates: file.duplicates,
+                                                skipped: file.skipped,
+                                            },
+                                        )
+                                    })
+                                    .join("\n");
+                                let finished = report
+                                    .finished
+                                    .with_timezone(&settings.timezone())
+                                    .format("%Y-%m-%d %H:%M")
+                                    .to_string();
+                                t(
+                                    lang,
+                                    Key::ImportStatus {
+                                        finished: &finished,
+                                        files: &files,
+                                    },
+                                )
+                            }
+                            Ok(None) => t(lang, Key::NoImportYet),
+                            Err(e) => {
+                                log::warn!("Error while getting the import report {}", e);
+                                t(lang, Key::DataError)
+                            }
+                        }
+                    };
+                    for answer in utils::chunk_with_size(answer.as_str()) {
+                        cx.answer(answer).send().await?;
+                    }
+                }
+                Command::Restore => {
+                    let document = cx
+                        .update
+                        .document()
+                        .or_else(|| cx.update.reply_to_message()?.document());
+                    let answer = match document {
+                        _ if !is_admin(cx, UserId(from.id)).await? => t(lang, Key::AdminsOnly),
+                        None => t(lang, Key::RestoreUsage),
+                        Some(document) => match download_document(cx, &document.file_id).await {
+                            Ok(bytes) => match parse_export(&bytes) {
+                                Ok(export) => match db.restore(
+                                    ChatId(cx.chat_id()),
+                                    ChatExport {
+                                        messages: with_kata_ids(&db, http, export.messages).await,
+                                        ..export
+                                    },
+                                ) {
+                                    Ok(restored) => t(
+                                        lang,
+                                        Key::Restored {
+                                            users: restored.users,
+                                            messages: restored.messages,
+                                        },
+                                    ),
+                                    Err(e) => {
+                                        log::warn!("Error while restoring {}", e);
+                                        t(lang, Key::RestoreFailed)
+                                    }
+                                },
+                                Err(e) => t(lang, Key::RestoreInvalid(&e.to_string())),
+                            },
+                            Err(e) => {
+                                log::warn!("Error while downloading a restored file {}", e);
+                                t(lang, Key::RestoreFailed)
+                            }
+                        },
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Hardest | Command::Easiest => {
+                    const MAX_EXAMPLES: usize = 3;
+
+                    let users = db.get_shared_users(ChatId(cx.chat_id()));
+                    let name = args.join(" ");
+                    let target = users.map(|us| {
+                        if name.is_empty() {
+                            Ok(UserId(from.id))
+                        } else {
+                            resolve_user(&us, &name)
+                                .or_reply(lang, &name)
+                                .map(|u| u.telegram_id)
+                        }
+                    });
+                    let answer = match (target, db.get_messages(ChatId(cx.chat_id()))) {
+                        (Ok(Ok(user)), Ok(messages)) => {
+                            let by_kyu =
+                                solved_by_kyu(messages.iter().filter(|msg| msg.from == user));
+                            let extreme = match command {
+                                Command::Hardest => by_kyu.iter().next(),
+                                _ => by_kyu.iter().next_back(),
+                            };
+                            match extreme {
+                                Some((&kyu, names)) => {
+                                    let katas = names.iter().take(MAX_EXAMPLES).join("\n");
+                                    let more = names.len().saturating_sub(MAX_EXAMPLES);
+                                    match command {
+                                        Command::Hardest => t(
+                                            lang,
+                                            Key::Hardest {
+                                                kyu,
+                                                katas: &katas,
+                                                more,
+                                            },
+                                        ),
+                                        _ => t(
+                                            lang,
+                                            Key::Easiest {
+                                                kyu,
+                                                katas: &katas,
+                                                more,
+                                            },
+                                        ),
+                                    }
+                                }
+                                None => t(lang, Key::NoKnownKyu),
+                            }
+                        }
+                        (Ok(Err(reply)), _) => reply,
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while getting solved katas {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::TopKyu => {
+                    let kyu = args
+                        .first()
+                        .and_then(|kyu| kyu.parse::<u8>().ok())
+                        .filter(|kyu| (1..=8).contains(kyu));
+                    let answer = match kyu {
+                        Some(kyu) => match (
+                            db.get_shared_users(ChatId(cx.chat_id())),
+                            db.get_messages(ChatId(cx.chat_id())),
+                        ) {
+                            (Ok(users), Ok(messages)) => {
+                                let counts = count_at_kyu(&users, &messages, kyu);
+                                if counts.is_empty() {
+                                    t(lang, Key::NoneAtKyu(kyu))
+                                } else {
+                                    t(
+                                        lang,
+                                        Key::TopKyu {
+                                            kyu,
+                                            board: &utils::leaderboard(counts, format),
+                                        },
+                                    )
+                                }
+                            }
+                            (Err(e), _) | (_, Err(e)) => {
+                                log::warn!("Error while ranking by kyu {}", e);
+                                t(lang, Key::DataError)
+                            }
+                        },
+                        None => format.text(&t(lang, Key::TopKyuUsage)),
+                    };
+                    answer_formatted(cx, format, &answer).await?;
+                }
+                Command::Calendar => {
+                    const DAYS: i64 = 14;
+
+                    let tz = settings.timezone();
+                    let answer = match db.get_messages(ChatId(cx.chat_id())) {
+                        Ok(messages) => {
+                            let (per_day, skipped) = solves_per_day(
+                                messages.iter().filter(|msg| msg.from == UserId(from.id)),
+                                tz,
+                            );
+                            let mut answer = calendar(&per_day, Window::last_days(DAYS, 0, tz))
+                                .into_iter()
+                                .map(|(day, solved)| {
+                                    let day = day.format("%Y-%m-%d").to_string();
+                                    t(lang, Key::CalendarDay { day: &day, solved })
+                                })
+                                .join("\n");
+                            if skipped > 0 {
+                                answer.push('\n');
+                                answer.push_str(&t(lang, Key::SkippedUntimestamped(skipped)));
+                            }
+                            answer
+                        }
+                        Err(e) => {
+                            log::warn!("Error while building the calendar {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Progress => {
+                    let answer = match db.get_messages(ChatId(cx.chat_id())) {
+                        Ok(messages) => {
+                            let tz = settings.timezone();
+                            let mine: Vec<_> = messages
+                                .into_iter()
+                                .filter(|msg| msg.from == UserId(from.id))
+                                .collect();
+                            let solved = |window: Window| {
+                                window
+                                    .filter(&mine)
+                                    .filter_map(|msg| msg.kata_key())
+                                    .unique()
+                                    .count() as i64
+                            };
+                            let mut answer = t(
+                                lang,
+                                Key::Progress {
+                                    this_week: solved(Window::last_days(7, 0, tz)),
+                                    last_week: solved(Window::last_days(7, 1, tz)),
+                                },
+                            );
+                            let skipped = mine.iter().filter(|msg| msg.date.is_none()).count();
+                            if skipped > 0 {
+                                answer.push('\n');
+                                answer.push_str(&t(lang, Key::SkippedUntimestamped(skipped)));
+                            }
+                            answer
+                        }
+                        Err(e) => {
+                            log::warn!("Error while computing progress {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Diff => {
+                    let days = match args.first().copied() {
+                        Some("week") => Some(7),
+                        Some("month") => Some(30),
+                        _ => None,
+                    };
+                    let chat_id = ChatId(cx.chat_id());
+                    let answer = match days {
+                        None => t(lang, Key::DiffUsage),
+                        Some(days) => {
+                            match (db.get_messages(chat_id), db.get_shared_users(chat_id)) {
+                                (Ok(messages), Ok(users)) => {
+                                    let tz = settings.timezone();
+                                    let diff = compute_period_diff(
+                                        &messages,
+                                        Window::last_days(days, 0, tz),
+                                        Window::last_days(days, 1, tz),
+                                    );
+                                    let list = diff
+                                        .per_user
+                                        .iter()
+                                        .map(|&(user, now, before)| {
+                                            format!(
+                                                "{}: {} ({:+})",
+                                                user_label(lang, &users, user),
+                                                now,
+                                                now as i64 - before as i64
+                                            )
+                                        })
+                                        .join("\n");
+                                    let mut answer = t(
+                                        lang,
+                                        Key::Diff {
+                                            days,
+                                            distinct: diff.distinct,
+                                            active: diff.active,
+                                            users: &list,
+                                        },
+                                    );
+                                    if diff.skipped > 0 {
+                                        answer.push('\n');
+                                        answer.push_str(&t(
+                                            lang,
+                                            Key::SkippedUntimestamped(diff.skipped),
+                                        ));
+                                    }
+                                    answer
+                                }
+                                (Err(e), _) | (_, Err(e)) => {
+                                    log::warn!("Error while computing the period diff {}", e);
+                                    t(lang, Key::DataError)
+                                }
+                            }
+                        }
+                    };
+                    for answer in utils::chunk_with_size(answer.as_str()) {
+                        cx.answer(answer).send().await?;
+                    }
+                }
+                Command::WhoAmI => {
+                    let user = db
+                        .get_users(ChatId(cx.chat_id()))
+                        .map(|mut us| us.remove(&UserId(from.id)));
+                    let answer = match user {
+                        Ok(Some(user)) => {
+                            let solved = db
+                                .user_solved_count(ChatId(cx.chat_id()), user.telegram_id)
+                                .map_err(|e| log::warn!("Error while getting solved count {}", e))
+                                .ok();
+                            t(
+                                lang,
+                                Key::WhoAmI {
+                                    codewars_name: &user.codewars_name,
+                                    username: user.username.as_deref(),
+                                    firstname: &user.firstname,
+                                    solved,
+                                },
+                            )
+                        }
+                        Ok(None) => t(lang, Key::NotRegisteredSelf),
+                        Err(e) => {
+                            log::warn!("Error while getting users {}", e);
+                            t(lang, Key::UserDataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Count => {
+                    let answer = match db.chat_counts(ChatId(cx.chat_id()), UserId(from.id)) {
+                        Ok(ChatCounts {
+                            solutions,
+                            distinct,
+                            own,
+                            rank,
+                            solvers,
+                        }) => t(
+                            lang,
+                            Key::Count {
+                                total: solutions,
+                                distinct,
+                                own,
+                                rank: rank.map(|rank| (rank, solvers)),
+                            },
+                        ),
+                        Err(e) => {
+                            log::warn!("Error while counting messages {}", e);
+                            t(lang, Key::CountFailed)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Summary => {
+                    const TOP_SOLVERS: usize = 3;
+
+                    let chat_id = ChatId(cx.chat_id());
+                    let answer = match (db.get_shared_users(chat_id), db.get_messages(chat_id)) {
+                        (Ok(users), Ok(messages)) => {
+                            let summary = aggregate_stats(&users, &messages);
+                            let top = summary
+                                .rows(lang, &users)
+                                .into_iter()
+                                .take(TOP_SOLVERS)
+                                .map(|(name, distinct)| format!("{} ({})", name, distinct))
+                                .join(", ");
+                            t(
+                                lang,
+                                Key::Summary {
+                                    users: users.len(),
+                                    distinct: summary.total_distinct,
+                                    solutions: summary.total_sent,
+                                    top: &top,
+                                    hardest: summary
+                                        .hardest
+                                        .as_ref()
+                                        .map(|(kyu, name)| (*kyu, name.as_str())),
+                                    language: summary.top_language(),
+                                },
+                            )
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while summarizing the chat {}", e);
+                            t(lang, Key::DataError)
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::KyuCheck => {
+                    let shared = db
+                        .get_shared_users(ChatId(cx.chat_id()))
+                        .unwrap_or_default();
+                    let mismatches = match (
+                        db.get_users(ChatId(cx.chat_id())),
+                        db.get_messages(ChatId(cx.chat_id())),
+                    ) {
+                        (Ok(us), Ok(msgs)) => compute_kyu_mismatches(http, us, msgs, &db).await,
+                        (Err(e), _) | (_, Err(e)) => Err(e),
+                    };
+                    let answer = match mismatches {
+                        Ok(ref users) if users.is_empty() => t(lang, Key::NoKyuMismatches),
+                        Ok(users) => t(
+                            lang,
+                            Key::KyuMismatches(
+                                users
+                                    .into_iter()
+                                    .map(|(user, katas)| {
+                                        format!(
+                                            "{}:\n{}",
+                                            user_label(lang, &shared, user.telegram_id),
+                                            katas
+                                                .into_iter()
+                                                .map(|k| t(
+                                                    lang,
+                                                    Key::KyuMismatch {
+                                                        name: &k.name,
+                                                        claimed: k.claimed,
+                                                        actual: k.actual,
+                                                    }
+                                                ))
+                                                .join("\n")
+                                        )
+                                    })
+                                    .join("\n"),
+                            ),
+                        ),
+                        Err(MainError::CodewarsApi(CodewarsApiError::NotFound(name))) => {
+                            t(lang, Key::UserNotFoundInApi(&name))
+                        }
+                        Err(e) => t(lang, Key::KyuCheckError(e.to_string())),
+                    };
+                    for answer in utils::chunk_with_size(answer.as_str()) {
+                        cx.answer(answer).send().await?;
+                    }
+                }
+                Command::Compare => {
+                    let users = db.get_shared_users(ChatId(cx.chat_id()));
+                    let messages = db.get_messages(ChatId(cx.chat_id()));
+                    match (users, messages) {
+                        (Ok(users), Ok(messages)) => {
+                            let caller = from.id.to_string();
+                            let pair = match args.as_slice() {
+                                [a] => Some((*a, caller.as_str())),
+                                [a, b] => Some((*a, *b)),
+                                _ => None,
+                            }
+                            .map(|(a, b)| {
+                                let resolve =
+                                    |query| resolve_user(&users, query).or_reply(lang, query);
+                                Ok::<_, String>((resolve(a)?, resolve(b)?))
+                            });
+                            match pair {
+                                Some(Ok((a, b))) if a.telegram_id != b.telegram_id => {
+                                    answer_image(cx, lang, compute_compare_chart(a, b, &messages))
+                                        .await?
+                                }
+                                Some(Err(reply)) => {
+                                    cx.answer(reply).send().await?;
+                                }
+                                _ => {
+                                    cx.answer(t(lang, Key::CompareUsage)).send().await?;
+                                }
+                            }
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            log::warn!("Error while comparing users {}", e);
+                            cx.answer(t(lang, Key::DataError)).send().await?;
+                        }
+                    }
+                }
+                Command::DeleteKata => {
+                    let name = args.join(" ");
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else if name.is_empty() {
+                        t(lang, Key::DeleteKataUsage)
+                    } else {
+                        match db.delete_kata(ChatId(cx.chat_id()), &name) {
+                            Ok(0) => t(lang, Key::KataNotFound(&name)),
+                            Ok(removed) => t(
+                                lang,
+                                Key::KataDeleted {
+                                    name: &name,
+                                    removed,
+                                },
+                            ),
+                            Err(e) => {
+                                log::warn!("Error while deleting a kata {}", e);
+                                t(lang, Key::DeleteKataFailed)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Prune => {
+                    let ids: Option<Vec<i32>> = args.iter().map(|arg| message_id(arg)).collect();
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        match ids {
+                            Some(ref ids) if !ids.is_empty() => {
+                                let removed = ids.iter().try_fold(0, |removed, &id| {
+                                    db.remove_message(ChatId(cx.chat_id()), id)
+                                        .map(|found| removed + found as usize)
+                                });
+                                match removed {
+                                    Ok(removed) => t(lang, Key::Pruned(removed)),
+                                    Err(e) => {
+                                        log::warn!("Error while pruning messages {}", e);
+                                        t(lang, Key::PruneFailed)
+                                    }
+                                }
+                            }
+                            _ => t(lang, Key::PruneUsage),
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::StartSeason => {
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        let settings = ChatSettings {
+                            season_start: Some(Utc::now()),
+                            ..settings
+                        };
+                        match db.set_settings(ChatId(cx.chat_id()), settings) {
+                            Ok(_) => t(lang, Key::SeasonStarted),
+                            Err(e) => {
+                                log::warn!("Error while starting a season {}", e);
+                                t(lang, Key::SeasonFailed)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Reparse => {
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        match db.reparse_messages(ChatId(cx.chat_id()), detector) {
+                            Ok(changed) => t(lang, Key::Reparsed(changed)),
+                            Err(e) => {
+                                log::warn!("Error while reparsing messages {}", e);
+                                t(lang, Key::ReparseFailed)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Recompute => {
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else {
+                        match db.rebuild_indexes(ChatId(cx.chat_id())) {
+                            Ok(recomputed) => t(
+                                lang,
+                                Key::Recomputed {
+                                    katas: recomputed.katas,
+                                    counters: recomputed.counters,
+                                    message_count: recomputed.message_count,
+                                },
+                            ),
+                            Err(e) => {
+                                log::warn!("Error while recomputing indexes {}", e);
+                                t(lang, Key::RecomputeFailed)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Bursts => {
+                    let limits = match args.as_slice() {
+                        [] => None,
+                        [katas, minutes] => match (katas.parse(), minutes.parse()) {
+                            (Ok(katas), Ok(minutes)) if katas > 1 && minutes > 0 => {
+                                Some(Some((katas, minutes)))
+                            }
+                            _ => Some(None),
+                        },
+                        _ => Some(None),
+                    };
+                    let answer = match limits {
+                        Some(None) => t(lang, Key::BurstsUsage),
+                        Some(Some(_)) if !is_admin(cx, UserId(from.id)).await? => {
+                            t(lang, Key::AdminsOnly)
+                        }
+                        Some(Some((burst_katas, burst_minutes))) => {
+                            let settings = ChatSettings {
+                                burst_katas,
+                                burst_minutes,
+                                ..settings
+                            };
+                            match db.set_settings(ChatId(cx.chat_id()), settings) {
+                                Ok(_) => t(
+                                    lang,
+                                    Key::BurstsSet {
+                                        katas: burst_katas,
+                                        minutes: burst_minutes,
+                                    },
+                                ),
+                                Err(e) => {
+                                    log::warn!("Error while setting bursts {}", e);
+                                    t(lang, Key::BurstsFailed)
+                                }
+                            }
+                        }
+                        None => match (
+                            db.get_shared_users(ChatId(cx.chat_id())),
+                            db.get_messages(ChatId(cx.chat_id())),
+                        ) {
+                            (Ok(users), Ok(messages)) => {
+                                let tz = settings.timezone();
+                                let format = |date: chrono::DateTime<Utc>| {
+                                    date.with_timezone(&tz).format("%Y-%m-%d %H:%M").to_string()
+                                };
+                                let lines: Vec<_> = users
+                                    .values()
+                                    .sorted_by_key(|user| user.firstname.clone())
+                                    .flat_map(|user| {
+                                        compute_bursts(
+                                            messages
+                                                .iter()
+                                                .filter(|msg| msg.from == user.telegram_id),
+                                            settings.burst_katas,
+                                            chrono::Duration::minutes(settings.burst_minutes),
+                                        )
+                                        .into_iter()
+                                        .map(move |burst| (user, burst))
+                                    })
+                                    .map(|(user, burst)| {
+                                        t(
+                                            lang,
+                                            Key::Burst {
+                                                name: &user.firstname,
+                                                katas: burst.katas,
+                                                start: format(burst.start),
+                                                end: format(burst.end),
+                                            },
+                                        )
+                                    })
+                                    .collect();
+                                if lines.is_empty() {
+                                    t(
+                                        lang,
+                                        Key::NoBursts {
+                                            katas: settings.burst_katas,
+                                            minutes: settings.burst_minutes,
+                                        },
+                                    )
+                                } else {
+                                    format!(
+                                        "{}\n{}",
+                                        t(
+                                            lang,
+                                            Key::BurstsHeader {
+                                                katas: settings.burst_katas,
+                                                minutes: settings.burst_minutes,
+                                            },
+                                        ),
+                                        lines.join("\n")
+                                    )
+                                }
+                            }
+                            (Err(e), _) | (_, Err(e)) => {
+                                log::warn!("Error while computing bursts {}", e);
+                                t(lang, Key::DataError)
+                            }
+                        },
+                    };
+                    for answer in utils::chunk_with_size(answer.as_str()) {
+                        cx.answer(answer).send().await?;
+                    }
+                }
+                Command::RemoveUser => {
+                    let target = match args.as_slice() {
+                        [user] => Some((*user, false)),
+                        [user, "purge"] => Some((*user, true)),
+                        _ => None,
+                    };
+                    let chat_id = ChatId(cx.chat_id());
+                    let answer = match target {
+                        _ if !is_admin(cx, UserId(from.id)).await? => t(lang, Key::AdminsOnly),
+                        None => t(lang, Key::RemoveUserUsage),
+                        Some((query, purge)) => match db.get_users(chat_id) {
+                            Ok(users) => match find_user(&users, query) {
+                                Some(user) => {
+                                    match db.remove_member(chat_id, user.telegram_id, purge) {
+                                        Ok(messages) => t(
+                                            lang,
+                                            Key::UserRemoved {
+                                                name: &user.firstname,
+                                                codewars_name: &user.codewars_name,
+                                                messages: Some(messages).filter(|_| purge),
+                                            },
+                                        ),
+                                        Err(e) => {
+                                            log::warn!("Error while removing a member {}", e);
+                                            t(lang, Key::RemoveFailed(&user.firstname))
+                                        }
+                                    }
+                                }
+                                None => t(lang, Key::NotRegistered(query)),
+                            },
+                            Err(e) => {
+                                log::warn!("Error while getting users {}", e);
+                                t(lang, Key::UserDataError)
+                            }
+                        },
+                    };
+                    cx.answer(answer).send().await?;
+                }
+                Command::Merge => {
+                    let answer = if !is_admin(cx, UserId(from.id)).await? {
+                        t(lang, Key::AdminsOnly)
+                    } else if args.len() != 2 {
+                        t(lang, Key::MergeUsage)
+                    } else {
+                        match db.get_users(ChatId(cx.chat_id())) {
+                            Ok(users) => {
+                                match (find_user(&users, args[0]), find_user(&users, args[1])) {
+                                    (Some(a), Some(b)) if a.telegram_id == b.telegram_id => {
+                                        t(lang, Key::MergeSelf)
+                                    }
+                                    (Some(a), Some(b)) => {
+                                        match db.merge_users(
+                                            ChatId(cx.chat_id()),
+                                            b.telegram_id,
+                                            a.telegram_id,
+                                        ) {
+                                            Ok(total) => t(
+                                                lang,
+                                                Key::Merged {
+                                                    from: &b.firstname,
+                                                    into: &a.firstname,
+                                                    total,
+                                                },
+                                            ),
+                                            Err(e) => {
+                                                log::warn!("Error while merging users {}", e);
+                                                t(lang, Key::MergeFailed)
+                                            }
+                                        }
+                                    }
+                                    (a, _) => t(
+                                        lang,
+                                        Key::NotRegistered(if a.is_none() {
+                                            args[0]
+                                        } else {
+                                            args[1]
+                                        }),
+                                    ),
+                                }
+                            }
+                            Err(e) => {
+                                log::warn!("Error while getting users {}", e);
+                                t(lang, Key::UserDataError)
+                            }
+                        }
+                    };
+                    cx.answer(answer).send().await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_parse::Codewars;
+
+    fn user(id: i32) -> User {
+        User {
+            id,
+            is_bot: false,
+            first_name: format!("user{}", id),
+            last_name: None,
+            username: None,
+            language_code: None,
+        }
+    }
+
+    #[test]
+    fn addme_tells_updates_from_repeats() {
+        let db = Persist::temporary();
+        let chat = ChatId(1);
+        let mut answers = Vec::new();
+        for codewars_name in ["alice", "alice", "alice_cw"].iter() {
+            let registered = CodeUser {
+                username: None,
+                firstname: "Alice".to_owned(),
+                telegram_id: UserId(1),
+                codewars_name: (*codewars_name).to_owned(),
+                default_language: None,
+            };
+            let previous = db.add_user(chat, registered).unwrap();
+            answers.push(t(
+                Lang::En,
+                registered_key("Alice", previous.as_ref(), codewars_name),
+            ));
+        }
+        assert_eq!(
+            answers,
+            vec![
+                "Added user Alice with codewars username alice",
+                "Alice is already registered with codewars username alice",
+                "Updated the codewars username of Alice from alice to alice_cw",
+            ]
+        );
+        assert_eq!(
+            db.get_users(chat).unwrap()[&UserId(1)].codewars_name,
+            "alice_cw"
+        );
+    }
+
+    #[test]
+    fn forwarded_solutions_follow_the_setting() {
+        let poster = user(1);
+        let original = user(2);
+        let attribute = ChatSettings::default();
+        let skip = ChatSettings {
+            skip_forwards: true,
+            ..ChatSettings::default()
+        };
+
+        let author = |forward, settings| solution_author(&poster, forward, settings).map(|u| u.id);
+        assert_eq!(author(None, &attribute), Some(1));
+        assert_eq!(author(None, &skip), Some(1));
+        assert_eq!(author(Some(Some(&original)), &attribute), Some(2));
+        assert_eq!(author(Some(None), &attribute), None);
+        assert_eq!(author(Some(Some(&original)), &skip), None);
+    }
+
+    #[test]
+    fn exported_solutions_skip_missing_authors() {
+        let chat: ChatData = serde_json::from_str(
+            r#"{
+                "name": "chat",
+                "id": 1,
+                "messages": [
+                    {"id": 1, "type": "message", "from_id": 10,
+                     "text": "7\nRobinson Crusoe\nhttps://pastebin.com/fZHdUbhT"},
+                    {"id": 2, "type": "message",
+                     "text": "6\nCreate Phone Number\nhttps://pastebin.com/grekUgAs"},
+                    {"id": 3, "type": "message", "from_id": 10, "text": "hi"},
+                    {"id": 4, "type": "service"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let exported = exported_solutions(&chat, &Codewars);
+        assert_eq!(
+            exported
+                .solutions
+                .iter()
+                .map(|msg| (msg.id, msg.from))
+                .collect::<Vec<_>>(),
+            vec![(1, UserId(10))]
+        );
+        assert_eq!(exported.no_author, 1);
+        assert_eq!(exported.non_solutions, 1);
+    }
+
+    #[test]
+    fn safe_mode_refuses_only_writes() {
+        let writes = |text| {
+            let (command, args) = Command::parse(text, "bot").unwrap();
+            command.writes(&args)
+        };
+        assert!(writes("/clear"));
+        assert!(writes("/kata 7 Robinson Crusoe"));
+        assert!(writes("/goal 100"));
+        assert!(!writes("/goal"));
+        assert!(!writes("/showstats"));
+        assert!(!writes("/count"));
+        assert!(!writes("/importstatus"));
+        assert!(writes("/kyucheck"));
+    }
+
+    #[test]
+    fn commands_are_labelled_by_name() {
+        let label = |text| Command::parse(text, "bot").unwrap().0.label();
+        assert_eq!(label("/showstats"), "showstats");
+        assert_eq!(label("/kyucheck@bot"), "kyucheck");
+        assert_eq!(label("/goal 100"), "goal");
+    }
+
+    #[test]
+    fn safe_mode_refuses_writes_in_the_chat_language() {
+        let db = Persist::temporary();
+        let settings = ChatSettings {
+            language: Some(Lang::Ru),
+            ..db.get_settings(ChatId(1)).unwrap()
+        };
+        db.set_settings(ChatId(1), settings).unwrap();
+        let message = |text: &str| -> Message {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "message_id": 5,
+                    "date": 1700000000,
+                    "chat": {{"id": 1, "type": "private", "first_name": "John"}},
+                    "from": {{"id": 10, "is_bot": false, "first_name": "John"}},
+                    "text": {}
+                }}"#,
+                serde_json::to_string(text).unwrap()
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            safe_mode_refusal(&message("/clear"), &db, "bot"),
+            Some(t(Lang::Ru, Key::SafeMode))
+        );
+        assert_eq!(
+            safe_mode_refusal(&message("/kyucheck"), &db, "bot"),
+            Some(t(Lang::Ru, Key::SafeMode))
+        );
+        assert_eq!(safe_mode_refusal(&message("/count"), &db, "bot"), None);
+        assert_eq!(
+            safe_mode_refusal(&message("7\nRobinson Crusoe"), &db, "bot"),
+            None
+        );
+    }
+
+    #[test]
+    fn caption_only_solutions_are_registered() {
+        let photo: Message = serde_json::from_str(
+            r#"{
+                "message_id": 5,
+                "date": 1700000000,
+                "chat": {"id": 1, "type": "private", "first_name": "John"},
+                "from": {"id": 10, "is_bot": false, "first_name": "John"},
+                "photo": [{"file_id": "a", "file_unique_id": "b", "width": 90, "height": 90}],
+                "caption": "7\nRobinson Crusoe\nhttps://pastebin.com/fZHdUbhT"
+            }"#,
+        )
+        .unwrap();
+        let text = solution_text(&photo).unwrap();
+        let solution = Codewars.parse_solution(text).unwrap();
+        assert_eq!(solution.link, "https://pastebin.com/fZHdUbhT");
+
+        let chat: ChatData = serde_json::from_str(
+            r#"{
+                "name": "chat",
+                "id": 1,
+                "messages": [
+                    {"id": 1, "type": "message", "from_id": 10, "photo": "photos/1.jpg",
+                     "text": "", "caption": "6\nCreate Phone Number\nhttps://pastebin.com/grekUgAs"},
+                    {"id": 2, "type": "message", "from_id": 10, "photo": "photos/2.jpg",
+                     "text": ["7\nRobinson Crusoe\n",
+                              {"type": "link", "text": "https://pastebin.com/fZHdUbhT"}]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let exported = exported_solutions(&chat, &Codewars);
+        assert_eq!(
+            exported
+                .solutions
+                .iter()
+                .map(|msg| msg.id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(exported.non_solutions, 0);
+    }
+
+    #[test]
+    fn codewars_name_is_trimmed() {
+        assert_eq!(parse_codewars_name("john_doe"), Ok("john_doe"));
+        assert_eq!(parse_codewars_name("  john_doe  "), Ok("john_doe"));
+        assert_eq!(parse_codewars_name(" @john_doe"), Ok("john_doe"));
+        assert_eq!(parse_codewars_name(""), Err(CodewarsNameError::Empty));
+        assert_eq!(parse_codewars_name("  @ "), Err(CodewarsNameError::Empty));
+        assert_eq!(
+            parse_codewars_name("john doe"),
+            Err(CodewarsNameError::Spaces("john doe"))
+        );
+    }
+
+    #[test]
+    fn codewars_name_is_validated() {
+        assert_eq!(parse_codewars_name("John-Doe_42"), Ok("John-Doe_42"));
+        let longest = "a".repeat(CODEWARS_NAME_MAX);
+        assert_eq!(parse_codewars_name(&longest), Ok(longest.as_str()));
+        assert_eq!(
+            parse_codewars_name(&"a".repeat(CODEWARS_NAME_MAX + 1)),
+            Err(CodewarsNameError::TooLong)
+        );
+        assert_eq!(
+            parse_codewars_name(&"a b".repeat(100)),
+            Err(CodewarsNameError::TooLong)
+        );
+        assert_eq!(parse_codewars_name("\t\n"), Err(CodewarsNameError::Empty));
+        for name in &[
+            "john/../doe",
+            "john?page=1",
+            "jöhn",
+            "john\u{0}",
+            "<b>john</b>",
+            "@@john",
+        ] {
+            assert_eq!(
+                parse_codewars_name(name),
+                Err(CodewarsNameError::InvalidChars),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn every_command_has_help() {
+        let commands: Vec<_> = Command::descriptions()
+            .lines()
+            .filter_map(|line| line.strip_prefix('/'))
+            .map(|line| line.split_whitespace().next().unwrap().to_owned())
+            .collect();
+        assert!(commands.len() > 20);
+        for command in commands {
+            for &lang in &[Lang::En, Lang::Ru] {
+                let help = command_help(lang, &command);
+                assert!(
+                    matches!(help, Some(help) if help.starts_with(&format!("/{}", command))),
+                    "no {:?} help for /{}",
+                    lang,
+                    command
+                );
+            }
+        }
+        assert_eq!(command_help(Lang::En, "nosuchcommand"), None);
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_cap() {
+        let delays: Vec<_> = (0..4).map(reconnect_delay).collect();
+        assert_eq!(
+            delays,
+            vec![1, 2, 4, 8]
+                .into_iter()
+                .map(Duration::from_secs)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(reconnect_delay(9), RECONNECT_MAX);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX);
+    }
+
+    #[test]
+    fn message_ids_from_links() {
+        assert_eq!(message_id("42"), Some(42));
+        assert_eq!(message_id("https://t.me/c/1234567890/42"), Some(42));
+        assert_eq!(message_id("https://t.me/some_chat/43/"), Some(43));
+        assert_eq!(message_id("https://t.me/some_chat"), None);
+        assert_eq!(message_id("forty-two"), None);
+    }
+
+    /// Fails `fails` times with `error`, returns the number of calls made
+    async fn send_failing(
+        calls: &std::cell::Cell<u32>,
+        fails: u32,
+        error: fn() -> RequestError,
+    ) -> ResponseResult<u32> {
+        calls.set(0);
+        send_with_retry(|| {
+            calls.set(calls.get() + 1);
+            let res = if calls.get() > fails {
+                Ok(calls.get())
+            } else {
+                Err(error())
+            };
+            async move { res }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn flood_control_is_waited_out() {
+        let calls = std::cell::Cell::new(0);
+        let retry_after = || RequestError::RetryAfter(0);
+        assert_eq!(send_failing(&calls, 2, retry_after).await.unwrap(), 3);
+        assert!(matches!(
+            send_failing(&calls, SEND_ATTEMPTS, retry_after).await,
+            Err(RequestError::RetryAfter(0))
+        ));
+        assert_eq!(calls.get(), SEND_ATTEMPTS);
+        assert!(matches!(
+            send_failing(&calls, 1, || RequestError::MigrateToChatId(1)).await,
+            Err(RequestError::MigrateToChatId(1))
+        ));
+        assert_eq!(calls.get(), 1);
+    }
+
+    /// Codewars that only knows Robinson Crusoe, counting the requests
+    struct OneKata {
+        requests: std::sync::Mutex<usize>,
+    }
+
+    impl Fetch for OneKata {
+        fn get_text<'a>(
+            &'a self,
+            url: &'a str,
+        ) -> futures::future::BoxFuture<'a, Result<String, MainError>> {
+            *self.requests.lock().unwrap() += 1;
+            let body = if url.ends_with("/code-challenges/robinson-crusoe") {
+                r#"{"id": "5a5f48f2880385e5f7ff5b81", "name": "Robinson Crusoe",
+                    "slug": "robinson-crusoe", "rank": {"id": -7}}"#
+            } else {
+                r#"{"success": false, "reason": "not found"}"#
+            };
+            Box::pin(futures::future::ready(Ok(body.to_owned())))
+        }
+    }
+
+    #[tokio::test]
+    async fn differently_typed_names_collapse_to_the_canonical_kata() {
+        let (db, http) = (
+            Persist::temporary(),
+            OneKata {
+                requests: Default::default(),
+            },
+        );
+        let chat = ChatId(1);
+        let posts = [
+            (1, 10, "7\nRobinson Crusoe"),
+            (2, 20, "7\nrobinson-crusoe"),
+            (3, 30, "7\nRobinson Crusoe!"),
+            (4, 10, "6\nCreate Phone Number"),
+        ];
+        for (id, from, name) in posts.iter() {
+            let text = format!("{}\nhttps://pastebin.com/abc{}", name, id);
+            let solution = Codewars.parse_solution(&text).unwrap();
+            db.add_message(
+                chat,
+                ChatMessage {
+                    id: *id,
+                    from: UserId(*from),
+                    kata_id: resolve_kata(&db, &http, &solution.kata_name).await,
+                    solution: Some(solution),
+                    text,
+                    date: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let canonical = Some("5a5f48f2880385e5f7ff5b81".to_owned());
+        assert_eq!(db.kata_alias("robinson-crusoe").unwrap(), canonical);
+        assert_eq!(db.kata_alias("Create Phone Number").unwrap(), None);
+        // the title and the slug are kept with the posted name, later variants aren't asked about
+        assert_eq!(*http.requests.lock().unwrap(), 2);
+        assert_eq!(db.distinct_kata_count(chat).unwrap(), 2);
+        assert_eq!(
+            db.messages_for_kata(chat, "robinson crusoe").unwrap().len(),
+            3
+        );
+        assert_eq!(
+            db.messages_for_kata(chat, "Create Phone Number")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn handled_updates_are_timed_by_label() {
+        let db = Arc::new(Persist::temporary());
+        let detector: Arc<dyn SolutionDetector> = Arc::new(Codewars);
+        let (config, metrics) = (Config::default(), Metrics::default());
+        let http = OneKata {
+            requests: Default::default(),
+        };
+        let settings = ChatSettings {
+            acknowledge: Acknowledge::Silent,
+            ..db.get_settings(ChatId(1)).unwrap()
+        };
+        db.set_settings(ChatId(1), settings).unwrap();
+        // answers go to a closed local port instead of telegram and fail there
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap())
+            .build()
+            .unwrap();
+        for (id, text) in [
+            (1, "/count"),
+            (2, "7\\nRobinson Crusoe\\nhttps://pastebin.com/fZHdUbhT"),
+            (3, "/count@CodeWarsCheatStats_bot"),
+        ]
+        .iter()
+        {
+            let update: Message = serde_json::from_str(&format!(
+                r#"{{
+                    "message_id": {},
+                    "date": 1700000000,
+                    "chat": {{"id": 1, "type": "private", "first_name": "John"}},
+                    "from": {{"id": 10, "is_bot": false, "first_name": "John"}},
+                    "text": "{}"
+                }}"#,
+                id, text
+            ))
+            .unwrap();
+            let cx = DispatcherHandlerCx {
+                bot: Bot::with_client("token", client.clone()),
+                update,
+            };
+            handle_message(cx, &db, &detector, &config, &http, &metrics)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(metrics.count("count"), 2);
+        assert_eq!(metrics.count("message"), 1);
+        assert_eq!(db.get_messages(ChatId(1)).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn solutions_below_min_kyu_are_not_stored() {
+        let db = Arc::new(Persist::temporary());
+        let http = OneKata {
+            requests: Default::default(),
+        };
+        let chat = ChatId(1);
+        // a silent chat sends nothing, so nothing here reaches telegram
+        let settings = ChatSettings {
+            min_kyu: Some(6),
+            acknowledge: Acknowledge::Silent,
+            ..db.get_settings(chat).unwrap()
+        };
+        db.set_settings(chat, settings).unwrap();
+        for (id, kyu) in [(1, 8), (2, 6), (3, 7), (4, 5)].iter() {
+            let update: Message = serde_json::from_str(&format!(
+                r#"{{
+                    "message_id": {},
+                    "date": 1700000000,
+                    "chat": {{"id": 1, "type": "private", "first_name": "John"}},
+                    "from": {{"id": 10, "is_bot": false, "first_name": "John"}},
+                    "text": "{}\nRobinson Crusoe\nhttps://pastebin.com/fZHdUbhT"
+                }}"#,
+                id, kyu
+            ))
+            .unwrap();
+            let cx = DispatcherHandlerCx {
+                bot: Bot::new("token"),
+                update,
+            };
+            store_message(cx, db.clone(), Arc::new(Codewars), &http)
+                .await
+                .unwrap();
+        }
+        let stored: Vec<_> = db
+            .get_messages(chat)
+            .unwrap()
+            .iter()
+            .map(|msg| msg.id)
+            .collect();
+        assert_eq!(stored, vec![2, 4]);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_asked_once() {
+        let (db, http) = (
+            Persist::temporary(),
+            OneKata {
+                requests: Default::default(),
+            },
+        );
+        let restored = vec![
+            ChatMessage {
+                id: 1,
+                from: UserId(10),
+                kata_id: None,
+                text: "7\nRobinson Crusoe\nhttps://pastebin.com/abc1".to_owned(),
+                solution: Codewars.parse_solution("7\nRobinson Crusoe\nhttps://pastebin.com/abc1"),
+                date: None,
+            },
+            ChatMessage {
+                id: 2,
+                from: UserId(10),
+                kata_id: None,
+                text: "6\nCreate Phone Number\nhttps://pastebin.com/abc2".to_owned(),
+                solution: Codewars
+                    .parse_solution("6\nCreate Phone Number\nhttps://pastebin.com/abc2"),
+                date: None,
+            },
+        ];
+        let resolved = with_kata_ids(&db, &http, restored).await;
+        assert_eq!(
+            resolved[0].kata_id.as_deref(),
+            Some("5a5f48f2880385e5f7ff5b81")
+        );
+        assert_eq!(resolved[1].kata_id, None);
+        assert_eq!(*http.requests.lock().unwrap(), 2);
+
+        assert_eq!(
+            resolve_kata(&db, &http, "6 Create Phone Number").await,
+            None
+        );
+        assert_eq!(*http.requests.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn export_files_are_matched_in_order() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        for name in &[
+            "exported_messages_2.json",
+            "exported_messages.json",
+            "used_exported_messages_1.json",
+            "exported_messages_1.json",
+            "exported_messages_3.txt",
+            "notes.json",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        std::fs::create_dir(dir.join("exported_messages_dir.json")).unwrap();
+
+        let names: Vec<_> = export_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            names,
+            vec![
+                "exported_messages.json",
+                "exported_messages_1.json",
+                "exported_messages_2.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn user_names_are_resolved_loosely() {
+        let code_user = |id: i32, codewars_name: &str, firstname: &str| {
+            (
+                UserId(id),
+                CodeUser {
+                    username: None,
+                    firstname: firstname.to_owned(),
+                    telegram_id: UserId(id),
+                    codewars_name: codewars_name.to_owned(),
+                    default_language: None,
+                },
+            )
+        };
+        let users: HashMap<_, _> = vec![
+            code_user(1, "Kolya_Cheater", "Nikolay"),
+            code_user(2, "lokiv", "Loki"),
+            code_user(3, "lokim", "Mark"),
+        ]
+        .into_iter()
+        .collect();
+        let found = |query| match resolve_user(&users, query) {
+            UserMatch::Found(user) => Some(user.telegram_id.0),
+            _ => None,
+        };
+
+        assert_eq!(found("lokiv"), Some(2));
+        assert_eq!(found("2"), Some(2));
+        assert_eq!(found("kolya_cheater"), Some(1));
+        assert_eq!(found("MARK"), Some(3));
+        assert_eq!(found("Kolya_Chaeter"), Some(1));
+        assert_eq!(found("Nikolai"), Some(1));
+        assert_eq!(found("Somebody"), None);
+        match resolve_user(&users, "lokix") {
+            UserMatch::Ambiguous(users) => assert_eq!(users.len(), 2),
+            _ => panic!("lokix is one typo away from both lokiv and lokim"),
+        }
+        assert_eq!(
+            resolve_user(&users, "lokix")
+                .or_reply(Lang::En, "lokix")
+                .map(|user| user.telegram_id),
+            Err("Several users match, did you mean lokim (Mark), lokiv (Loki)?".to_owned())
+        );
+    }
 }
@@ -1,38 +1,340 @@
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 lazy_static! {
     static ref IS_SOLUTION_REGEX: regex::Regex =
         regex::Regex::new(r"^\d[\s\S]*?https://pastebin\.com/").unwrap();
     static ref KATA_KYU: regex::Regex = regex::Regex::new(r"^\d(?:\s*kyu|\s)").unwrap();
+    static ref KYU_PREFIX: regex::Regex = regex::Regex::new(r"^(\d)(?:\s*kyu)?\s*").unwrap();
     static ref JUST_LINK: regex::Regex =
         regex::Regex::new(r"https://pastebin\.com/[a-zA-Z\d]*").unwrap();
+    static ref PASTE_ID: regex::Regex =
+        regex::Regex::new(r"pastebin\.com/(?:raw/)?([a-zA-Z\d]+)(?:\s|$)").unwrap();
     static ref LINK_AND_EVERYTHING_AFTER: regex::Regex =
         regex::Regex::new(r"https://pastebin\.com/(.|\s)*").unwrap();
+    static ref HASHTAG: regex::Regex = regex::Regex::new(r"#(\S+)").unwrap();
+}
+
+/// Spellings people use for a language and the name it's stored under
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("rust", "rust"),
+    ("rs", "rust"),
+    ("python", "python"),
+    ("py", "python"),
+    ("javascript", "javascript"),
+    ("js", "javascript"),
+    ("typescript", "typescript"),
+    ("ts", "typescript"),
+    ("java", "java"),
+    ("kotlin", "kotlin"),
+    ("kt", "kotlin"),
+    ("scala", "scala"),
+    ("haskell", "haskell"),
+    ("hs", "haskell"),
+    ("c", "c"),
+    ("cpp", "cpp"),
+    ("c++", "cpp"),
+    ("csharp", "csharp"),
+    ("c#", "csharp"),
+    ("cs", "csharp"),
+    ("go", "go"),
+    ("golang", "go"),
+    ("ruby", "ruby"),
+    ("rb", "ruby"),
+    ("elixir", "elixir"),
+    ("clojure", "clojure"),
+    ("clj", "clojure"),
+    ("fsharp", "fsharp"),
+    ("f#", "fsharp"),
+    ("sql", "sql"),
+];
+
+/// Everything the parser extracts from a solution message, stored next to the raw text
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ParsedSolution {
+    /// As posted, with the claimed kyu if there is one
+    pub kata_name: String,
+    pub link: String,
+    pub kyu: Option<u8>,
+    pub language: Option<String>,
+    pub paste_id: Option<String>,
+    /// Recorded with /kata, there is no paste to check
+    #[serde(default)]
+    pub linkless: bool,
+}
+
+impl ParsedSolution {
+    /// Solution claimed without a paste, the name gets the kyu like a posted one
+    pub fn linkless(kyu: u8, name: &str) -> Self {
+        ParsedSolution {
+            kata_name: format!("{} {}", kyu, name),
+            link: String::new(),
+            kyu: Some(kyu),
+            language: None,
+            paste_id: None,
+            linkless: true,
+        }
+    }
+
+    /// `default` fills in the language only when the message names none
+    pub fn with_default_language(mut self, default: Option<&str>) -> Self {
+        if self.language.is_none() {
+            self.language = default.map(str::to_owned);
+        }
+        self
+    }
+}
+
+/// Recognizes solution messages of one source, like Codewars or LeetCode
+pub trait SolutionDetector: Send + Sync {
+    fn is_solution(&self, msg: &str) -> bool;
+
+    /// `None` if the message isn't a solution
+    fn parse_solution(&self, msg: &str) -> Option<ParsedSolution>;
+}
+
+pub struct Codewars;
+
+impl SolutionDetector for Codewars {
+    fn is_solution(&self, msg: &str) -> bool {
+        is_codewars_solution(msg)
+    }
+
+    fn parse_solution(&self, msg: &str) -> Option<ParsedSolution> {
+        if !self.is_solution(msg) {
+            return None;
+        }
+        let (kata_name, link) = kata_name_link(msg)?;
+        Some(ParsedSolution {
+            kyu: claimed_kyu(&kata_name),
+            language: language(msg),
+            kata_name,
+            link,
+            paste_id: paste_id(msg),
+            linkless: false,
+        })
+    }
+}
+
+/// Detector for the configured `solution_source`, Codewars for unknown ones
+pub fn detector_for(source: &str) -> Arc<dyn SolutionDetector> {
+    if source != "codewars" {
+        log::warn!("Unknown solution source {}, using codewars", source);
+    }
+    Arc::new(Codewars)
 }
 
 pub fn is_codewars_solution(msg: &str) -> bool {
     IS_SOLUTION_REGEX.is_match(msg)
 }
 
-pub fn kata_name_link(msg: &str) -> (String, String) {
+/// `None` if the text isn't a codewars solution
+pub fn kata_name_link(msg: &str) -> Option<(String, String)> {
     if !is_codewars_solution(msg) {
-        panic!("Text {} is not a codewars solution", msg);
+        return None;
     }
-    let link = JUST_LINK
-        .find(msg)
-        .expect(format!("Link not found in {}", msg).as_str());
+    let link = JUST_LINK.find(msg)?;
     let name = LINK_AND_EVERYTHING_AFTER.replace(msg, "");
-    (
+    Some((
         name.trim().replace("\n", " "),
         link.as_str().trim().replace("\n", " "),
-    )
+    ))
+}
+
+/// Bare paste id of the first pastebin link, raw links included
+pub fn paste_id(msg: &str) -> Option<String> {
+    PASTE_ID
+        .captures(msg)
+        .map(|c| c[1].to_owned())
+        .filter(|id| id != "raw")
+}
+
+/// Stored name of a language, `None` for unknown ones
+pub fn normalize_language(name: &str) -> Option<&'static str> {
+    let name = name
+        .trim()
+        .trim_end_matches(&[',', '.', '!', '?', ';', ':', ')'][..])
+        .to_lowercase();
+    LANGUAGE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, language)| *language)
+}
+
+/// A hashtag anywhere or the first word after the link, the rest of the text is
+/// never looked at since names like "Go Left" would be taken for a language
+pub fn language(msg: &str) -> Option<String> {
+    let after_link = JUST_LINK.find(msg).map_or("", |link| &msg[link.end()..]);
+    HASHTAG
+        .captures_iter(msg)
+        .map(|c| c.get(1).unwrap().as_str())
+        .chain(after_link.split_whitespace().next())
+        .find_map(normalize_language)
+        .map(str::to_owned)
+}
+
+/// Kyu the poster put in front of the kata name
+pub fn claimed_kyu(name: &str) -> Option<u8> {
+    KYU_PREFIX
+        .captures(name)
+        .and_then(|c| c[1].parse().ok())
+        .filter(|kyu| (1..=8).contains(kyu))
+}
+
+/// Kata name without the claimed kyu, the way Codewars names it
+pub fn strip_kyu(name: &str) -> &str {
+    match KYU_PREFIX.find(name) {
+        Some(m) => &name[m.end()..],
+        None => name,
+    }
+}
+
+/// Codewars slug the title `name` would get, e.g. "Don't Stop!" is "dont-stop"
+pub fn kata_slug(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '\'' | '\u{2019}'))
+        .collect::<String>()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .join("-")
+}
+
+/// Dedup key for kata names, the name itself is kept for display
+pub fn normalize_kata_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() && c != ')')
+        .chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201C}' | '\u{201D}' => '"',
+            '\u{2013}' | '\u{2014}' => '-',
+            c => c,
+        })
+        .collect::<String>()
+        .to_lowercase()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Messages like "aoc 2019 day 1 https://adventofcode.com/2019/day/1"
+    struct AdventOfCode;
+
+    impl SolutionDetector for AdventOfCode {
+        fn is_solution(&self, msg: &str) -> bool {
+            msg.starts_with("aoc ") && msg.contains("https://adventofcode.com/")
+        }
+
+        fn parse_solution(&self, msg: &str) -> Option<ParsedSolution> {
+            if !self.is_solution(msg) {
+                return None;
+            }
+            let link_start = msg.find("https://")?;
+            Some(ParsedSolution {
+                kata_name: msg[4..link_start].trim().to_owned(),
+                link: msg[link_start..].trim().to_owned(),
+                kyu: None,
+                language: None,
+                paste_id: None,
+                linkless: false,
+            })
+        }
+    }
+
+    #[test]
+    fn paste_id_test() {
+        assert_eq!(
+            paste_id("7 kyu Kata\nhttps://pastebin.com/Ab3dE5gH"),
+            Some("Ab3dE5gH".to_owned())
+        );
+        assert_eq!(
+            paste_id("7 kyu Kata\nhttps://pastebin.com/raw/Ab3dE5gH\nnice one"),
+            Some("Ab3dE5gH".to_owned())
+        );
+        assert_eq!(paste_id("7 kyu Kata\nhttps://pastebin.com/"), None);
+        assert_eq!(paste_id("7 kyu Kata\nhttps://pastebin.com/raw/"), None);
+        assert_eq!(paste_id("7 kyu Kata\nhttps://pastebin.com/raw"), None);
+        assert_eq!(paste_id("7 kyu Kata\nhttps://pastebin.com/ab-cd"), None);
+        assert_eq!(paste_id("7 kyu Kata\nhttps://pastebin.com/u/someone"), None);
+    }
+
+    #[test]
+    fn detectors_are_interchangeable() {
+        let codewars = "7 kyu Robinson Crusoe\nhttps://pastebin.com/abc";
+        let aoc = "aoc 2019 day 1 https://adventofcode.com/2019/day/1";
+        let detectors: Vec<Box<dyn SolutionDetector>> =
+            vec![Box::new(Codewars), Box::new(AdventOfCode)];
+
+        let found: Vec<_> = detectors
+            .iter()
+            .map(|d| {
+                [codewars, aoc]
+                    .iter()
+                    .filter_map(|msg| d.parse_solution(msg))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                vec![ParsedSolution {
+                    kata_name: "7 kyu Robinson Crusoe".to_owned(),
+                    link: "https://pastebin.com/abc".to_owned(),
+                    kyu: Some(7),
+                    language: None,
+                    paste_id: Some("abc".to_owned()),
+                    linkless: false,
+                }],
+                vec![ParsedSolution {
+                    kata_name: "2019 day 1".to_owned(),
+                    link: "https://adventofcode.com/2019/day/1".to_owned(),
+                    kyu: None,
+                    language: None,
+                    paste_id: None,
+                    linkless: false,
+                }],
+            ]
+        );
+        assert!(!detectors[1].is_solution(codewars));
+    }
+
+    #[test]
+    fn language_test() {
+        assert_eq!(
+            language("7 Go Left\nhttps://pastebin.com/abc rs"),
+            Some("rust".to_owned())
+        );
+        assert_eq!(
+            language("7 #Python Go Left\nhttps://pastebin.com/abc"),
+            Some("python".to_owned())
+        );
+        assert_eq!(
+            language("7 Go Left\nhttps://pastebin.com/abc\nC#, finally"),
+            Some("csharp".to_owned())
+        );
+        assert_eq!(
+            language("7 Go Left\nhttps://pastebin.com/abc\nlet's go"),
+            None
+        );
+        assert_eq!(
+            language("7 Go Left\nhttps://pastebin.com/abc\nin #c#"),
+            Some("csharp".to_owned())
+        );
+        assert_eq!(
+            language("7 Go Left\nhttps://pastebin.com/abc\nnice one"),
+            None
+        );
+        assert_eq!(normalize_language(" C++ "), Some("cpp"));
+        assert_eq!(normalize_language("brainfuck"), None);
+    }
+
     #[test]
     fn kata_name_link_test1() {
         let message = "7
@@ -43,10 +345,10 @@ https://pastebin.com/nRkGjfp5";
 
         assert_eq!(
             kata_name_link(message),
-            (
+            Some((
                 "7 Functions of Integers on Cartesian Plane".to_owned(),
                 "https://pastebin.com/nRkGjfp5".to_owned()
-            )
+            ))
         )
     }
 
@@ -60,10 +362,10 @@ https://pastebin.com/fZHdUbhT";
 
         assert_eq!(
             kata_name_link(message),
-            (
+            Some((
                 "7 Robinson Crusoe".to_owned(),
                 "https://pastebin.com/fZHdUbhT".to_owned()
-            )
+            ))
         )
     }
 
@@ -77,10 +379,10 @@ https://pastebin.com/8hPWe1L6";
 
         assert_eq!(
             kata_name_link(message),
-            (
+            Some((
                 "6 Replace With Alphabet Position".to_owned(),
                 "https://pastebin.com/8hPWe1L6".to_owned()
-            )
+            ))
         )
     }
 
@@ -94,10 +396,50 @@ https://pastebin.com/grekUgAs";
 
         assert_eq!(
             kata_name_link(message),
-            (
+            Some((
                 "6 Create Phone Number".to_owned(),
                 "https://pastebin.com/grekUgAs".to_owned()
-            )
+            ))
         )
     }
+
+    #[test]
+    fn kata_name_link_not_a_solution() {
+        assert_eq!(
+            kata_name_link("just chatting https://pastebin.com/grekUgAs"),
+            None
+        );
+        assert_eq!(kata_name_link(""), None);
+    }
+
+    #[test]
+    fn normalize_kata_name_test() {
+        assert_eq!(normalize_kata_name("Foo Bar"), "foo bar");
+        assert_eq!(normalize_kata_name("Foo  Bar."), "foo bar");
+        assert_eq!(normalize_kata_name("foo bar"), "foo bar");
+        assert_eq!(normalize_kata_name(" Foo\tBar!? "), "foo bar");
+        assert_eq!(normalize_kata_name("Don\u{2019}t Stop"), "don't stop");
+        assert_eq!(normalize_kata_name("Sum (of digits)"), "sum (of digits)");
+    }
+
+    #[test]
+    fn kata_slug_test() {
+        assert_eq!(kata_slug("Robinson Crusoe"), "robinson-crusoe");
+        assert_eq!(kata_slug("robinson-crusoe"), "robinson-crusoe");
+        assert_eq!(kata_slug(" Don\u{2019}t  Stop!? "), "dont-stop");
+        assert_eq!(kata_slug("Sum (of digits)"), "sum-of-digits");
+    }
+
+    #[test]
+    fn claimed_kyu_test() {
+        assert_eq!(claimed_kyu("7 Robinson Crusoe"), Some(7));
+        assert_eq!(claimed_kyu("6kyu Create Phone Number"), Some(6));
+        assert_eq!(claimed_kyu("9 Nonexistent"), None);
+        assert_eq!(claimed_kyu("Robinson Crusoe"), None);
+        assert_eq!(
+            strip_kyu("6 kyu Create Phone Number"),
+            "Create Phone Number"
+        );
+        assert_eq!(strip_kyu("7 Robinson Crusoe"), "Robinson Crusoe");
+    }
 }
@@ -0,0 +1,1432 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    En,
+    Ru,
+}
+
+impl Lang {
+    /// Accepts both bare codes and telegram's `language_code` like "en-US"
+    pub fn from_code(code: &str) -> Option<Lang> {
+        match code.split('-').next()?.to_lowercase().as_str() {
+            "en" => Some(Lang::En),
+            "ru" => Some(Lang::Ru),
+            _ => None,
+        }
+    }
+}
+
+/// Every user-facing reply, both languages must cover all of them
+pub enum Key<'a> {
+    UserNotFoundInApi(&'a str),
+    StatsError(String),
+    RemoveFailed(&'a str),
+    Removed(&'a str),
+    AddFailed(&'a str, &'a str),
+    Added(&'a str, &'a str),
+    AlreadyRegistered(&'a str, &'a str),
+    CodewarsNameUpdated {
+        name: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
+    NoCodewarsName(&'a str),
+    CodewarsNameSpaces(&'a str, &'a str),
+    CodewarsNameTooLong(&'a str, usize),
+    CodewarsNameInvalid(&'a str),
+    UserDataError,
+    DataError,
+    Cleared,
+    ClearFailed,
+    NoSolvedKatas,
+    ShowSolvedUsage,
+    SolvedKatas(String),
+    Streak {
+        days: usize,
+        start: String,
+        end: String,
+        current: usize,
+    },
+    NoTimestamped,
+    SkippedUntimestamped(usize),
+    NotRegistered(&'a str),
+    NotRegisteredSelf,
+    TimezoneSet(&'a str),
+    TimezoneFailed,
+    UnknownTimezone(&'a str),
+    NoTimezone,
+    Progress {
+        this_week: i64,
+        last_week: i64,
+    },
+    WhoAmI {
+        codewars_name: &'a str,
+        username: Option<&'a str>,
+        firstname: &'a str,
+        solved: Option<usize>,
+    },
+    Count {
+        total: usize,
+        distinct: usize,
+        own: usize,
+        /// The caller's place out of the solvers, `None` before their first solution
+        rank: Option<(usize, usize)>,
+    },
+    CountFailed,
+    StatsCaption {
+        solutions: usize,
+        distinct: usize,
+    },
+    NoKyuMismatches,
+    KyuMismatches(String),
+    KyuMismatch {
+        name: &'a str,
+        claimed: u8,
+        actual: u8,
+    },
+    KyuCheckError(String),
+    AdminsOnly,
+    MergeUsage,
+    MergeSelf,
+    Merged {
+        from: &'a str,
+        into: &'a str,
+        total: usize,
+    },
+    MergeFailed,
+    SolvedBy(&'a str),
+    LangSet,
+    LangFailed,
+    UnknownLang(&'a str),
+    GoalSet(usize),
+    GoalUsage,
+    GoalFailed,
+    NoGoal,
+    GoalProgress {
+        goal: usize,
+        solved: usize,
+    },
+    GoalReached {
+        name: &'a str,
+        goal: usize,
+    },
+    RandomKata {
+        name: &'a str,
+        link: &'a str,
+        solvers: &'a str,
+    },
+    SolvedAll,
+    MyDataSent,
+    MyDataFailed,
+    Hardest {
+        kyu: u8,
+        katas: &'a str,
+        more: usize,
+    },
+    Easiest {
+        kyu: u8,
+        katas: &'a str,
+        more: usize,
+    },
+    NoKnownKyu,
+    TopKyuUsage,
+    TopKyu {
+        kyu: u8,
+        board: &'a str,
+    },
+    NoneAtKyu(u8),
+    ForwardsSet {
+        skip: bool,
+    },
+    ForwardsFailed,
+    ForwardsUsage,
+    SolvedByUsage,
+    NobodySolved(&'a str),
+    FirstSolveBoard(&'a str),
+    NoFirstSolves,
+    DeleteKataUsage,
+    KataDeleted {
+        name: &'a str,
+        removed: usize,
+    },
+    KataNotFound(&'a str),
+    DeleteKataFailed,
+    CompareUsage,
+    Reparsed(usize),
+    ReparseFailed,
+    Recomputed {
+        katas: usize,
+        counters: usize,
+        message_count: bool,
+    },
+    RecomputeFailed,
+    BurstsHeader {
+        katas: usize,
+        minutes: i64,
+    },
+    Burst {
+        name: &'a str,
+        katas: usize,
+        start: String,
+        end: String,
+    },
+    NoBursts {
+        katas: usize,
+        minutes: i64,
+    },
+    BurstsSet {
+        katas: usize,
+        minutes: i64,
+    },
+    BurstsUsage,
+    BurstsFailed,
+    Registered,
+    AcknowledgeSet(&'a str),
+    AcknowledgeUsage,
+    AcknowledgeFailed,
+    UnknownCommand(&'a str),
+    Leaderboard(&'a str),
+    NoLeaderboard,
+    LeaderboardUsage,
+    Rank {
+        name: &'a str,
+        position: usize,
+        total: usize,
+    },
+    RankPercentile(usize),
+    AnonymizeSet(bool),
+    AnonymizeUsage,
+    /// `None` follows the chat
+    AnonymizeMeSet(Option<bool>),
+    AnonymizeMeUsage,
+    AnonymizeFailed,
+    KataRecorded(&'a str),
+    KataUsage,
+    KataFailed,
+    LinklessDisabled,
+    LinklessSet(bool),
+    LinklessUsage,
+    LinklessFailed,
+    Badges(&'a str),
+    NoBadges,
+    BadgesEarned {
+        name: &'a str,
+        badges: &'a str,
+    },
+    Pruned(usize),
+    DefaultLanguage(Option<&'a str>),
+    SeasonLeaderboard {
+        since: &'a str,
+        board: &'a str,
+    },
+    NoSeason,
+    LoneSolves(&'a str),
+    NoLoneSolves,
+    SeasonStarted,
+    SeasonFailed,
+    DefaultLanguageSet(&'a str),
+    DefaultLanguageFailed,
+    UnknownSolutionLanguage(&'a str),
+    PruneUsage,
+    PruneFailed,
+    Diff {
+        days: i64,
+        distinct: (usize, usize),
+        active: (usize, usize),
+        users: &'a str,
+    },
+    DiffUsage,
+    DidYouMean(&'a str),
+    ExportUsage,
+    Retention {
+        days: Option<u32>,
+        untimestamped: bool,
+    },
+    RetentionUsage,
+    RetentionFailed,
+    Summary {
+        users: usize,
+        distinct: usize,
+        solutions: usize,
+        top: &'a str,
+        hardest: Option<(u8, &'a str)>,
+        language: Option<(&'a str, usize)>,
+    },
+    Nudge(i64),
+    NobodyToNudge(i64),
+    NudgeUsage,
+    NudgeMeSet(bool),
+    NudgeMeUsage,
+    NudgeMeFailed,
+    FormattingSet(bool),
+    FormattingUsage,
+    FormattingFailed,
+    Restored {
+        users: usize,
+        messages: usize,
+    },
+    RestoreUsage,
+    RestoreInvalid(&'a str),
+    RestoreFailed,
+    TopKatas {
+        list: &'a str,
+        more: usize,
+    },
+    Pace {
+        days: i64,
+        per_week: f64,
+        target: usize,
+        date: &'a str,
+    },
+    NoRecentPace(i64),
+    PaceUsage,
+    RenderFailed,
+    CalendarDay {
+        day: &'a str,
+        solved: usize,
+    },
+    Polyglots(&'a str),
+    NoPolyglots,
+    UnknownLanguages(&'a str),
+    BelowMinKyu {
+        kyu: u8,
+        min_kyu: u8,
+    },
+    MinKyuSet(Option<u8>),
+    MinKyuUsage,
+    MinKyuFailed,
+    UserRemoved {
+        name: &'a str,
+        codewars_name: &'a str,
+        /// Deleted messages, `None` when they were kept
+        messages: Option<usize>,
+    },
+    RemoveUserUsage,
+    ImportStatus {
+        finished: &'a str,
+        files: &'a str,
+    },
+    ImportedFile {
+        name: &'a str,
+        added: usize,
+        duplicates: usize,
+        skipped: usize,
+    },
+    NoImportYet,
+    SafeMode,
+    LanguageLeaderboard {
+        language: &'a str,
+        board: &'a str,
+    },
+    NoLanguageLeaderboard(&'a str),
+    /// A sender without a registration, by telegram id
+    Unregistered(i32),
+    DigestTitle,
+    DigestKyu(u8),
+    /// By `Badge::id`
+    BadgeName(&'a str),
+}
+
+pub fn t(lang: Lang, key: Key) -> String {
+    match lang {
+        Lang::En => en(key),
+        Lang::Ru => ru(key),
+    }
+}
+
+fn trend(delta: i64) -> &'static str {
+    match delta {
+        d if d > 0 => "▲",
+        d if d < 0 => "▼",
+        _ => "=",
+    }
+}
+
+fn and_more_en(more: usize) -> String {
+    match more {
+        0 => String::new(),
+        more => format!("\nand {} more", more),
+    }
+}
+
+fn and_more_ru(more: usize) -> String {
+    match more {
+        0 => String::new(),
+        more => format!("\nи ещё {}", more),
+    }
+}
+
+fn en(key: Key) -> String {
+    use Key::*;
+    match key {
+        UserNotFoundInApi(name) => format!("User not found in Codewars API: {}", name),
+        StatsError(e) => format!("Error while getting stats: {}", e),
+        RemoveFailed(name) => format!("Couldn't remove user {} due to a serialization error", name),
+        Removed(name) => format!("Removed user {} successfully", name),
+        AddFailed(name, codewars) => format!(
+            "Couldn't add user {} with codewars username {} because of a serialization failure",
+            name, codewars
+        ),
+        Added(name, codewars) => format!("Added user {} with codewars username {}", name, codewars),
+        AlreadyRegistered(name, codewars) => format!(
+            "{} is already registered with codewars username {}",
+            name, codewars
+        ),
+        CodewarsNameUpdated { name, from, to } => format!(
+            "Updated the codewars username of {} from {} to {}",
+            name, from, to
+        ),
+        NoCodewarsName(name) => format!(
+            "Couldn't add user {} because codewars username wasn't supplied, expected /addme <codewars name>",
+            name
+        ),
+        CodewarsNameSpaces(name, codewars) => format!(
+            "Couldn't add user {}: codewars usernames have no spaces, got \"{}\". Expected /addme <codewars name>",
+            name, codewars
+        ),
+        CodewarsNameTooLong(name, max) => format!(
+            "Couldn't add user {}: codewars usernames are at most {} characters long",
+            name, max
+        ),
+        CodewarsNameInvalid(name) => format!(
+            "Couldn't add user {}: codewars usernames only have latin letters, digits, _ and -",
+            name
+        ),
+        UserDataError => "Couldn't get user data due to an internal error".to_owned(),
+        DataError => "Couldn't get data due to an internal error".to_owned(),
+        Cleared => "Cleared all users and solutions for this chat".to_owned(),
+        ClearFailed => "Couldn't clear the chat due to a serialization failure".to_owned(),
+        NoSolvedKatas => "No solved katas".to_owned(),
+        ShowSolvedUsage => "Usage: /showsolved or /showsolved verbose".to_owned(),
+        SolvedKatas(list) => format!("The following katas were solved:\n{}", list),
+        Streak {
+            days,
+            start,
+            end,
+            current,
+        } => format!(
+            "Longest streak: {} days ({} - {})\nCurrent streak: {} days",
+            days, start, end, current
+        ),
+        NoTimestamped => "No timestamped solutions found".to_owned(),
+        SkippedUntimestamped(n) => format!("{} solutions without a timestamp were skipped", n),
+        NotRegistered(name) => format!("User {} is not registered", name),
+        NotRegisteredSelf => "You aren't registered, use /addme <codewars name>".to_owned(),
+        TimezoneSet(tz) => format!("Timezone set to {}", tz),
+        TimezoneFailed => "Couldn't set timezone due to an internal error".to_owned(),
+        UnknownTimezone(tz) => format!(
+            "Unknown timezone {}, expected an IANA name like Europe/Moscow",
+            tz
+        ),
+        NoTimezone => "Couldn't set timezone because it wasn't supplied".to_owned(),
+        Progress {
+            this_week,
+            last_week,
+        } => format!(
+            "This week: {} katas\nLast week: {} katas\n{} {:+}",
+            this_week,
+            last_week,
+            trend(this_week - last_week),
+            this_week - last_week
+        ),
+        WhoAmI {
+            codewars_name,
+            username,
+            firstname,
+            solved,
+        } => format!(
+            "Codewars name: {}\nUsername: {}\nFirst name: {}\nSolved katas: {}",
+            codewars_name,
+            username.unwrap_or("none"),
+            firstname,
+            solved.map_or("unknown".to_owned(), |s| s.to_string())
+        ),
+        Count {
+            total,
+            distinct,
+            own,
+            rank,
+        } => format!(
+            "Solutions posted in the chat: {}\nDistinct katas solved in the chat: {}\nDistinct katas you solved: {}\nYour rank: {}",
+            total,
+            distinct,
+            own,
+            rank.map_or("none yet".to_owned(), |(rank, solvers)| format!(
+                "{} of {}",
+                rank, solvers
+            ))
+        ),
+        CountFailed => "Couldn't count solutions due to an internal error".to_owned(),
+        StatsCaption {
+            solutions,
+            distinct,
+        } => format!(
+            "{} solutions posted, {} distinct katas solved",
+            solutions, distinct
+        ),
+        NoKyuMismatches => "No kyu mismatches found".to_owned(),
+        KyuMismatches(list) => format!("Solutions posted with a wrong kyu:\n{}", list),
+        KyuMismatch {
+            name,
+            claimed,
+            actual,
+        } => format!("  {}: claimed {} kyu, actual {} kyu", name, claimed, actual),
+        KyuCheckError(e) => format!("Error while checking kyu: {}", e),
+        AdminsOnly => "Only chat admins can do this".to_owned(),
+        MergeUsage => "Usage: /merge <user to keep> <duplicate user>".to_owned(),
+        MergeSelf => "Can't merge a user into themselves".to_owned(),
+        Merged { from, into, total } => format!(
+            "Merged {} into {}, {} solutions in total",
+            from, into, total
+        ),
+        MergeFailed => "Couldn't merge users due to an internal error".to_owned(),
+        SolvedBy(solvers) => format!("Solved by: {}", solvers),
+        LangSet => "Language set to English".to_owned(),
+        LangFailed => "Couldn't set language due to an internal error".to_owned(),
+        UnknownLang(lang) => format!("Unknown language {}, expected en or ru", lang),
+        GoalSet(goal) => format!("Your goal is {} katas now", goal),
+        GoalUsage => "Usage: /goal <number of katas>".to_owned(),
+        GoalFailed => "Couldn't set the goal due to an internal error".to_owned(),
+        NoGoal => "You don't have a goal yet, set one with /goal <number of katas>".to_owned(),
+        GoalProgress { goal, solved } => format!(
+            "Goal: {} katas\nSolved: {} ({}%)\nRemaining: {}",
+            goal,
+            solved,
+            solved * 100 / goal,
+            goal.saturating_sub(solved)
+        ),
+        GoalReached { name, goal } => {
+            format!(
+                "Congratulations {}, you reached your goal of {} katas!",
+                name, goal
+            )
+        }
+        RandomKata {
+            name,
+            link,
+            solvers,
+        } => format!(
+            "Try this one:\n{}\n{}\nAlready solved by: {}",
+            name, link, solvers
+        ),
+        SolvedAll => "You've solved them all!".to_owned(),
+        MyDataSent => "Sent your data in a private message".to_owned(),
+        MyDataFailed => {
+            "Couldn't send your data, start a private chat with me and try again".to_owned()
+        }
+        Hardest { kyu, katas, more } => {
+            format!(
+                "Hardest solved: {} kyu\n{}{}",
+                kyu,
+                katas,
+                and_more_en(more)
+            )
+        }
+        Easiest { kyu, katas, more } => {
+            format!(
+                "Easiest solved: {} kyu\n{}{}",
+                kyu,
+                katas,
+                and_more_en(more)
+            )
+        }
+        NoKnownKyu => "No solved katas with a known kyu".to_owned(),
+        TopKyuUsage => "Usage: /topkyu <kyu from 1 to 8>".to_owned(),
+        TopKyu { kyu, board } => format!("Top solvers of {} kyu katas:\n{}", kyu, board),
+        NoneAtKyu(kyu) => format!("Nobody has solved a {} kyu kata yet", kyu),
+        ForwardsSet { skip: true } => "Forwarded solutions will be skipped".to_owned(),
+        ForwardsSet { skip: false } => {
+            "Forwarded solutions will be credited to their author".to_owned()
+        }
+        ForwardsFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        ForwardsUsage => "Usage: /forwards <attribute|skip>".to_owned(),
+        SolvedByUsage => "Usage: /solvedby <kata name>".to_owned(),
+        NobodySolved(name) => format!("Nobody has posted a solution of {}", name),
+        FirstSolveBoard(board) => format!("Katas solved first in the chat:\n{}", board),
+        NoFirstSolves => "No katas solved in this chat yet".to_owned(),
+        DeleteKataUsage => "Usage: /deletekata <kata name>".to_owned(),
+        KataDeleted { name, removed } => format!("Deleted {} solutions of {}", removed, name),
+        KataNotFound(name) => format!("No solutions of {} found", name),
+        DeleteKataFailed => "Couldn't delete the kata due to an internal error".to_owned(),
+        CompareUsage => "Usage: /compare <user> [other user], both must be registered".to_owned(),
+        Reparsed(changed) => format!("Reparsed stored solutions, {} changed", changed),
+        ReparseFailed => "Couldn't reparse solutions due to an internal error".to_owned(),
+        Recomputed {
+            katas,
+            counters,
+            message_count,
+        } => format!(
+            "Rebuilt from stored messages: {} kata entries and {} solved counters fixed, message counter {}",
+            katas,
+            counters,
+            if message_count { "fixed" } else { "was correct" }
+        ),
+        RecomputeFailed => "Couldn't rebuild the indexes due to an internal error".to_owned(),
+        BurstsHeader { katas, minutes } => format!(
+            "At least {} katas within {} minutes, this is a heuristic and not proof of cheating:",
+            katas, minutes
+        ),
+        Burst {
+            name,
+            katas,
+            start,
+            end,
+        } => format!("{}: {} katas from {} to {}", name, katas, start, end),
+        NoBursts { katas, minutes } => format!(
+            "Nobody posted {} katas within {} minutes among timestamped solutions",
+            katas, minutes
+        ),
+        BurstsSet { katas, minutes } => format!(
+            "Bursts are now {} katas within {} minutes",
+            katas, minutes
+        ),
+        BurstsUsage => {
+            "Usage: /bursts to report, /bursts <katas> <minutes> to change the limits".to_owned()
+        }
+        BurstsFailed => "Couldn't change the limits due to an internal error".to_owned(),
+        Registered => "Registered!".to_owned(),
+        AcknowledgeSet(mode) => format!("Solutions are now acknowledged with: {}", mode),
+        AcknowledgeUsage => "Usage: /acknowledge <react|reply|silent>".to_owned(),
+        AcknowledgeFailed => {
+            "Couldn't change acknowledgements due to an internal error".to_owned()
+        }
+        UnknownCommand(name) => format!(
+            "Unknown command {}, send /help to see all of them",
+            name
+        ),
+        Leaderboard(board) => format!("Distinct katas solved:\n{}", board),
+        NoLeaderboard => "Nobody is registered in this chat yet, use /addme".to_owned(),
+        LeaderboardUsage => "Usage: /leaderboard [season] [image|language]".to_owned(),
+        Rank {
+            name,
+            position,
+            total,
+        } => format!("{} is #{} of {} by distinct katas", name, position, total),
+        RankPercentile(percent) => format!("Ahead of {}% of the chat", percent),
+        AnonymizeSet(true) => {
+            "Leaderboards and exports will show pseudonyms instead of names".to_owned()
+        }
+        AnonymizeSet(false) => "Leaderboards and exports will show names".to_owned(),
+        AnonymizeUsage => "Usage: /anonymize <on|off>".to_owned(),
+        AnonymizeMeSet(Some(true)) => "You will be shown by a pseudonym".to_owned(),
+        AnonymizeMeSet(Some(false)) => "You will be shown by your name".to_owned(),
+        AnonymizeMeSet(None) => "You will be shown as the chat setting says".to_owned(),
+        AnonymizeMeUsage => "Usage: /anonymizeme <on|off|chat>".to_owned(),
+        AnonymizeFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        KataRecorded(name) => format!("Recorded {} without a link", name),
+        KataUsage => "Usage: /kata <kyu> <kata name>".to_owned(),
+        KataFailed => "Couldn't record the kata due to an internal error".to_owned(),
+        LinklessDisabled => {
+            "Solutions without a link are off in this chat, turn them on with /linkless on"
+                .to_owned()
+        }
+        LinklessSet(true) => "/kata now records solutions without a link".to_owned(),
+        LinklessSet(false) => "Solutions need a link again".to_owned(),
+        LinklessUsage => "Usage: /linkless <on|off>".to_owned(),
+        LinklessFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        Badges(badges) => format!("Your badges: {}", badges),
+        NoBadges => "No badges yet, see /help badges for how to earn them".to_owned(),
+        BadgesEarned { name, badges } => format!("{} earned a badge: {}!", name, badges),
+        Pruned(count) => format!("Removed {} stored solutions", count),
+        SeasonLeaderboard { since, board } => {
+            format!("Distinct katas solved since {}:\n{}", since, board)
+        }
+        LoneSolves(list) => format!("Katas only one of you solved:\n{}", list),
+        Diff {
+            days,
+            distinct: (distinct, distinct_before),
+            active: (active, active_before),
+            users,
+        } => format!(
+            "Last {} days against the {} days before:\nDistinct katas: {} ({:+})\nActive members: {} ({:+})\n{}",
+            days,
+            days,
+            distinct,
+            distinct as i64 - distinct_before as i64,
+            active,
+            active as i64 - active_before as i64,
+            users
+        ),
+        DiffUsage => "Expected a period: /diff week or /diff month".to_owned(),
+        DidYouMean(names) => format!("Several users match, did you mean {}?", names),
+        ExportUsage => {
+            "Expected a format: /export markdown for the chat, /export markdown me or /export json"
+                .to_owned()
+        }
+        Retention { days: None, .. } => "Messages are kept forever".to_owned(),
+        Retention {
+            days: Some(days),
+            untimestamped: false,
+        } => format!(
+            "Messages older than {} days are deleted, ones without a timestamp are kept",
+            days
+        ),
+        Retention {
+            days: Some(days),
+            untimestamped: true,
+        } => format!(
+            "Messages older than {} days and ones without a timestamp are deleted",
+            days
+        ),
+        RetentionUsage => "Usage: /retention <days> [all] or /retention off".to_owned(),
+        RetentionFailed => "Couldn't change the retention due to an internal error".to_owned(),
+        Nudge(days) => format!(
+            "Nothing solved in the last {} days, the katas miss you! Try /randomkata for a start",
+            days
+        ),
+        NobodyToNudge(days) => format!("Everyone solved something in the last {} days", days),
+        NudgeUsage => "Usage: /nudge [days], 14 days by default".to_owned(),
+        NudgeMeSet(true) => "/nudge may tag you again".to_owned(),
+        NudgeMeSet(false) => "/nudge won't tag you anymore".to_owned(),
+        NudgeMeUsage => "Usage: /nudgeme <on|off>".to_owned(),
+        NudgeMeFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        FormattingSet(true) => "Replies will use bold, links and mentions".to_owned(),
+        FormattingSet(false) => "Replies will be plain text".to_owned(),
+        FormattingUsage => "Usage: /formatting <on|off>".to_owned(),
+        FormattingFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        Restored { users, messages } => format!(
+            "Restored {} users and {} messages, the ones already here were kept",
+            users, messages
+        ),
+        RestoreUsage => {
+            "Send a file from /export json or /mydata with the caption /restore, or reply /restore to it"
+                .to_owned()
+        }
+        RestoreInvalid(error) => format!(
+            "This isn't a file from /export json or /mydata, nothing was restored: {}",
+            error
+        ),
+        RestoreFailed => "Couldn't restore the file due to an internal error".to_owned(),
+        TopKatas { list, more } => format!(
+            "The hardest katas solved here:\n{}{}",
+            list,
+            and_more_en(more)
+        ),
+        Pace {
+            days,
+            per_week,
+            target,
+            date,
+        } => format!(
+            "You solved {:.1} katas a week over the last {} days, at this pace you reach {} katas on {}",
+            per_week, days, target, date
+        ),
+        NoRecentPace(days) => format!(
+            "Not enough recent activity, you solved nothing in the last {} days",
+            days
+        ),
+        PaceUsage => "Usage: /pace [days from 1 to 365]".to_owned(),
+        RenderFailed => "Couldn't render the chart on this server.".to_owned(),
+        CalendarDay { day, solved: 1 } => format!("{}: 1 kata", day),
+        CalendarDay { day, solved } => format!("{}: {} katas", day, solved),
+        Polyglots(board) => format!("Languages solved in:\n{}", board),
+        NoPolyglots => "Nobody has posted a solution with a language yet".to_owned(),
+        BelowMinKyu { kyu, min_kyu } => format!(
+            "Seen, but {} kyu is easier than the {} kyu this chat tracks, so it wasn't recorded",
+            kyu, min_kyu
+        ),
+        MinKyuSet(Some(kyu)) => format!("Only katas of {} kyu or harder are recorded now", kyu),
+        MinKyuSet(None) => "Katas of every kyu are recorded now".to_owned(),
+        MinKyuUsage => "Usage: /minkyu <kyu from 1 to 8> or /minkyu off".to_owned(),
+        MinKyuFailed => "Couldn't change the setting due to an internal error".to_owned(),
+        UserRemoved {
+            name,
+            codewars_name,
+            messages: None,
+        } => format!(
+            "Removed {} ({}), their solutions are kept",
+            name, codewars_name
+        ),
+        UserRemoved {
+            name,
+            codewars_name,
+            messages: Some(messages),
+        } => format!(
+            "Removed {} ({}) and {} of their messages",
+            name, codewars_name, messages
+        ),
+        RemoveUserUsage => "Usage: /removeuser <user> [purge]".to_owned(),
+        ImportStatus { finished, files } => {
+            format!("The last import finished at {}:\n{}", finished, files)
+        }
+        ImportedFile {
+            name,
+            added,
+            duplicates,
+            skipped,
+        } => format!(
+            "{}: {} added, {} duplicates, {} skipped",
+            name, added, duplicates, skipped
+        ),
+        NoImportYet => "No export has been imported yet".to_owned(),
+        SafeMode => "Bot is in read-only maintenance mode".to_owned(),
+        LanguageLeaderboard { language, board } => {
+            format!("Distinct katas solved in {}:\n{}", language, board)
+        }
+        NoLanguageLeaderboard(language) => {
+            format!("Nobody has posted a solution in {} yet", language)
+        }
+        UnknownLanguages(users) => format!(
+            "Many solutions without a language, set one with /language: {}",
+            users
+        ),
+        Summary {
+            users,
+            distinct,
+            solutions,
+            top,
+            hardest,
+            language,
+        } => format!(
+            "Members: {}\nDistinct katas: {}\nSolutions: {}\nTop solvers: {}\nHardest kata: {}\nMost used language: {}",
+            users,
+            distinct,
+            solutions,
+            if top.is_empty() { "nobody yet" } else { top },
+            hardest.map_or("none yet".to_owned(), |(kyu, name)| format!("{} ({} kyu)", name, kyu)),
+            language.map_or("none tagged".to_owned(), |(language, count)| format!(
+                "{} ({} solutions)",
+                language, count
+            )),
+        ),
+        NoLoneSolves => "Every kata here was solved by more than one person".to_owned(),
+        NoSeason => "No season has started, admins start one with /startseason".to_owned(),
+        SeasonStarted => {
+            "A new season starts now, see /leaderboard season. Nothing was deleted".to_owned()
+        }
+        SeasonFailed => "Couldn't start the season due to an internal error".to_owned(),
+        DefaultLanguage(Some(language)) => format!(
+            "Your solutions without a language are counted as {}",
+            language
+        ),
+        DefaultLanguage(None) => {
+            "No default language, set one with /language <language>".to_owned()
+        }
+        DefaultLanguageSet(language) => format!(
+            "Your solutions without a language will be counted as {}",
+            language
+        ),
+        DefaultLanguageFailed => {
+            "Couldn't set the language due to an internal error".to_owned()
+        }
+        UnknownSolutionLanguage(name) => format!("Unknown language {}", name),
+        PruneUsage => "Usage: /prune <message id or link>...".to_owned(),
+        PruneFailed => "Couldn't remove the solutions due to an internal error".to_owned(),
+        Unregistered(id) => format!("Unregistered ({})", id),
+        DigestTitle => "Solved katas".to_owned(),
+        DigestKyu(kyu) => format!("{} kyu", kyu),
+        BadgeName(id) => match id {
+            "century" => "Century",
+            "polyglot" => "Polyglot",
+            "hard_mode" => "Hard Mode",
+            "early_bird" => "Early Bird",
+            _ => id,
+        }
+        .to_owned(),
+    }
+}
+
+fn ru(key: Key) -> String {
+    use Key::*;
+    match key {
+        UserNotFoundInApi(name) => format!("Пользователь не найден в Codewars API: {}", name),
+        StatsError(e) => format!("Ошибка при получении статистики: {}", e),
+        RemoveFailed(name) => format!(
+            "Не удалось удалить пользователя {} из-за ошибки сериализации",
+            name
+        ),
+        Removed(name) => format!("Пользователь {} удалён", name),
+        AddFailed(name, codewars) => format!(
+            "Не удалось добавить пользователя {} с ником codewars {} из-за ошибки сериализации",
+            name, codewars
+        ),
+        Added(name, codewars) => format!(
+            "Пользователь {} добавлен с ником codewars {}",
+            name, codewars
+        ),
+        AlreadyRegistered(name, codewars) => format!(
+            "{} уже зарегистрирован с ником codewars {}",
+            name, codewars
+        ),
+        CodewarsNameUpdated { name, from, to } => format!(
+            "Ник codewars пользователя {} изменён с {} на {}",
+            name, from, to
+        ),
+        NoCodewarsName(name) => format!(
+            "Не удалось добавить пользователя {}: не указан ник codewars, ожидается /addme <ник на codewars>",
+            name
+        ),
+        CodewarsNameSpaces(name, codewars) => format!(
+            "Не удалось добавить пользователя {}: в нике codewars не бывает пробелов, получено \"{}\". Ожидается /addme <ник на codewars>",
+            name, codewars
+        ),
+        CodewarsNameTooLong(name, max) => format!(
+            "Не удалось добавить пользователя {}: ник codewars не длиннее {} символов",
+            name, max
+        ),
+        CodewarsNameInvalid(name) => format!(
+            "Не удалось добавить пользователя {}: в нике codewars только латинские буквы, цифры, _ и -",
+            name
+        ),
+        UserDataError => {
+            "Не удалось получить данные пользователей из-за внутренней ошибки".to_owned()
+        }
+        DataError => "Не удалось получить данные из-за внутренней ошибки".to_owned(),
+        Cleared => "Все пользователи и решения этого чата удалены".to_owned(),
+        ClearFailed => "Не удалось очистить чат из-за ошибки сериализации".to_owned(),
+        NoSolvedKatas => "Решённых кат нет".to_owned(),
+        ShowSolvedUsage => "Использование: /showsolved или /showsolved verbose".to_owned(),
+        SolvedKatas(list) => format!("Решены следующие каты:\n{}", list),
+        Streak {
+            days,
+            start,
+            end,
+            current,
+        } => format!(
+            "Самая длинная серия: {} дн. ({} - {})\nТекущая серия: {} дн.",
+            days, start, end, current
+        ),
+        NoTimestamped => "Решений с датой не найдено".to_owned(),
+        SkippedUntimestamped(n) => format!("Пропущено решений без даты: {}", n),
+        NotRegistered(name) => format!("Пользователь {} не зарегистрирован", name),
+        NotRegisteredSelf => "Вы не зарегистрированы, используйте /addme <ник codewars>".to_owned(),
+        TimezoneSet(tz) => format!("Часовой пояс установлен: {}", tz),
+        TimezoneFailed => "Не удалось установить часовой пояс из-за внутренней ошибки".to_owned(),
+        UnknownTimezone(tz) => format!(
+            "Неизвестный часовой пояс {}, ожидается имя IANA, например Europe/Moscow",
+            tz
+        ),
+        NoTimezone => "Не удалось установить часовой пояс: он не указан".to_owned(),
+        Progress {
+            this_week,
+            last_week,
+        } => format!(
+            "На этой неделе: {} кат\nНа прошлой неделе: {} кат\n{} {:+}",
+            this_week,
+            last_week,
+            trend(this_week - last_week),
+            this_week - last_week
+        ),
+        WhoAmI {
+            codewars_name,
+            username,
+            firstname,
+            solved,
+        } => format!(
+            "Ник codewars: {}\nИмя пользователя: {}\nИмя: {}\nРешено кат: {}",
+            codewars_name,
+            username.unwrap_or("нет"),
+            firstname,
+            solved.map_or("неизвестно".to_owned(), |s| s.to_string())
+        ),
+        Count {
+            total,
+            distinct,
+            own,
+            rank,
+        } => format!(
+            "Отправлено решений в чате: {}\nРешено разных кат в чате: {}\nРешено разных кат вами: {}\nВаше место: {}",
+            total,
+            distinct,
+            own,
+            rank.map_or("пока нет".to_owned(), |(rank, solvers)| format!(
+                "{} из {}",
+                rank, solvers
+            ))
+        ),
+        CountFailed => "Не удалось посчитать решения из-за внутренней ошибки".to_owned(),
+        StatsCaption {
+            solutions,
+            distinct,
+        } => format!(
+            "Отправлено решений: {}, решено разных кат: {}",
+            solutions, distinct
+        ),
+        NoKyuMismatches => "Несовпадений kyu не найдено".to_owned(),
+        KyuMismatches(list) => format!("Решения с неверно указанным kyu:\n{}", list),
+        KyuMismatch {
+            name,
+            claimed,
+            actual,
+        } => format!(
+            "  {}: указано {} kyu, на самом деле {} kyu",
+            name, claimed, actual
+        ),
+        KyuCheckError(e) => format!("Ошибка при проверке kyu: {}", e),
+        AdminsOnly => "Это могут делать только администраторы чата".to_owned(),
+        MergeUsage => "Использование: /merge <основной пользователь> <дубликат>".to_owned(),
+        MergeSelf => "Нельзя объединить пользователя с самим собой".to_owned(),
+        Merged { from, into, total } => {
+            format!("{} объединён с {}, всего решений: {}", from, into, total)
+        }
+        MergeFailed => "Не удалось объединить пользователей из-за внутренней ошибки".to_owned(),
+        SolvedBy(solvers) => format!("Решили: {}", solvers),
+        LangSet => "Язык изменён на русский".to_owned(),
+        LangFailed => "Не удалось изменить язык из-за внутренней ошибки".to_owned(),
+        UnknownLang(lang) => format!("Неизвестный язык {}, ожидается en или ru", lang),
+        GoalSet(goal) => format!("Ваша цель теперь {} кат", goal),
+        GoalUsage => "Использование: /goal <количество кат>".to_owned(),
+        GoalFailed => "Не удалось установить цель из-за внутренней ошибки".to_owned(),
+        NoGoal => "У вас пока нет цели, установите её через /goal <количество кат>".to_owned(),
+        GoalProgress { goal, solved } => format!(
+            "Цель: {} кат\nРешено: {} ({}%)\nОсталось: {}",
+            goal,
+            solved,
+            solved * 100 / goal,
+            goal.saturating_sub(solved)
+        ),
+        GoalReached { name, goal } => {
+            format!("Поздравляем, {}! Вы достигли цели в {} кат!", name, goal)
+        }
+        RandomKata {
+            name,
+            link,
+            solvers,
+        } => format!(
+            "Попробуйте эту:\n{}\n{}\nУже решили: {}",
+            name, link, solvers
+        ),
+        SolvedAll => "Вы решили их все!".to_owned(),
+        MyDataSent => "Ваши данные отправлены в личные сообщения".to_owned(),
+        MyDataFailed => {
+            "Не удалось отправить данные, начните личный чат со мной и попробуйте снова".to_owned()
+        }
+        Hardest { kyu, katas, more } => {
+            format!(
+                "Самая сложная решённая: {} kyu\n{}{}",
+                kyu,
+                katas,
+                and_more_ru(more)
+            )
+        }
+        Easiest { kyu, katas, more } => {
+            format!(
+                "Самая простая решённая: {} kyu\n{}{}",
+                kyu,
+                katas,
+                and_more_ru(more)
+            )
+        }
+        NoKnownKyu => "Нет решённых кат с известным kyu".to_owned(),
+        TopKyuUsage => "Использование: /topkyu <kyu от 1 до 8>".to_owned(),
+        TopKyu { kyu, board } => format!("Лучшие по катам {} kyu:\n{}", kyu, board),
+        NoneAtKyu(kyu) => format!("Каты {} kyu ещё никто не решил", kyu),
+        ForwardsSet { skip: true } => "Пересланные решения будут пропускаться".to_owned(),
+        ForwardsSet { skip: false } => {
+            "Пересланные решения будут засчитываться их автору".to_owned()
+        }
+        ForwardsFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        ForwardsUsage => "Использование: /forwards <attribute|skip>".to_owned(),
+        SolvedByUsage => "Использование: /solvedby <название каты>".to_owned(),
+        NobodySolved(name) => format!("Решений {} ещё никто не присылал", name),
+        FirstSolveBoard(board) => format!("Каты, решённые в чате первыми:\n{}", board),
+        NoFirstSolves => "В этом чате ещё не решено ни одной каты".to_owned(),
+        DeleteKataUsage => "Использование: /deletekata <название каты>".to_owned(),
+        KataDeleted { name, removed } => format!("Удалено решений {}: {}", name, removed),
+        KataNotFound(name) => format!("Решений {} не найдено", name),
+        DeleteKataFailed => "Не удалось удалить кату из-за внутренней ошибки".to_owned(),
+        CompareUsage => {
+            "Использование: /compare <пользователь> [другой пользователь], оба должны быть зарегистрированы"
+                .to_owned()
+        }
+        Reparsed(changed) => format!("Решения разобраны заново, изменилось: {}", changed),
+        ReparseFailed => "Не удалось разобрать решения из-за внутренней ошибки".to_owned(),
+        Recomputed {
+            katas,
+            counters,
+            message_count,
+        } => format!(
+            "Пересчитано по сохранённым сообщениям: исправлено записей о катах: {}, счётчиков решённых: {}, счётчик сообщений {}",
+            katas,
+            counters,
+            if message_count { "исправлен" } else { "был верным" }
+        ),
+        RecomputeFailed => "Не удалось пересчитать индексы из-за внутренней ошибки".to_owned(),
+        BurstsHeader { katas, minutes } => format!(
+            "Не меньше {} кат за {} минут, это эвристика, а не доказательство списывания:",
+            katas, minutes
+        ),
+        Burst {
+            name,
+            katas,
+            start,
+            end,
+        } => format!("{}: {} кат с {} по {}", name, katas, start, end),
+        NoBursts { katas, minutes } => format!(
+            "Среди решений с датой никто не прислал {} кат за {} минут",
+            katas, minutes
+        ),
+        BurstsSet { katas, minutes } => {
+            format!("Теперь всплеск — это {} кат за {} минут", katas, minutes)
+        }
+        BurstsUsage => {
+            "Использование: /bursts для отчёта, /bursts <каты> <минуты> чтобы изменить пороги"
+                .to_owned()
+        }
+        BurstsFailed => "Не удалось изменить пороги из-за внутренней ошибки".to_owned(),
+        Registered => "Записано!".to_owned(),
+        AcknowledgeSet(mode) => format!("Теперь решения подтверждаются так: {}", mode),
+        AcknowledgeUsage => "Использование: /acknowledge <react|reply|silent>".to_owned(),
+        AcknowledgeFailed => {
+            "Не удалось изменить подтверждения из-за внутренней ошибки".to_owned()
+        }
+        UnknownCommand(name) => format!(
+            "Неизвестная команда {}, отправьте /help, чтобы увидеть все",
+            name
+        ),
+        Leaderboard(board) => format!("Решено разных кат:\n{}", board),
+        NoLeaderboard => "В этом чате ещё никто не зарегистрирован, используйте /addme".to_owned(),
+        LeaderboardUsage => "Использование: /leaderboard [season] [image|язык]".to_owned(),
+        Rank {
+            name,
+            position,
+            total,
+        } => format!("{} на {} месте из {} по числу разных кат", name, position, total),
+        RankPercentile(percent) => format!("Впереди {}% чата", percent),
+        AnonymizeSet(true) => "В рейтингах и выгрузках будут псевдонимы вместо имён".to_owned(),
+        AnonymizeSet(false) => "В рейтингах и выгрузках будут имена".to_owned(),
+        AnonymizeUsage => "Использование: /anonymize <on|off>".to_owned(),
+        AnonymizeMeSet(Some(true)) => "Вы будете показаны под псевдонимом".to_owned(),
+        AnonymizeMeSet(Some(false)) => "Вы будете показаны под своим именем".to_owned(),
+        AnonymizeMeSet(None) => "Вы будете показаны по настройке чата".to_owned(),
+        AnonymizeMeUsage => "Использование: /anonymizeme <on|off|chat>".to_owned(),
+        AnonymizeFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        KataRecorded(name) => format!("Записано {} без ссылки", name),
+        KataUsage => "Использование: /kata <кю> <название каты>".to_owned(),
+        KataFailed => "Не удалось записать кату из-за внутренней ошибки".to_owned(),
+        LinklessDisabled => {
+            "Решения без ссылки в этом чате выключены, включите их через /linkless on".to_owned()
+        }
+        LinklessSet(true) => "Теперь /kata записывает решения без ссылки".to_owned(),
+        LinklessSet(false) => "Решениям снова нужна ссылка".to_owned(),
+        LinklessUsage => "Использование: /linkless <on|off>".to_owned(),
+        LinklessFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        Badges(badges) => format!("Ваши значки: {}", badges),
+        NoBadges => "Значков пока нет, как их получить, смотрите в /help badges".to_owned(),
+        BadgesEarned { name, badges } => format!("{} получает значок: {}!", name, badges),
+        Pruned(count) => format!("Удалено сохранённых решений: {}", count),
+        SeasonLeaderboard { since, board } => {
+            format!("Решено разных кат с {}:\n{}", since, board)
+        }
+        LoneSolves(list) => format!("Каты, которые решил только один из вас:\n{}", list),
+        Diff {
+            days,
+            distinct: (distinct, distinct_before),
+            active: (active, active_before),
+            users,
+        } => format!(
+            "Последние {} дней против {} дней до них:\nРазных кат: {} ({:+})\nАктивных участников: {} ({:+})\n{}",
+            days,
+            days,
+            distinct,
+            distinct as i64 - distinct_before as i64,
+            active,
+            active as i64 - active_before as i64,
+            users
+        ),
+        DiffUsage => "Укажите период: /diff week или /diff month".to_owned(),
+        DidYouMean(names) => format!("Подходят несколько пользователей, вы имели в виду {}?", names),
+        ExportUsage => {
+            "Укажите формат: /export markdown для чата, /export markdown me или /export json"
+                .to_owned()
+        }
+        Retention { days: None, .. } => "Сообщения хранятся бессрочно".to_owned(),
+        Retention {
+            days: Some(days),
+            untimestamped: false,
+        } => format!(
+            "Сообщения старше {} дней удаляются, сообщения без даты сохраняются",
+            days
+        ),
+        Retention {
+            days: Some(days),
+            untimestamped: true,
+        } => format!("Сообщения старше {} дней и сообщения без даты удаляются", days),
+        RetentionUsage => "Использование: /retention <дни> [all] или /retention off".to_owned(),
+        RetentionFailed => "Не удалось изменить срок хранения из-за внутренней ошибки".to_owned(),
+        Nudge(days) => format!(
+            "Ничего не решено за последние {} дней, каты скучают! Для начала попробуйте /randomkata",
+            days
+        ),
+        NobodyToNudge(days) => format!("Все что-нибудь решили за последние {} дней", days),
+        NudgeUsage => "Использование: /nudge [дни], по умолчанию 14 дней".to_owned(),
+        NudgeMeSet(true) => "/nudge снова может вас отмечать".to_owned(),
+        NudgeMeSet(false) => "/nudge больше не будет вас отмечать".to_owned(),
+        NudgeMeUsage => "Использование: /nudgeme <on|off>".to_owned(),
+        NudgeMeFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        FormattingSet(true) => "В ответах будут жирный шрифт, ссылки и упоминания".to_owned(),
+        FormattingSet(false) => "Ответы будут простым текстом".to_owned(),
+        FormattingUsage => "Использование: /formatting <on|off>".to_owned(),
+        FormattingFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        Restored { users, messages } => format!(
+            "Восстановлено пользователей: {}, сообщений: {}, уже имеющиеся оставлены",
+            users, messages
+        ),
+        RestoreUsage => {
+            "Отправьте файл из /export json или /mydata с подписью /restore или ответьте на него /restore"
+                .to_owned()
+        }
+        RestoreInvalid(error) => format!(
+            "Это не файл из /export json или /mydata, ничего не восстановлено: {}",
+            error
+        ),
+        RestoreFailed => "Не удалось восстановить файл из-за внутренней ошибки".to_owned(),
+        TopKatas { list, more } => format!(
+            "Самые сложные каты, решённые здесь:\n{}{}",
+            list,
+            and_more_ru(more)
+        ),
+        Pace {
+            days,
+            per_week,
+            target,
+            date,
+        } => format!(
+            "За последние {} дней вы решали {:.1} кат в неделю, в таком темпе {} кат будет {}",
+            days, per_week, target, date
+        ),
+        NoRecentPace(days) => format!(
+            "Мало недавней активности, за последние {} дней ничего не решено",
+            days
+        ),
+        PaceUsage => "Использование: /pace [дней от 1 до 365]".to_owned(),
+        RenderFailed => "Не удалось нарисовать график на этом сервере.".to_owned(),
+        CalendarDay { day, solved } => format!("{}: решено {}", day, solved),
+        Polyglots(board) => format!("Языки решений:\n{}", board),
+        NoPolyglots => "Пока никто не отправил решение с языком".to_owned(),
+        BelowMinKyu { kyu, min_kyu } => format!(
+            "Принято к сведению, но {} kyu проще {} kyu, которые учитывает этот чат, поэтому решение не записано",
+            kyu, min_kyu
+        ),
+        MinKyuSet(Some(kyu)) => format!("Теперь записываются только каты {} kyu и сложнее", kyu),
+        MinKyuSet(None) => "Теперь записываются каты любого kyu".to_owned(),
+        MinKyuUsage => "Использование: /minkyu <kyu от 1 до 8> или /minkyu off".to_owned(),
+        MinKyuFailed => "Не удалось изменить настройку из-за внутренней ошибки".to_owned(),
+        UserRemoved {
+            name,
+            codewars_name,
+            messages: None,
+        } => format!(
+            "Пользователь {} ({}) удалён, его решения сохранены",
+            name, codewars_name
+        ),
+        UserRemoved {
+            name,
+            codewars_name,
+            messages: Some(messages),
+        } => format!(
+            "Пользователь {} ({}) удалён вместе с сообщениями: {}",
+            name, codewars_name, messages
+        ),
+        RemoveUserUsage => "Использование: /removeuser <пользователь> [purge]".to_owned(),
+        ImportStatus { finished, files } => {
+            format!("Последний импорт завершён {}:\n{}", finished, files)
+        }
+        ImportedFile {
+            name,
+            added,
+            duplicates,
+            skipped,
+        } => format!(
+            "{}: добавлено {}, повторов {}, пропущено {}",
+            name, added, duplicates, skipped
+        ),
+        NoImportYet => "Экспорты ещё не импортировались".to_owned(),
+        SafeMode => "Бот в режиме обслуживания, только чтение".to_owned(),
+        LanguageLeaderboard { language, board } => {
+            format!("Решено разных кат на {}:\n{}", language, board)
+        }
+        NoLanguageLeaderboard(language) => {
+            format!("Решений на {} ещё никто не присылал", language)
+        }
+        UnknownLanguages(users) => format!(
+            "Много решений без языка, укажите его через /language: {}",
+            users
+        ),
+        Summary {
+            users,
+            distinct,
+            solutions,
+            top,
+            hardest,
+            language,
+        } => format!(
+            "Участников: {}\nРазных кат: {}\nРешений: {}\nЛучшие: {}\nСамая сложная ката: {}\nЧаще всего язык: {}",
+            users,
+            distinct,
+            solutions,
+            if top.is_empty() { "пока никого" } else { top },
+            hardest.map_or("пока нет".to_owned(), |(kyu, name)| format!("{} ({} кю)", name, kyu)),
+            language.map_or("не указан".to_owned(), |(language, count)| format!(
+                "{} (решений: {})",
+                language, count
+            )),
+        ),
+        NoLoneSolves => "Каждую кату здесь решили несколько человек".to_owned(),
+        NoSeason => "Сезон не начат, админы начинают его через /startseason".to_owned(),
+        SeasonStarted => {
+            "Новый сезон начинается сейчас, смотрите /leaderboard season. Ничего не удалено"
+                .to_owned()
+        }
+        SeasonFailed => "Не удалось начать сезон из-за внутренней ошибки".to_owned(),
+        DefaultLanguage(Some(language)) => {
+            format!("Ваши решения без языка считаются на {}", language)
+        }
+        DefaultLanguage(None) => {
+            "Язык по умолчанию не задан, задайте его через /language <язык>".to_owned()
+        }
+        DefaultLanguageSet(language) => {
+            format!("Ваши решения без языка будут считаться на {}", language)
+        }
+        DefaultLanguageFailed => {
+            "Не удалось задать язык из-за внутренней ошибки".to_owned()
+        }
+        UnknownSolutionLanguage(name) => format!("Неизвестный язык {}", name),
+        PruneUsage => "Использование: /prune <id или ссылка на сообщение>...".to_owned(),
+        PruneFailed => "Не удалось удалить решения из-за внутренней ошибки".to_owned(),
+        Unregistered(id) => format!("Незарегистрированный ({})", id),
+        DigestTitle => "Решённые каты".to_owned(),
+        DigestKyu(kyu) => format!("{} кю", kyu),
+        BadgeName(id) => match id {
+            "century" => "Сотня",
+            "polyglot" => "Полиглот",
+            "hard_mode" => "Хардкор",
+            "early_bird" => "Ранняя пташка",
+            _ => id,
+        }
+        .to_owned(),
+    }
+}
+
+/// Extended `/help <command>` text, `None` for an unknown command
+pub fn command_help(lang: Lang, command: &str) -> Option<&'static str> {
+    match lang {
+        Lang::En => help_en(command),
+        Lang::Ru => help_ru(command),
+    }
+}
+
+fn help_en(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "help" => "/help [command]\nWithout an argument lists every command, with one explains it.\nExample: /help goal",
+        "addme" => "/addme <codewars name>\nRegisters you in this chat under your Codewars name, running it again replaces the name.\nExample: /addme john_doe",
+        "deleteme" => "/deleteme\nRemoves your registration from this chat, your solutions stay.",
+        "clear" => "/clear\nRemoves every registered user and every stored solution of this chat.",
+        "showstats" => "/showstats [season] [user]\nCharts solutions of every registered user of the chat, or of one user given a Codewars name, a @username or a telegram id. With season only solutions since /startseason count.\nExample: /showstats season john_doe",
+        "showsolved" => "/showsolved [verbose]\nLists the distinct katas solved in the chat with links to the solutions. With verbose each kata names who solved it first.\nExample: /showsolved verbose",
+        "showhonor" => "/showhonor\nCharts Codewars honor of every registered user.",
+        "streak" => "/streak [codewars name]\nYour or a user's longest and current run of days with a solution, days follow the chat timezone.\nExample: /streak john_doe",
+        "settimezone" => "/settimezone <IANA name>\nSets the timezone days are counted in, UTC until set.\nExample: /settimezone Europe/Moscow",
+        "removeuser" => "/removeuser <user> [purge]\nAdmins only. Removes another member's registration, a user is a telegram id, a @username or a Codewars name. Their solutions stay unless purge is added.\nExample: /removeuser @johnny purge",
+        "merge" => "/merge <user> <other user>\nAdmins only. Moves the solutions of the second user to the first and removes the second. Users are a telegram id, a @username or a Codewars name.\nExample: /merge john_doe @johnny",
+        "kyucheck" => "/kyucheck\nLists solutions whose kyu differs from the one on Codewars.",
+        "count" => "/count\nNumber of stored solutions and distinct katas in the chat, reposts of a kata count as solutions only. Also your own distinct katas and your rank among the solvers.",
+        "whoami" => "/whoami\nShows the Codewars name you are registered under.",
+        "progress" => "/progress\nCompares katas you solved in the last 7 days with the 7 days before.",
+        "calendar" => "/calendar\nYour solutions of each of the last 14 days in the chat's timezone as text, days without any included. Solutions without a timestamp are left out.\nExample: /calendar",
+        "setlang" => "/setlang <en|ru>\nSets the language of the bot in this chat.\nExample: /setlang ru",
+        "goal" => "/goal [number]\nSets a goal of distinct katas, without a number shows how close you are.\nExample: /goal 100",
+        "randomkata" => "/randomkata\nSuggests a kata someone in the chat solved and you didn't.",
+        "mydata" => "/mydata\nSends everything stored about you as a file in a private message, start a chat with the bot first.",
+        "hardest" => "/hardest [user]\nYour or a user's solved katas of the best kyu.\nExample: /hardest john_doe",
+        "easiest" => "/easiest [user]\nYour or a user's solved katas of the lowest kyu.\nExample: /easiest john_doe",
+        "topkyu" => "/topkyu <1-8>\nRanks users by distinct katas solved at the kyu.\nExample: /topkyu 5",
+        "forwards" => "/forwards <attribute|skip>\nForwarded solutions are credited to their original author or ignored.\nExample: /forwards skip",
+        "acknowledge" => "/acknowledge <react|reply|silent>\nHow the bot confirms a stored solution: a reaction, a reply or nothing.\nExample: /acknowledge silent",
+        "solvedby" => "/solvedby <kata name>\nLists who posted a solution of the kata, the kyu may be left out.\nExample: /solvedby Create Phone Number",
+        "firstsolveboard" => "/firstsolveboard\nRanks users by katas they were the first in the chat to solve.",
+        "rank" => "/rank [user]\nYour or a user's place among the registered users by distinct katas and the share of the chat they are ahead of, equal counts share a place.\nExample: /rank john_doe",
+        "anonymize" => "/anonymize <on|off>\nAdmins only. Show stable pseudonyms instead of names and Codewars usernames in every stats output and in markdown exports, the counts stay the same.\nExample: /anonymize on",
+        "anonymizeme" => "/anonymizeme <on|off|chat>\nShow yourself by a pseudonym or by name regardless of /anonymize, chat goes back to the chat setting.\nExample: /anonymizeme off",
+        "kata" => "/kata <kyu> <kata name>\nRecord a solved kata without a pastebin link, only when /linkless is on. Such entries are marked as link-less.\nExample: /kata 7 Robinson Crusoe",
+        "linkless" => "/linkless <on|off>\nAdmins only. Allow /kata to record solutions without a link, off by default.\nExample: /linkless on",
+        "lonesolves" => "/lonesolves\nKatas solved by exactly one member with their solver, the hardest first and katas without a kyu last.\nExample: /lonesolves",
+        "minkyu" => "/minkyu <1-8|off>\nAdmins only. Solutions of katas easier than this kyu are answered but not recorded, a silent chat isn't answered, solutions without a kyu always are. Off records every kyu, the default. Solutions stored before aren't touched.\nExample: /minkyu 6",
+        "polyglots" => "/polyglots\nRanks registered users by the distinct languages of their solutions and lists them, equal counts share a place. Solutions without a language don't count, users with many of them are noted below.\nExample: /polyglots",
+        "pace" => "/pace [days]\nYour katas a week over the last days, 30 by default, and the day you reach your /goal at that pace. Without a goal ahead it aims for the next of 10, 25, 50, 100, 250, 500 and 1000 katas. Solutions without a timestamp don't count.\nExample: /pace 14",
+        "topkata" => "/topkata\nThe 10 hardest katas anyone in the chat has solved with everyone who solved them, katas of the same kyu by name. Unlike /hardest it covers the whole chat, katas without a kyu are left out.\nExample: /topkata",
+        "formatting" => "/formatting <on|off>\nLeaderboards bold the first place, kata lists link their solutions and /rank mentions the user. Turn it off for clients that show the markup poorly, on by default.\nExample: /formatting off",
+        "nudge" => "/nudge [days]\nAdmins tag the registered members without a solution in the last days, 14 by default. Members who turned it off with /nudgeme off aren't tagged.\nExample: /nudge 30",
+        "nudgeme" => "/nudgeme <on|off>\nLet /nudge tag you when you haven't solved anything for a while, on by default.\nExample: /nudgeme off",
+        "summary" => "/summary\nThe chat's members, distinct katas, solutions, top 3 solvers, hardest kata and most used language in one message, without drawing a chart.\nExample: /summary",
+        "retention" => "/retention [<days> [all]|off]\nAdmins make the bot delete messages older than the given number of days, checked every hour. Messages without a timestamp are kept unless all is added. Without arguments shows the current setting, off keeps everything again.\nExample: /retention 90",
+        "export" => "/export <markdown [me]|json>\nSends a Markdown study log of the solved katas grouped by kyu with solution links and solvers. The whole chat is for admins only, add me for your own katas. Katas posted without a kyu are left out. json sends the chat's users and messages for /restore, admins only.\nExample: /export markdown me",
+        "importstatus" => "/importstatus\nAdmins only. When the last startup import of this chat finished and how many of its messages each export file added, repeated or skipped.\nExample: /importstatus",
+        "restore" => "/restore\nAdmins only. Send a file from /export json or /mydata with this caption, or reply to it with /restore. Users and messages the chat already has are kept, so restoring twice adds nothing.\nExample: /restore",
+        "diff" => "/diff <week|month>\nCompares the chat's distinct katas and active members in the last 7 or 30 days with the period before, and lists every member's katas with the change. Solutions without a timestamp are skipped.\nExample: /diff week",
+        "startseason" => "/startseason\nStart a new season now, admins only. /leaderboard season and /showstats season only count solutions posted since, all-time scopes and stored data stay.\nExample: /startseason",
+        "language" => "/language [language]\nSet the language your solutions are counted in when they don't name one, without an argument shows the current one. Aliases like rs or py are understood.\nExample: /language rust",
+        "prune" => "/prune <message id or link>...\nRemove the stored copies of deleted solution messages, admins only. Telegram doesn't tell bots about deletions in groups, so deleted solutions keep counting until pruned.\nExample: /prune https://t.me/c/1234567890/42 43",
+        "badges" => "/badges\nYour badges: Century for 100 distinct katas, Polyglot for 5 languages, Hard Mode for a 1 or 2 kyu kata and Early Bird for 10 katas solved first in the chat. New badges are announced once.\nExample: /badges",
+        "leaderboard" => "/leaderboard [season] [image|language]\nRanks registered users by distinct katas solved, with image sends a bar chart of the top 10. With a language like rust or py only solutions in it count. With season only solutions since /startseason count.\nExample: /leaderboard season image",
+        "deletekata" => "/deletekata <kata name>\nAdmins only. Removes every solution of the kata in the chat.\nExample: /deletekata Create Phone Number",
+        "compare" => "/compare <user> [other user]\nCharts two registered users head-to-head, with one user compares them with you.\nExample: /compare john_doe",
+        "reparse" => "/reparse\nAdmins only. Parses the stored solutions again, use it after a parser fix.",
+        "recompute" => "/recompute\nAdmins only. Rebuilds the kata index and counters of the chat from the stored solutions and reports what was fixed.",
+        "bursts" => "/bursts [katas minutes]\nReports users who posted many distinct katas in a short time, a hint and not proof. Admins change the limits with two numbers.\nExample: /bursts 10 5",
+        _ => return None,
+    })
+}
+
+fn help_ru(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "help" => "/help [команда]\nБез аргумента перечисляет все команды, с аргументом объясняет одну.\nПример: /help goal",
+        "addme" => "/addme <имя на codewars>\nРегистрирует вас в этом чате под именем с Codewars, повторный вызов заменяет имя.\nПример: /addme john_doe",
+        "deleteme" => "/deleteme\nУдаляет вашу регистрацию в этом чате, решения остаются.",
+        "clear" => "/clear\nУдаляет всех зарегистрированных пользователей и все сохранённые решения чата.",
+        "showstats" => "/showstats [season] [пользователь]\nГрафик решений всех зарегистрированных пользователей чата или одного пользователя по нику Codewars, @username или telegram id. С season учитываются только решения с /startseason.\nПример: /showstats season john_doe",
+        "showsolved" => "/showsolved [verbose]\nСписок решённых в чате кат со ссылками на решения. С verbose у каждой каты указано, кто решил её первым.\nПример: /showsolved verbose",
+        "showhonor" => "/showhonor\nГрафик honor на Codewars всех зарегистрированных пользователей.",
+        "streak" => "/streak [имя на codewars]\nСамая длинная и текущая серия дней с решениями, ваша или пользователя, дни считаются в часовом поясе чата.\nПример: /streak john_doe",
+        "settimezone" => "/settimezone <имя IANA>\nЗадаёт часовой пояс для подсчёта дней, по умолчанию UTC.\nПример: /settimezone Europe/Moscow",
+        "removeuser" => "/removeuser <пользователь> [purge]\nТолько для админов. Удаляет регистрацию другого участника, пользователь — это telegram id, @username или имя на Codewars. Его решения остаются, если не добавить purge.\nПример: /removeuser @johnny purge",
+        "merge" => "/merge <пользователь> <другой пользователь>\nТолько для админов. Переносит решения второго пользователя первому и удаляет второго. Пользователь — это telegram id, @username или имя на Codewars.\nПример: /merge john_doe @johnny",
+        "kyucheck" => "/kyucheck\nРешения, у которых кю отличается от указанного на Codewars.",
+        "count" => "/count\nЧисло сохранённых решений и разных кат в чате, повторные решения каты считаются только как решения. А также ваше число разных кат и место среди решавших.",
+        "whoami" => "/whoami\nПоказывает имя на Codewars, под которым вы зарегистрированы.",
+        "progress" => "/progress\nСравнивает каты, решённые за последние 7 дней, с предыдущими 7 днями.",
+        "calendar" => "/calendar\nВаши решения за каждый из последних 14 дней по часовому поясу чата текстом, дни без решений тоже. Решения без даты не учитываются.\nПример: /calendar",
+        "setlang" => "/setlang <en|ru>\nЗадаёт язык бота в этом чате.\nПример: /setlang en",
+        "goal" => "/goal [число]\nЗадаёт цель по числу разных кат, без числа показывает прогресс.\nПример: /goal 100",
+        "randomkata" => "/randomkata\nПредлагает кату, которую решил кто-то в чате, но не вы.",
+        "mydata" => "/mydata\nПрисылает в личные сообщения файл со всем, что о вас хранится, сначала начните чат с ботом.",
+        "hardest" => "/hardest [пользователь]\nВаши или пользователя решённые каты самого высокого кю.\nПример: /hardest john_doe",
+        "easiest" => "/easiest [пользователь]\nВаши или пользователя решённые каты самого низкого кю.\nПример: /easiest john_doe",
+        "topkyu" => "/topkyu <1-8>\nРейтинг пользователей по числу решённых кат этого кю.\nПример: /topkyu 5",
+        "forwards" => "/forwards <attribute|skip>\nПересланные решения засчитываются автору оригинала или пропускаются.\nПример: /forwards skip",
+        "acknowledge" => "/acknowledge <react|reply|silent>\nКак бот подтверждает сохранённое решение: реакцией, ответом или никак.\nПример: /acknowledge silent",
+        "solvedby" => "/solvedby <название каты>\nКто присылал решение каты, кю можно не указывать.\nПример: /solvedby Create Phone Number",
+        "firstsolveboard" => "/firstsolveboard\nРейтинг пользователей по катам, которые они решили в чате первыми.",
+        "rank" => "/rank [пользователь]\nВаше или пользователя место среди зарегистрированных по числу разных кат и доля чата, которую он опережает, при равенстве место общее.\nПример: /rank john_doe",
+        "anonymize" => "/anonymize <on|off>\nТолько для админов. Показывать постоянные псевдонимы вместо имён и ников Codewars во всей статистике и в markdown-выгрузках, числа не меняются.\nПример: /anonymize on",
+        "anonymizeme" => "/anonymizeme <on|off|chat>\nПоказывать вас под псевдонимом или по имени независимо от /anonymize, chat возвращает настройку чата.\nПример: /anonymizeme off",
+        "kata" => "/kata <кю> <название каты>\nЗаписать решённую кату без ссылки на pastebin, только если включён /linkless. Такие записи помечаются как решения без ссылки.\nПример: /kata 7 Robinson Crusoe",
+        "linkless" => "/linkless <on|off>\nТолько для админов. Разрешить /kata записывать решения без ссылки, по умолчанию выключено.\nПример: /linkless on",
+        "lonesolves" => "/lonesolves\nКаты, которые решил ровно один участник, и кто их решил, сначала самые сложные, каты без кю в конце.\nПример: /lonesolves",
+        "minkyu" => "/minkyu <1-8|off>\nТолько для админов. На решения кат проще этого kyu бот отвечает, но не записывает их, в тихом чате не отвечает, решения без kyu записываются всегда. Off записывает любой kyu, по умолчанию. Уже сохранённые решения не меняются.\nПример: /minkyu 6",
+        "polyglots" => "/polyglots\nРейтинг зарегистрированных пользователей по числу разных языков их решений со списком языков, при равенстве место общее. Решения без языка не считаются, пользователи с большим их числом указаны ниже.\nПример: /polyglots",
+        "pace" => "/pace [дни]\nСколько кат в неделю вы решали за последние дни, по умолчанию 30, и когда в таком темпе достигнете цели из /goal. Без цели впереди считается следующая отметка из 10, 25, 50, 100, 250, 500 и 1000 кат. Решения без даты не учитываются.\nПример: /pace 14",
+        "topkata" => "/topkata\n10 самых сложных кат, которые решил кто-нибудь в чате, и все, кто их решил, каты одного кю по названию. В отличие от /hardest охватывает весь чат, каты без кю не попадают.\nПример: /topkata",
+        "formatting" => "/formatting <on|off>\nВ рейтингах первое место выделяется жирным, списки кат ссылаются на решения, а /rank упоминает пользователя. Выключите для клиентов, которые плохо это показывают, по умолчанию включено.\nПример: /formatting off",
+        "nudge" => "/nudge [дни]\nАдмины отмечают зарегистрированных участников без решений за последние дни, по умолчанию 14. Участников, выключивших это через /nudgeme off, не отмечают.\nПример: /nudge 30",
+        "nudgeme" => "/nudgeme <on|off>\nРазрешить /nudge отмечать вас, если вы давно ничего не решали, по умолчанию включено.\nПример: /nudgeme off",
+        "summary" => "/summary\nУчастники, разные каты, решения, тройка лучших, самая сложная ката и самый частый язык чата одним сообщением, без графика.\nПример: /summary",
+        "retention" => "/retention [<дни> [all]|off]\nАдмины включают удаление сообщений старше заданного числа дней, проверка раз в час. Сообщения без даты сохраняются, если не добавить all. Без аргументов показывает текущую настройку, off снова хранит всё.\nПример: /retention 90",
+        "export" => "/export <markdown [me]|json>\nПрисылает Markdown-журнал решённых кат по кю со ссылками на решения и решившими. Весь чат только для админов, добавьте me для своих кат. Каты без кю не попадают. json присылает пользователей и сообщения чата для /restore, только для админов.\nПример: /export markdown me",
+        "importstatus" => "/importstatus\nТолько для админов. Когда завершился последний импорт этого чата при запуске и сколько его сообщений каждый файл экспорта добавил, повторил или пропустил.\nПример: /importstatus",
+        "restore" => "/restore\nТолько для админов. Отправьте файл из /export json или /mydata с этой подписью или ответьте на него /restore. Пользователи и сообщения, которые уже есть в чате, остаются, так что повторное восстановление ничего не добавит.\nПример: /restore",
+        "diff" => "/diff <week|month>\nСравнивает разные каты и активных участников чата за последние 7 или 30 дней с предыдущим периодом и показывает каты каждого участника с изменением. Решения без даты пропускаются.\nПример: /diff week",
+        "startseason" => "/startseason\nНачать новый сезон, только для админов. /leaderboard season и /showstats season учитывают только решения с его начала, общий зачёт и данные остаются.\nПример: /startseason",
+        "language" => "/language [язык]\nЗадать язык, на котором считаются ваши решения без указанного языка, без аргумента показывает текущий. Понимает сокращения вроде rs или py.\nПример: /language rust",
+        "prune" => "/prune <id или ссылка на сообщение>...\nУдалить сохранённые копии удалённых сообщений с решениями, только для админов. Telegram не сообщает ботам об удалениях в группах, поэтому удалённые решения учитываются, пока их не удалят здесь.\nПример: /prune https://t.me/c/1234567890/42 43",
+        "badges" => "/badges\nВаши значки: Сотня за 100 разных кат, Полиглот за 5 языков, Хардкор за кату 1 или 2 кю и Ранняя пташка за 10 кат, решённых в чате первым. О новых значках бот сообщает один раз.\nПример: /badges",
+        "leaderboard" => "/leaderboard [season] [image|язык]\nРейтинг зарегистрированных пользователей по числу решённых разных кат, с image присылает график первой десятки. С языком, например rust или py, учитываются только решения на нём. С season учитываются только решения с /startseason.\nПример: /leaderboard season image",
+        "deletekata" => "/deletekata <название каты>\nТолько для админов. Удаляет все решения каты в чате.\nПример: /deletekata Create Phone Number",
+        "compare" => "/compare <пользователь> [другой пользователь]\nГрафик двух зарегистрированных пользователей, с одним аргументом сравнивает его с вами.\nПример: /compare john_doe",
+        "reparse" => "/reparse\nТолько для админов. Заново разбирает сохранённые решения, нужна после исправления парсера.",
+        "recompute" => "/recompute\nТолько для админов. Пересобирает индекс кат и счётчики чата по сохранённым решениям и сообщает, что исправлено.",
+        "bursts" => "/bursts [каты минуты]\nПользователи, приславшие много разных кат за короткое время, это подсказка, а не доказательство. Админы меняют пороги двумя числами.\nПример: /bursts 10 5",
+        _ => return None,
+    })
+}
@@ -0,0 +1,237 @@
+use crate::error::MainError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::prelude::*;
+
+/// Upper bounds in seconds, chart rendering and Codewars requests reach the last ones
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Histogram {
+    /// Observations of each bucket alone, summed up when rendered
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Latency of each handler by name, served in the Prometheus text format at `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    latencies: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn observe(&self, handler: &str, elapsed: Duration) {
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        latencies
+            .entry(handler.to_owned())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Awaits `f` and records how long it took, its output is returned as is
+    pub async fn time<F: Future>(&self, handler: &str, f: F) -> F::Output {
+        let start = Instant::now();
+        let output = f.await;
+        self.observe(handler, start.elapsed());
+        output
+    }
+
+    /// Observations of the handler so far
+    #[cfg(test)]
+    pub fn count(&self, handler: &str) -> u64 {
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        latencies
+            .get(handler)
+            .map_or(0, |histogram| histogram.count)
+    }
+
+    pub fn render(&self) -> String {
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut text = String::new();
+        text.push_str(
+            "# HELP bot_handler_seconds Time spent answering a command or storing a message\n",
+        );
+        text.push_str("# TYPE bot_handler_seconds histogram\n");
+        for (handler, histogram) in latencies.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "bot_handler_seconds_bucket{{handler=\"{}\",le=\"{}\"}} {}",
+                    handler, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                text,
+                "bot_handler_seconds_bucket{{handler=\"{}\",le=\"+Inf\"}} {}",
+                handler, histogram.count
+            );
+            let _ = writeln!(
+                text,
+                "bot_handler_seconds_sum{{handler=\"{}\"}} {}",
+                handler, histogram.sum
+            );
+            let _ = writeln!(
+                text,
+                "bot_handler_seconds_count{{handler=\"{}\"}} {}",
+                handler, histogram.count
+            );
+        }
+        text
+    }
+}
+
+/// Answers `GET /metrics` on `addr`, every other request gets a 404
+pub async fn serve(addr: &str, metrics: std::sync::Arc<Metrics>) -> Result<(), MainError> {
+    // bound by std, the address conversion of tokio's own bind fails on recent compilers
+    let listener = TcpListener::from_std(std::net::TcpListener::bind(addr)?)?;
+    log::info!("metrics served on {}", addr);
+    answer(listener, metrics).await
+}
+
+/// Accepts until the process exits, a failed accept (e.g. out of file descriptors) is logged and skipped
+async fn answer(
+    mut listener: TcpListener,
+    metrics: std::sync::Arc<Metrics>,
+) -> Result<(), MainError> {
+    loop {
+        let mut socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                log::warn!("Couldn't accept a metrics connection: {}", e);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // the request line may come in pieces, it's only routed once the headers end
+            let mut request = [0; 1024];
+            let mut read = 0;
+            while read < request.len() && !request[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+                match socket.read(&mut request[read..]).await {
+                    Ok(0) => break,
+                    Ok(n) => read += n,
+                    Err(e) => {
+                        log::warn!("Couldn't read a metrics request: {}", e);
+                        return;
+                    }
+                }
+            }
+            let response = if request[..read].starts_with(b"GET /metrics") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned()
+            };
+            if let Err(e) = socket.write_all(response.as_bytes()).await {
+                log::warn!("Couldn't answer a metrics request: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn timed_handlers_are_counted_and_keep_their_errors() {
+        let metrics = Metrics::default();
+        let res: Result<(), &str> = metrics.time("showstats", async { Err("failed") }).await;
+        assert_eq!(res, Err("failed"));
+        metrics.time("showstats", async {}).await;
+        metrics.observe("message", Duration::from_millis(300));
+
+        assert_eq!(metrics.count("showstats"), 2);
+        assert_eq!(metrics.count("leaderboard"), 0);
+        let text = metrics.render();
+        assert!(text.contains("bot_handler_seconds_count{handler=\"showstats\"} 2"));
+        assert!(text.contains("bot_handler_seconds_bucket{handler=\"message\",le=\"0.25\"} 0"));
+        assert!(text.contains("bot_handler_seconds_bucket{handler=\"message\",le=\"0.5\"} 1"));
+        assert!(text.contains("bot_handler_seconds_bucket{handler=\"message\",le=\"+Inf\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn requests_in_pieces_are_routed_whole() {
+        let metrics = std::sync::Arc::new(Metrics::default());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(answer(TcpListener::from_std(listener).unwrap(), metrics));
+
+        let response = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Write};
+            let mut socket = std::net::TcpStream::connect(addr).unwrap();
+            socket.set_nodelay(true).unwrap();
+            for piece in &["GE", "T /met", "rics HTTP/1.1\r\n", "\r\n"] {
+                socket.write_all(piece.as_bytes()).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            let mut response = String::new();
+            socket.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        let metrics = std::sync::Arc::new(Metrics::default());
+        metrics.observe("showstats", Duration::from_millis(20));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(answer(TcpListener::from_std(listener).unwrap(), metrics));
+
+        let get = |path: &'static str| {
+            tokio::task::spawn_blocking(move || {
+                use std::io::{Read, Write};
+                let mut socket = std::net::TcpStream::connect(addr).unwrap();
+                let request = format!("GET {} HTTP/1.1\r\n\r\n", path);
+                socket.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                socket.read_to_string(&mut response).unwrap();
+                response
+            })
+        };
+        let response = get("/metrics").await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("bot_handler_seconds_count{handler=\"showstats\"} 1"));
+        assert!(get("/other")
+            .await
+            .unwrap()
+            .starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,7 +22,26 @@ pub struct MessageData {
     #[serde(rename = "type")]
     pub msg_type: String,
     pub text: Option<Text>,
+    /// Some exports keep the caption of a photo or a file apart from `text`
+    #[serde(default)]
+    pub caption: Option<Text>,
     pub from_id: Option<i32>,
+    pub date: Option<String>,
+    pub date_unixtime: Option<String>,
+}
+
+impl MessageData {
+    /// Older exports only have a local `date` without an offset, it is treated as UTC
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        match self.date_unixtime {
+            Some(ref secs) => secs.parse().ok().map(|secs| Utc.timestamp(secs, 0)),
+            None => self
+                .date
+                .as_ref()
+                .and_then(|d| NaiveDateTime::parse_from_str(d, "%Y-%m-%dT%H:%M:%S").ok())
+                .map(|d| DateTime::from_utc(d, Utc)),
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
@@ -29,6 +49,23 @@ pub enum Text {
     String(String),
     Links(Vec<TextData>),
 }
+
+impl Text {
+    /// The text without its formatting, links included as they were written
+    pub fn flatten(&self) -> String {
+        match self {
+            Text::String(s) => s.clone(),
+            Text::Links(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    TextData::String(s) => s.as_str(),
+                    TextData::Typed { text, .. } => text.as_str(),
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum TextData {
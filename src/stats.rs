@@ -1,11 +1,16 @@
-use crate::codewars_requests::{get_completed, get_honor};
-use crate::db::{ChatMessage, CodeUser, UserId};
-use crate::error::MainError;
+use crate::codewars_requests::{get_completed, get_honor, get_kata_kyu};
+use crate::db::{ChatMessage, CodeUser, Persist, UserId};
+use crate::error::{MainError, RenderError};
+use crate::http::Fetch;
+use crate::message_parse::{normalize_kata_name, strip_kyu};
+use crate::messages::{t, Key, Lang};
+use chrono::{DateTime, Utc};
 use futures::future::join_all;
-use plotlib::style::BoxStyle;
+use itertools::Itertools;
+use plotlib::style::{BoxStyle, LineStyle};
 use plotlib::{page, repr, view};
 use resvg::usvg;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter::once;
 use std::path::PathBuf;
 use svg;
@@ -13,13 +18,200 @@ use uuid;
 
 const SIZE_MULT: u32 = 2;
 const SPACE_LEN: u32 = 40;
+const LABEL_LEN: usize = 16;
 
-pub async fn compute_honor(users: HashMap<UserId, CodeUser>) -> Result<PathBuf, MainError> {
+/// The messages earliest posted first, see `ChatMessage::posted_order`
+pub fn in_posted_order<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> Vec<&'a ChatMessage> {
+    messages
+        .into_iter()
+        .sorted_by_key(|msg| msg.posted_order())
+        .collect()
+}
+
+/// Number of different katas among the messages
+pub fn distinct_katas<'a>(messages: impl IntoIterator<Item = &'a ChatMessage>) -> usize {
+    messages
+        .into_iter()
+        .filter_map(|msg| msg.kata_key())
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Distinct katas as (name, link) sorted by name and the number of stored
+/// messages that don't parse as a solution
+pub fn solved_katas(messages: &[ChatMessage]) -> (Vec<(String, String)>, usize) {
+    let parsed: Vec<_> = in_posted_order(messages)
+        .into_iter()
+        .filter_map(|msg| msg.solution.as_ref())
+        .map(|solution| (solution.kata_name.clone(), solution.link.clone()))
+        .collect();
+    let skipped = messages.len() - parsed.len();
+    let katas = parsed
+        .into_iter()
+        .unique_by(|(name, _)| normalize_kata_name(name))
+        .sorted_by_key(|(name, _)| normalize_kata_name(name))
+        .collect();
+    (katas, skipped)
+}
+
+/// Distinct katas grouped by claimed kyu, katas posted without a kyu are skipped
+pub fn solved_by_kyu<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> BTreeMap<u8, Vec<String>> {
+    solutions_by_kyu(messages)
+        .into_iter()
+        .map(|(kyu, katas)| (kyu, katas.into_iter().map(|(name, _)| name).collect()))
+        .collect()
+}
+
+/// `solved_by_kyu` keeping the messages posted for every kata
+pub fn solutions_by_kyu<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> BTreeMap<u8, Vec<(String, Vec<&'a ChatMessage>)>> {
+    let mut katas: HashMap<String, (u8, String, Vec<&ChatMessage>)> = HashMap::new();
+    for msg in in_posted_order(messages) {
+        let solution = match msg.solution {
+            Some(ref solution) => solution,
+            None => continue,
+        };
+        if let Some(kyu) = solution.kyu {
+            let name = strip_kyu(&solution.kata_name);
+            katas
+                .entry(normalize_kata_name(name))
+                .or_insert_with(|| (kyu, name.to_owned(), Vec::new()))
+                .2
+                .push(msg);
+        }
+    }
+    let mut by_kyu: BTreeMap<u8, Vec<(String, Vec<&ChatMessage>)>> = BTreeMap::new();
+    for (kyu, name, messages) in katas.values().cloned() {
+        by_kyu.entry(kyu).or_default().push((name, messages));
+    }
+    for katas in by_kyu.values_mut() {
+        katas.sort_by_key(|(name, _)| normalize_kata_name(name));
+    }
+    by_kyu
+}
+
+/// Study log of the distinct katas by kyu with a solution link and who solved them
+pub fn markdown_digest(
+    lang: Lang,
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+) -> String {
+    let escape = |text: &str| text.replace('[', "\\[").replace(']', "\\]");
+    let mut digest = format!("# {}\n", t(lang, Key::DigestTitle));
+    for (kyu, katas) in solutions_by_kyu(messages) {
+        digest.push_str(&format!("\n## {}\n\n", t(lang, Key::DigestKyu(kyu))));
+        for (name, messages) in katas {
+            let link = messages
+                .iter()
+                .filter_map(|msg| msg.solution.as_ref())
+                .map(|solution| solution.link.as_str())
+                .find(|link| !link.is_empty());
+            let solvers = messages
+                .iter()
+                .map(|msg| msg.from)
+                .unique()
+                .map(|id| user_label(lang, users, id))
+                .join(", ");
+            let kata = match link {
+                Some(link) => format!("[{}]({})", escape(&name), link),
+                None => escape(&name),
+            };
+            digest.push_str(&format!("- {} - {}\n", kata, solvers));
+        }
+    }
+    digest
+}
+
+/// Registered users with at least one distinct kata of the given kyu solved
+pub fn count_at_kyu(
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+    kyu: u8,
+) -> Vec<(String, usize)> {
+    users
+        .values()
+        .filter_map(|user| {
+            let by_kyu = solved_by_kyu(messages.iter().filter(|msg| msg.from == user.telegram_id));
+            by_kyu
+                .get(&kyu)
+                .map(|katas| (user.firstname.clone(), katas.len()))
+        })
+        .collect()
+}
+
+/// Who posted the kata first among its posts, see `ChatMessage::posted_order`
+pub fn first_solver<'a>(posts: impl IntoIterator<Item = &'a ChatMessage>) -> Option<UserId> {
+    posts
+        .into_iter()
+        .min_by_key(|msg| msg.posted_order())
+        .map(|msg| msg.from)
+}
+
+/// How many katas each user posted first, katas are told apart as the kata index does
+pub fn count_first_solves(messages: &[ChatMessage]) -> HashMap<UserId, usize> {
+    let mut seen = HashSet::new();
+    let mut firsts = HashMap::new();
+    for msg in in_posted_order(messages) {
+        if let Some(key) = msg.kata_key() {
+            if seen.insert(key) {
+                *firsts.entry(msg.from).or_insert(0) += 1;
+            }
+        }
+    }
+    firsts
+}
+
+pub struct UnsolvedKata {
+    pub name: String,
+    pub link: String,
+    pub solvers: Vec<UserId>,
+}
+
+/// Katas solved in the chat by someone other than `user` and never by `user`, sorted by name
+pub fn compute_unsolved(messages: &[ChatMessage], user: UserId) -> Vec<UnsolvedKata> {
+    let solved: HashSet<_> = messages
+        .iter()
+        .filter(|msg| msg.from == user)
+        .filter_map(|msg| msg.kata_key())
+        .collect();
+
+    let mut katas: HashMap<String, UnsolvedKata> = HashMap::new();
+    for msg in in_posted_order(messages) {
+        let (key, solution) = match (msg.kata_key(), msg.solution.as_ref()) {
+            (Some(key), Some(solution)) if !solved.contains(&key) => (key, solution),
+            _ => continue,
+        };
+        let kata = katas.entry(key).or_insert_with(|| UnsolvedKata {
+            name: solution.kata_name.clone(),
+            link: solution.link.clone(),
+            solvers: Vec::new(),
+        });
+        if !kata.solvers.contains(&msg.from) {
+            kata.solvers.push(msg.from);
+        }
+    }
+
+    let mut katas: Vec<_> = katas.into_iter().collect();
+    katas.sort_by(|a, b| a.0.cmp(&b.0));
+    katas.into_iter().map(|(_, kata)| kata).collect()
+}
+
+pub async fn compute_honor(
+    lang: Lang,
+    http: &dyn Fetch,
+    users: HashMap<UserId, CodeUser>,
+    shared: &HashMap<UserId, CodeUser>,
+) -> Result<PathBuf, MainError> {
     let honors = join_all(users.values().cloned().map(|u: CodeUser| async {
         let u = u;
         Result::<_, MainError>::Ok((
-            get_honor(u.codewars_name.as_str()).await?,
-            u.firstname.to_owned(),
+            get_honor(http, u.codewars_name.as_str()).await?,
+            user_label(lang, shared, u.telegram_id),
         ))
     }))
     .await
@@ -56,31 +248,181 @@ pub async fn compute_honor(users: HashMap<UserId, CodeUser>) -> Result<PathBuf,
         view = view.add(bar)
     }
 
-    Ok(to_image(
-        page::Page::single(&view).dimensions(600.max(width), 600),
-    ))
+    to_image(page::Page::single(&view).dimensions(600.max(width), 600))
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct UserSummary {
+    /// Solution messages, reposts included
+    pub sent: usize,
+    pub distinct: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StatsSummary {
+    /// Every registered user, even without solutions
+    pub per_user: HashMap<UserId, UserSummary>,
+    /// Senders who posted solutions before or without `/addme`
+    pub unregistered: HashMap<UserId, UserSummary>,
+    /// Distinct katas of the chat by claimed kyu
+    pub per_kyu: BTreeMap<u8, usize>,
+    pub total_sent: usize,
+    pub total_distinct: usize,
+    /// The lowest claimed kyu and the first of its katas by name
+    pub hardest: Option<(u8, String)>,
+    /// Solutions by stored language, untagged ones aren't counted
+    pub per_language: HashMap<String, usize>,
+}
+
+impl StatsSummary {
+    /// Users and unregistered senders by distinct katas, the most first
+    pub fn rows(&self, lang: Lang, users: &HashMap<UserId, CodeUser>) -> Vec<(String, usize)> {
+        self.per_user
+            .iter()
+            .chain(self.unregistered.iter())
+            .map(|(&id, user)| (user_label(lang, users, id), user.distinct))
+            .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .collect()
+    }
+
+    /// Language with the most solutions, ties go to the first name
+    pub fn top_language(&self) -> Option<(&str, usize)> {
+        self.per_language
+            .iter()
+            .map(|(language, &count)| (language.as_str(), count))
+            .min_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)))
+    }
+}
+
+/// Name of a registered user, senders without a `CodeUser` are still told apart
+pub fn user_label(lang: Lang, users: &HashMap<UserId, CodeUser>, id: UserId) -> String {
+    match users.get(&id) {
+        Some(user) => user.firstname.clone(),
+        None => t(lang, Key::Unregistered(id.0)),
+    }
+}
+
+/// Numbers behind the stats, unregistered senders are kept apart from the users
+pub fn aggregate_stats(
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+) -> StatsSummary {
+    let summary = |id: UserId| {
+        let own: Vec<_> = messages.iter().filter(|msg| msg.from == id).collect();
+        UserSummary {
+            sent: own.len(),
+            distinct: distinct_katas(own),
+        }
+    };
+    let per_user = users.keys().map(|&id| (id, summary(id))).collect();
+    let unregistered = messages
+        .iter()
+        .map(|msg| msg.from)
+        .filter(|id| !users.contains_key(id))
+        .unique()
+        .map(|id| (id, summary(id)))
+        .collect();
+    let by_kyu = solved_by_kyu(messages);
+    let mut per_language: HashMap<String, usize> = HashMap::new();
+    for language in messages
+        .iter()
+        .filter_map(|msg| msg.solution.as_ref()?.language.clone())
+    {
+        *per_language.entry(language).or_insert(0) += 1;
+    }
+    StatsSummary {
+        per_user,
+        unregistered,
+        per_kyu: by_kyu
+            .iter()
+            .map(|(&kyu, katas)| (kyu, katas.len()))
+            .collect(),
+        total_sent: messages.len(),
+        total_distinct: distinct_katas(messages),
+        hardest: by_kyu
+            .into_iter()
+            .next()
+            .and_then(|(kyu, katas)| Some((kyu, katas.into_iter().next()?))),
+        per_language,
+    }
+}
+
+/// Languages of one registered user's solutions, `/polyglots`
+#[derive(Debug, PartialEq)]
+pub struct Polyglot {
+    pub user: UserId,
+    /// Sorted, each once
+    pub languages: Vec<String>,
+    pub solutions: usize,
+    /// Solutions without a stored language, the default one is applied when they're posted
+    pub unknown: usize,
+}
+
+/// Registered users with a solution, the most languages first and ties by name
+pub fn compute_polyglots(
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+) -> Vec<Polyglot> {
+    users
+        .keys()
+        .filter_map(|&user| {
+            let solutions: Vec<_> = messages
+                .iter()
+                .filter(|msg| msg.from == user)
+                .filter_map(|msg| msg.solution.as_ref())
+                .collect();
+            if solutions.is_empty() {
+                return None;
+            }
+            Some(Polyglot {
+                user,
+                languages: solutions
+                    .iter()
+                    .filter_map(|solution| solution.language.clone())
+                    .unique()
+                    .sorted()
+                    .collect(),
+                solutions: solutions.len(),
+                unknown: solutions
+                    .iter()
+                    .filter(|solution| solution.language.is_none())
+                    .count(),
+            })
+        })
+        .sorted_by(|a, b| {
+            b.languages
+                .len()
+                .cmp(&a.languages.len())
+                .then_with(|| users[&a.user].firstname.cmp(&users[&b.user].firstname))
+        })
+        .collect()
 }
 
 pub async fn compute_stats(
+    lang: Lang,
+    http: &dyn Fetch,
     users: HashMap<UserId, CodeUser>,
     messages: Vec<ChatMessage>,
 ) -> Result<PathBuf, MainError> {
+    let summary = aggregate_stats(&users, &messages);
     let mut user_stats = Vec::new();
     let mut maxy = 5;
     for user in users.values() {
-        let solved_in_scala: Vec<_> = get_completed(user.codewars_name.as_str())
+        let solved_in_scala: Vec<_> = get_completed(http, user.codewars_name.as_str())
             .await?
             .into_iter()
             .filter(|k| k.completed_languages.contains(&"scala".to_owned()))
             .collect();
-        let sent_to_chat = messages
-            .iter()
-            .filter(|msg| msg.from == user.telegram_id)
-            .count();
+        let sent_to_chat = summary.per_user[&user.telegram_id].sent;
         user_stats.push((user.clone(), solved_in_scala.len(), sent_to_chat));
 
         maxy = maxy.max(solved_in_scala.len().max(sent_to_chat));
     }
+    maxy = summary
+        .unregistered
+        .values()
+        .map(|sender| sender.sent)
+        .fold(maxy, usize::max);
 
     let bars: Vec<repr::BarChart> = user_stats
         .into_iter()
@@ -98,6 +440,17 @@ pub async fn compute_stats(
             ))
         })
         .flatten()
+        .chain(
+            summary
+                .unregistered
+                .iter()
+                .sorted_by_key(|(id, _)| id.0)
+                .map(|(&id, sender)| {
+                    repr::BarChart::new(sender.sent as f64)
+                        .label(format!("{} sent", user_label(lang, &users, id)))
+                        .style(&BoxStyle::new().fill("grey"))
+                }),
+        )
         .collect();
 
     let mut view = view::CategoricalView::new()
@@ -119,34 +472,677 @@ pub async fn compute_stats(
     for bar in bars {
         view = view.add(bar)
     }
-    Ok(to_image(
-        page::Page::single(&view).dimensions(600.max(width), 600),
-    ))
+    to_image(page::Page::single(&view).dimensions(600.max(width), 600))
 }
 
-fn to_image(page: page::Page) -> PathBuf {
+/// Users and unregistered senders by distinct katas, best first and ties by name
+pub fn leaderboard_rows(
+    lang: Lang,
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+) -> Vec<(String, usize)> {
+    aggregate_stats(users, messages).rows(lang, users)
+}
+
+/// `leaderboard_rows` of the solutions in `language`, users without one aren't listed
+pub fn language_leaderboard_rows(
+    lang: Lang,
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+    language: &str,
+) -> Vec<(String, usize)> {
+    let in_language: Vec<_> = messages
+        .iter()
+        .filter(|msg| {
+            msg.solution
+                .as_ref()
+                .and_then(|solution| solution.language.as_deref())
+                == Some(language)
+        })
+        .cloned()
+        .collect();
+    leaderboard_rows(lang, users, &in_language)
+        .into_iter()
+        .filter(|(_, distinct)| *distinct > 0)
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rank {
+    /// Equal counts share a position
+    pub position: usize,
+    pub total: usize,
+    /// Share of the other users with fewer katas, `None` when there is nobody else
+    pub ahead_of_percent: Option<usize>,
+}
+
+/// Where `user` stands among the registered users by distinct katas
+pub fn compute_rank(counts: &HashMap<UserId, usize>, user: UserId) -> Option<Rank> {
+    let own = *counts.get(&user)?;
+    let ahead = counts.values().filter(|&&count| count > own).count();
+    let behind = counts.values().filter(|&&count| count < own).count();
+    let others = counts.len() - 1;
+    Some(Rank {
+        position: ahead + 1,
+        total: counts.len(),
+        ahead_of_percent: if others == 0 {
+            None
+        } else {
+            Some((behind as f64 * 100. / others as f64).round() as usize)
+        },
+    })
+}
+
+/// Long names are cut so they don't run into the bars
+fn truncate_label(name: &str) -> String {
+    if name.chars().count() <= LABEL_LEN {
+        name.to_owned()
+    } else {
+        name.chars().take(LABEL_LEN - 1).chain(once('…')).collect()
+    }
+}
+
+/// Horizontal bars of the top `top_n` solvers, drawn by hand since plotlib's bars are vertical only
+pub fn compute_leaderboard_chart(
+    lang: Lang,
+    users: &HashMap<UserId, CodeUser>,
+    messages: &[ChatMessage],
+    top_n: usize,
+) -> Result<PathBuf, MainError> {
+    use svg::node::element::{Rectangle, Text};
+
+    const WIDTH: usize = 600;
+    const ROW: usize = 40;
+    const LABEL_WIDTH: usize = 175;
+    const COUNT_WIDTH: usize = 50;
+
+    let rows: Vec<_> = leaderboard_rows(lang, users, messages)
+        .into_iter()
+        .take(top_n)
+        .collect();
+    let max = rows
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let height = ROW * rows.len().max(1);
+    let bar_space = WIDTH - LABEL_WIDTH - COUNT_WIDTH;
+
+    let mut document = svg::Document::new()
+        .set("width", WIDTH)
+        .set("height", height)
+        .set("viewBox", (0, 0, WIDTH, height))
+        .add(
+            Rectangle::new()
+                .set("width", WIDTH)
+                .set("height", height)
+                .set("fill", "white"),
+        );
+    for (i, (name, count)) in rows.iter().enumerate() {
+        let y = i * ROW;
+        let bar = bar_space * count / max;
+        document = document
+            .add(
+                Text::new()
+                    .set("x", 5)
+                    .set("y", y + ROW * 2 / 3)
+                    .set("font-size", 16)
+                    .add(svg::node::Text::new(truncate_label(name))),
+            )
+            .add(
+                Rectangle::new()
+                    .set("x", LABEL_WIDTH)
+                    .set("y", y + ROW / 8)
+                    .set("width", bar)
+                    .set("height", ROW * 3 / 4)
+                    .set("fill", "green"),
+            )
+            .add(
+                Text::new()
+                    .set("x", LABEL_WIDTH + bar + 5)
+                    .set("y", y + ROW * 2 / 3)
+                    .set("font-size", 16)
+                    .add(svg::node::Text::new(count.to_string())),
+            );
+    }
+
+    let mut bytes = Vec::new();
+    svg::write(&mut bytes, &document).map_err(|e| RenderError::Chart(e.to_string()))?;
+    render_svg(&bytes)
+}
+
+/// Cumulative distinct katas over days since the first solution of either user,
+/// per-kyu bars when one of them has no timestamped solutions
+pub fn compute_compare_chart(
+    user_a: &CodeUser,
+    user_b: &CodeUser,
+    messages: &[ChatMessage],
+) -> Result<PathBuf, MainError> {
+    const COLOURS: [&str; 2] = ["orange", "green"];
+
+    let users = [user_a, user_b];
+    let timelines: Vec<_> = users
+        .iter()
+        .map(|user| first_solve_dates(messages.iter().filter(|msg| msg.from == user.telegram_id)))
+        .collect();
+
+    if timelines.iter().all(|dates| !dates.is_empty()) {
+        let start = *timelines
+            .iter()
+            .filter_map(|dates| dates.first())
+            .min()
+            .unwrap();
+        let days = |date: &DateTime<Utc>| (*date - start).num_seconds() as f64 / 86400.;
+        let maxx = timelines
+            .iter()
+            .filter_map(|dates| dates.last())
+            .map(days)
+            .fold(1., f64::max);
+        let maxy = timelines.iter().map(Vec::len).max().unwrap_or(1);
+
+        let mut view = view::ContinuousView::new()
+            .x_range(0., maxx)
+            .y_range(0., maxy as f64)
+            .x_label("days")
+            .y_label("katas");
+        for ((user, dates), colour) in users.iter().zip(timelines.iter()).zip(COLOURS.iter()) {
+            let points = once((0., 0.))
+                .chain(
+                    dates
+                        .iter()
+                        .enumerate()
+                        .map(|(i, date)| (days(date), (i + 1) as f64)),
+                )
+                .collect();
+            view = view.add(
+                repr::Plot::new(points)
+                    .legend(user.firstname.clone())
+                    .line_style(LineStyle::new().colour(*colour)),
+            );
+        }
+        return to_image(page::Page::single(&view).dimensions(600, 600));
+    }
+
+    let by_kyu: Vec<_> = users
+        .iter()
+        .map(|user| solved_by_kyu(messages.iter().filter(|msg| msg.from == user.telegram_id)))
+        .collect();
+    let maxy = by_kyu
+        .iter()
+        .flat_map(|kyus| kyus.values().map(Vec::len))
+        .max()
+        .unwrap_or(5);
+    let bars: Vec<_> = (1..=8)
+        .rev()
+        .flat_map(|kyu| {
+            users.iter().zip(by_kyu.iter()).zip(COLOURS.iter()).map(
+                move |((user, kyus), colour)| {
+                    repr::BarChart::new(kyus.get(&kyu).map_or(0, Vec::len) as f64)
+                        .label(format!("{} {}kyu", user.firstname, kyu))
+                        .style(&BoxStyle::new().fill(*colour))
+                },
+            )
+        })
+        .collect();
+
+    let width = bars
+        .iter()
+        .map(|bar| (bar.get_label().chars().count() as u32 + SPACE_LEN) * SIZE_MULT)
+        .sum();
+    let mut view = view::CategoricalView::new()
+        .y_range(0., maxy as f64)
+        .x_label("kyu")
+        .y_label("katas");
+    for bar in bars {
+        view = view.add(bar)
+    }
+    to_image(page::Page::single(&view).dimensions(600.max(width), 600))
+}
+
+/// When each distinct kata was first posted, sorted, untimestamped messages are skipped
+fn first_solve_dates<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+) -> Vec<DateTime<Utc>> {
+    let mut firsts: HashMap<String, DateTime<Utc>> = HashMap::new();
+    for msg in messages {
+        if let (Some(key), Some(date)) = (msg.kata_key(), msg.date) {
+            let first = firsts.entry(key).or_insert(date);
+            *first = (*first).min(date);
+        }
+    }
+    firsts.values().copied().sorted().collect()
+}
+
+pub struct KyuMismatch {
+    pub name: String,
+    pub claimed: u8,
+    pub actual: u8,
+}
+
+/// Katas whose official kyu couldn't be resolved are skipped
+pub async fn compute_kyu_mismatches(
+    http: &dyn Fetch,
+    users: HashMap<UserId, CodeUser>,
+    messages: Vec<ChatMessage>,
+    db: &Persist,
+) -> Result<Vec<(CodeUser, Vec<KyuMismatch>)>, MainError> {
+    let mut result = Vec::new();
+    for user in users.values() {
+        let completed: HashMap<_, _> = get_completed(http, user.codewars_name.as_str())
+            .await?
+            .into_iter()
+            .map(|k| (normalize_kata_name(&k.name), k.id))
+            .collect();
+
+        let mut seen = HashSet::new();
+        let mut mismatches = Vec::new();
+        for msg in in_posted_order(messages.iter().filter(|msg| msg.from == user.telegram_id)) {
+            let solution = match msg.solution {
+                Some(ref solution) => solution,
+                None => continue,
+            };
+            let name = strip_kyu(&solution.kata_name);
+            let key = normalize_kata_name(name);
+            let (claimed, id) = match (solution.kyu, completed.get(&key)) {
+                (Some(claimed), Some(id)) if seen.insert(key.clone()) => (claimed, id),
+                _ => continue,
+            };
+            let actual = match db.get_kata_kyu(id)? {
+                Some(kyu) => kyu,
+                None => match get_kata_kyu(http, id).await? {
+                    Some(kyu) => {
+                        db.set_kata_kyu(id, kyu)?;
+                        kyu
+                    }
+                    None => continue,
+                },
+            };
+            if actual != claimed {
+                mismatches.push(KyuMismatch {
+                    name: name.to_owned(),
+                    claimed,
+                    actual,
+                });
+            }
+        }
+        if !mismatches.is_empty() {
+            result.push((user.clone(), mismatches));
+        }
+    }
+    Ok(result)
+}
+
+fn to_image(page: page::Page) -> Result<PathBuf, MainError> {
+    let chart = |e: &dyn std::fmt::Display| RenderError::Chart(e.to_string());
     let mut bytes = Vec::new();
-    svg::write(&mut bytes, &page.to_svg().unwrap()).unwrap();
+    svg::write(&mut bytes, &page.to_svg().map_err(|e| chart(&e))?).map_err(|e| chart(&e))?;
+    render_svg(&bytes)
+}
+
+/// Fails with `StatsRender` rather than panicking when the host can't draw
+fn render_svg(bytes: &[u8]) -> Result<PathBuf, MainError> {
     let svg = usvg::Tree::from_data(
-        bytes.as_slice(),
+        bytes,
         &usvg::Options {
             font_family: "Liberation Serif".to_string(),
             ..usvg::Options::default()
         },
     )
-    .unwrap();
+    .map_err(|e| RenderError::Svg(e.to_string()))?;
     let mut img = resvg::default_backend()
         .render_to_image(&svg, &resvg::Options::default())
-        .unwrap();
+        .ok_or(RenderError::Backend)?;
     let path = PathBuf::from(format!("tmp/img_{}.png", uuid::Uuid::new_v4()).as_str());
 
     // create dir if doesn't exist
     let mut dir_path = path.clone();
     dir_path.pop();
     if !dir_path.exists() {
-        std::fs::create_dir_all(dir_path).unwrap();
+        std::fs::create_dir_all(dir_path)?;
+    }
+
+    if !img.save_png(path.as_path()) {
+        return Err(RenderError::Save(path).into());
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_parse::{Codewars, SolutionDetector};
+
+    fn message(id: i32, text: &str) -> ChatMessage {
+        ChatMessage {
+            id,
+            text: text.to_owned(),
+            from: UserId(1),
+            kata_id: None,
+            date: None,
+            solution: Codewars.parse_solution(text),
+        }
+    }
+
+    #[test]
+    fn broken_charts_fail_with_the_cause() {
+        match render_svg(b"<svg unclosed") {
+            Err(MainError::StatsRender(RenderError::Svg(cause))) => assert!(!cause.is_empty()),
+            res => panic!("expected a render error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn first_solves_go_to_the_earliest_message() {
+        use chrono::TimeZone;
+
+        let dated = |user, id, name, day: Option<u32>| ChatMessage {
+            date: day.map(|day| Utc.ymd(2020, 4, day).and_hms(12, 0, 0)),
+            ..from(user, id, name)
+        };
+        // live messages have higher ids than imported ones posted later
+        let messages = vec![
+            dated(2, 5, "7 Robinson Crusoe", Some(1)),
+            dated(1, 3, "7 Robinson Crusoe", Some(2)),
+            dated(2, 4, "6 Create Phone Number", None),
+            dated(1, 1, "8 Even or Odd", Some(3)),
+            dated(2, 2, "8 Even or Odd", None),
+            message(6, "not a solution"),
+        ];
+        let firsts = count_first_solves(&messages);
+        assert_eq!(firsts.get(&UserId(1)), Some(&1));
+        assert_eq!(firsts.get(&UserId(2)), Some(&2));
+        assert_eq!(first_solver(&messages[..2]), Some(UserId(2)));
+        assert_eq!(first_solver(&messages[3..5]), Some(UserId(1)));
+        assert_eq!(first_solver(&[]), None);
+    }
+
+    fn from(user: i32, id: i32, name: &str) -> ChatMessage {
+        ChatMessage {
+            from: UserId(user),
+            ..message(id, &format!("{}\nhttps://pastebin.com/p{}", name, id))
+        }
+    }
+
+    fn user(id: i32, name: &str) -> CodeUser {
+        CodeUser {
+            username: None,
+            firstname: name.to_owned(),
+            telegram_id: UserId(id),
+            codewars_name: format!("cw{}", id),
+            default_language: None,
+        }
+    }
+
+    fn users(names: &[(i32, &str)]) -> HashMap<UserId, CodeUser> {
+        names
+            .iter()
+            .map(|&(id, name)| (UserId(id), user(id, name)))
+            .collect()
+    }
+
+    #[test]
+    fn leaderboard_rows_and_labels() {
+        let users = users(&[(1, "Bob"), (2, "Alice"), (3, "Maximilian Alexander")]);
+        let messages = vec![
+            from(1, 1, "7 Robinson Crusoe"),
+            from(1, 2, "7 Robinson Crusoe"),
+            from(2, 3, "7 Robinson Crusoe"),
+            from(3, 4, "6 Create Phone Number"),
+            from(3, 5, "7 Robinson Crusoe"),
+        ];
+        assert_eq!(
+            leaderboard_rows(Lang::En, &users, &messages),
+            vec![
+                ("Maximilian Alexander".to_owned(), 2),
+                ("Alice".to_owned(), 1),
+                ("Bob".to_owned(), 1),
+            ]
+        );
+        assert_eq!(truncate_label("Alice"), "Alice");
+        assert_eq!(truncate_label("Maximilian Alexander"), "Maximilian Alex…");
+        assert_eq!(truncate_label("Maximilian Alexander").chars().count(), 16);
+    }
+
+    #[test]
+    fn language_leaderboards_count_katas_in_that_language() {
+        let users = users(&[(1, "Bob"), (2, "Alice")]);
+        let messages = vec![
+            from(1, 1, "7 Robinson Crusoe #rust"),
+            from(1, 2, "7 Robinson Crusoe #python"),
+            from(1, 3, "6 Create Phone Number #rust"),
+            from(1, 4, "6 Create Phone Number #rust"),
+            from(2, 5, "7 Robinson Crusoe #python"),
+            from(2, 6, "6 Create Phone Number #py"),
+            from(3, 7, "5 Directions Reduction #rust"),
+            from(2, 8, "5 Directions Reduction"),
+        ];
+        assert_eq!(
+            language_leaderboard_rows(Lang::En, &users, &messages, "rust"),
+            vec![("Bob".to_owned(), 2), ("Unregistered (3)".to_owned(), 1)]
+        );
+        assert_eq!(
+            language_leaderboard_rows(Lang::En, &users, &messages, "python"),
+            vec![("Alice".to_owned(), 2), ("Bob".to_owned(), 1)]
+        );
+        assert!(language_leaderboard_rows(Lang::En, &users, &messages, "haskell").is_empty());
     }
 
-    img.save_png(path.as_path());
-    path
+    #[test]
+    fn orphaned_messages_are_labelled_until_addme() {
+        let mut users = HashMap::new();
+        let messages = vec![
+            from(7, 1, "7 Robinson Crusoe"),
+            from(7, 2, "6 Create Phone Number"),
+        ];
+        assert_eq!(
+            leaderboard_rows(Lang::En, &users, &messages),
+            vec![("Unregistered (7)".to_owned(), 2)]
+        );
+
+        users.insert(UserId(7), user(7, "Late"));
+        assert_eq!(
+            leaderboard_rows(Lang::En, &users, &messages),
+            vec![("Late".to_owned(), 2)]
+        );
+        assert!(aggregate_stats(&users, &messages).unregistered.is_empty());
+    }
+
+    #[test]
+    fn rank_percentiles_with_ties() {
+        let counts: HashMap<_, _> = vec![(1, 10), (2, 7), (3, 7), (4, 3), (5, 0)]
+            .into_iter()
+            .map(|(id, count)| (UserId(id), count))
+            .collect();
+        let rank = |id| compute_rank(&counts, UserId(id)).unwrap();
+        assert_eq!(
+            rank(1),
+            Rank {
+                position: 1,
+                total: 5,
+                ahead_of_percent: Some(100),
+            }
+        );
+        assert_eq!(rank(2), rank(3));
+        assert_eq!((rank(2).position, rank(2).ahead_of_percent), (2, Some(50)));
+        assert_eq!((rank(4).position, rank(4).ahead_of_percent), (4, Some(25)));
+        assert_eq!((rank(5).position, rank(5).ahead_of_percent), (5, Some(0)));
+        assert_eq!(compute_rank(&counts, UserId(6)), None);
+
+        let alone: HashMap<_, _> = vec![(UserId(1), 3)].into_iter().collect();
+        assert_eq!(
+            compute_rank(&alone, UserId(1)).unwrap().ahead_of_percent,
+            None
+        );
+    }
+
+    #[test]
+    fn aggregate_stats_summary() {
+        let users = users(&[(1, "user1"), (2, "user2"), (3, "user3")]);
+        let messages = vec![
+            from(1, 1, "7 Robinson Crusoe"),
+            from(1, 2, "7 Robinson Crusoe"),
+            from(1, 3, "6 Create Phone Number"),
+            from(2, 4, "7 robinson crusoe"),
+            from(2, 5, "5 Directions Reduction"),
+            from(4, 6, "8 Even or Odd"),
+        ];
+
+        let summary = aggregate_stats(&users, &messages);
+        let per_user = |id| &summary.per_user[&UserId(id)];
+        assert_eq!(
+            per_user(1),
+            &UserSummary {
+                sent: 3,
+                distinct: 2
+            }
+        );
+        assert_eq!(
+            per_user(2),
+            &UserSummary {
+                sent: 2,
+                distinct: 2
+            }
+        );
+        assert_eq!(per_user(3), &UserSummary::default());
+        assert!(!summary.per_user.contains_key(&UserId(4)));
+        assert_eq!(
+            summary.unregistered.into_iter().collect::<Vec<_>>(),
+            vec![(
+                UserId(4),
+                UserSummary {
+                    sent: 1,
+                    distinct: 1
+                }
+            )]
+        );
+        assert_eq!(
+            summary.per_kyu.into_iter().collect::<Vec<_>>(),
+            vec![(5, 1), (6, 1), (7, 1), (8, 1)]
+        );
+        assert_eq!(summary.total_sent, 6);
+        assert_eq!(summary.total_distinct, 4);
+        assert_eq!(
+            summary.hardest,
+            Some((5, "Directions Reduction".to_owned()))
+        );
+        assert!(summary.per_language.is_empty());
+    }
+
+    #[test]
+    fn top_language_counts_solutions() {
+        let messages = vec![
+            from(1, 1, "7 Robinson Crusoe #rust"),
+            from(2, 2, "7 Robinson Crusoe #scala"),
+            from(1, 3, "6 Create Phone Number #scala"),
+            from(2, 4, "5 Directions Reduction #rs"),
+            from(2, 5, "8 Even or Odd"),
+        ];
+        let summary = aggregate_stats(&HashMap::new(), &messages);
+        assert_eq!(summary.top_language(), Some(("rust", 2)));
+        assert_eq!(summary.per_language.values().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn polyglots_count_distinct_languages() {
+        let users = users(&[(1, "user1"), (2, "user2"), (3, "user3"), (5, "user5")]);
+        let messages = vec![
+            from(2, 1, "7 Robinson Crusoe #rust"),
+            from(2, 2, "6 Create Phone Number #scala"),
+            from(2, 3, "5 Directions Reduction #rs"),
+            from(1, 4, "7 Robinson Crusoe #scala"),
+            from(1, 5, "8 Even or Odd #rust"),
+            from(1, 6, "6 Create Phone Number"),
+            from(3, 7, "8 Even or Odd"),
+            from(4, 8, "8 Even or Odd #kotlin"),
+        ];
+
+        let polyglots = compute_polyglots(&users, &messages);
+        assert_eq!(
+            polyglots
+                .iter()
+                .map(|p| (p.user.0, p.languages.join(" "), p.unknown))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "rust scala".to_owned(), 1),
+                (2, "rust scala".to_owned(), 0),
+                (3, String::new(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn earliest_post_names_the_kata_in_any_order() {
+        use chrono::TimeZone;
+
+        let dated = |id, name, day: Option<u32>| ChatMessage {
+            date: day.map(|day| Utc.ymd(2020, 4, day).and_hms(12, 0, 0)),
+            ..from(1, id, name)
+        };
+        let mut messages = vec![
+            dated(1, "7 robinson crusoe", None),
+            dated(2, "7 ROBINSON CRUSOE", Some(3)),
+            dated(3, "7 Robinson Crusoe", Some(2)),
+            dated(4, "7 Robinson crusoe", Some(2)),
+        ];
+        for _ in 0..messages.len() {
+            messages.rotate_left(1);
+            let (katas, _) = solved_katas(&messages);
+            assert_eq!(katas[0].0, "7 Robinson Crusoe");
+            assert_eq!(solved_by_kyu(&messages)[&7], vec!["Robinson Crusoe"]);
+            assert_eq!(
+                compute_unsolved(&messages, UserId(2))[0].name,
+                "7 Robinson Crusoe"
+            );
+        }
+    }
+
+    #[test]
+    fn solved_katas_skips_non_solutions() {
+        let messages = vec![
+            message(1, "7\nRobinson Crusoe\nhttps://pastebin.com/fZHdUbhT"),
+            message(2, "force registered, not a solution"),
+            message(3, "6\nCreate Phone Number\nhttps://pastebin.com/grekUgAs"),
+            message(4, "7\nRobinson Crusoe\nhttps://pastebin.com/other"),
+        ];
+
+        let (katas, skipped) = solved_katas(&messages);
+        assert_eq!(
+            katas,
+            vec![
+                (
+                    "6 Create Phone Number".to_owned(),
+                    "https://pastebin.com/grekUgAs".to_owned()
+                ),
+                (
+                    "7 Robinson Crusoe".to_owned(),
+                    "https://pastebin.com/fZHdUbhT".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn markdown_digest_groups_by_kyu() {
+        let users = users(&[(1, "Bob")]);
+        let messages = vec![
+            from(1, 1, "7 Robinson [Crusoe]"),
+            from(2, 2, "6 Create Phone Number"),
+            from(1, 3, "6 Create Phone Number"),
+            from(1, 4, "Valid Braces"),
+        ];
+
+        assert_eq!(
+            markdown_digest(Lang::En, &users, &messages),
+            "# Solved katas\n\
+             \n## 6 kyu\n\n\
+             - [Create Phone Number](https://pastebin.com/p2) - Unregistered (2), Bob\n\
+             \n## 7 kyu\n\n\
+             - [Robinson \\[Crusoe\\]](https://pastebin.com/p1) - Bob\n"
+        );
+        let ru = markdown_digest(Lang::Ru, &users, &messages);
+        assert!(ru.starts_with("# Решённые каты\n\n## 6 кю\n"));
+        assert!(ru.contains("Незарегистрированный (2), Bob"));
+    }
 }
@@ -1,9 +1,18 @@
 use crate::error::MainError;
 use serde::{de::DeserializeOwned, Serialize};
+use sled::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
 use std::marker::PhantomData;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub struct TypedDb<K, V> {
     inner: sled::Db,
+    /// Upcoming inserts that fail with an io error
+    #[cfg(test)]
+    failing_inserts: AtomicUsize,
     kv: PhantomData<(K, V)>,
 }
 
@@ -15,10 +24,23 @@ where
     pub fn new(db: sled::Db) -> Self {
         Self {
             inner: db,
+            #[cfg(test)]
+            failing_inserts: AtomicUsize::new(0),
             kv: PhantomData::default(),
         }
     }
 
+    #[cfg(test)]
+    pub fn inner(&self) -> &sled::Db {
+        &self.inner
+    }
+
+    /// Makes the next `count` inserts fail as a transient io error would
+    #[cfg(test)]
+    pub fn fail_inserts(&self, count: usize) {
+        self.failing_inserts.store(count, Ordering::SeqCst);
+    }
+
     pub fn get(&self, key: &K) -> Result<Option<V>, MainError> {
         self.inner
             .get(serde_json::to_vec(key)?.as_slice())?
@@ -26,7 +48,62 @@ where
             .map_or(Ok(None), |r| r.map(Some))
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V), MainError>> {
+        self.inner.iter().map(|kv| {
+            let (k, v) = kv?;
+            Ok((
+                serde_json::from_slice(k.as_ref())?,
+                serde_json::from_slice(v.as_ref())?,
+            ))
+        })
+    }
+
+    /// Entries of tuple keys whose first element is `first`, the rest of the tree isn't read
+    pub fn iter_first<P: Serialize>(
+        &self,
+        first: &P,
+    ) -> Result<impl Iterator<Item = Result<(K, V), MainError>>, MainError> {
+        Ok(self.inner.scan_prefix(first_prefix(first)?).map(|kv| {
+            let (k, v) = kv?;
+            Ok((
+                serde_json::from_slice(k.as_ref())?,
+                serde_json::from_slice(v.as_ref())?,
+            ))
+        }))
+    }
+
+    /// Number of `iter_first` entries, their values aren't deserialized
+    pub fn count_first<P: Serialize>(&self, first: &P) -> Result<usize, MainError> {
+        let mut count = 0;
+        for entry in self.inner.scan_prefix(first_prefix(first)?) {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    #[cfg(test)]
+    pub fn remove(&self, key: &K) -> Result<(), MainError> {
+        Ok(self
+            .inner
+            .remove(serde_json::to_vec(key)?.as_slice())
+            .map(|_| ())?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub fn insert(&self, key: &K, value: V) -> Result<(), MainError> {
+        #[cfg(test)]
+        {
+            let left = self.failing_inserts.load(Ordering::SeqCst);
+            if left > 0 {
+                self.failing_inserts.store(left - 1, Ordering::SeqCst);
+                let injected = std::io::Error::new(std::io::ErrorKind::Interrupted, "injected");
+                return Err(sled::Error::Io(injected).into());
+            }
+        }
         Ok(self
             .inner
             .insert(
@@ -35,4 +112,61 @@ where
             )
             .map(|_| ())?)
     }
+
+    /// Runs `f` as one sled transaction, nothing it wrote is kept if it returns an error.
+    /// Sled may run `f` again on a conflict
+    pub fn transaction<A>(
+        &self,
+        f: impl Fn(&TypedTx<K, V>) -> ConflictableTransactionResult<A, MainError>,
+    ) -> Result<A, MainError> {
+        self.inner
+            .transaction(|tx| {
+                f(&TypedTx {
+                    inner: tx,
+                    kv: PhantomData,
+                })
+            })
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            })
+    }
+}
+
+/// How every json array key starting with `first` starts
+fn first_prefix<P: Serialize>(first: &P) -> Result<Vec<u8>, MainError> {
+    let mut prefix = b"[".to_vec();
+    prefix.extend(serde_json::to_vec(first)?);
+    prefix.push(b',');
+    Ok(prefix)
+}
+
+/// `TypedDb` inside `TypedDb::transaction`
+pub struct TypedTx<'a, K, V> {
+    inner: &'a TransactionalTree,
+    kv: PhantomData<(K, V)>,
+}
+
+/// Error that makes a transaction roll back
+pub fn abort(e: impl Into<MainError>) -> ConflictableTransactionError<MainError> {
+    ConflictableTransactionError::Abort(e.into())
+}
+
+impl<'a, K, V> TypedTx<'a, K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn remove(&self, key: &K) -> ConflictableTransactionResult<(), MainError> {
+        self.inner.remove(serde_json::to_vec(key).map_err(abort)?)?;
+        Ok(())
+    }
+
+    pub fn insert(&self, key: &K, value: V) -> ConflictableTransactionResult<(), MainError> {
+        self.inner.insert(
+            serde_json::to_vec(key).map_err(abort)?,
+            serde_json::to_vec(&value).map_err(abort)?,
+        )?;
+        Ok(())
+    }
 }
@@ -1,3 +1,5 @@
+use crate::format::Format;
+
 pub fn chunk_with_size(s: &str) -> Vec<String> {
     const MAX_CHUNK_SIZE: usize = 2048;
 
@@ -14,3 +16,40 @@ pub fn chunk_with_size(s: &str) -> Vec<String> {
     }
     chunks
 }
+
+/// Numbered lines sorted by score, equal scores share a place and the first place is bold
+pub fn leaderboard(rows: impl IntoIterator<Item = (String, usize)>, format: Format) -> String {
+    let mut rows: Vec<_> = rows.into_iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut place = 0;
+    let mut lines = Vec::new();
+    for (i, (name, score)) in rows.iter().enumerate() {
+        if i == 0 || rows[i - 1].1 != *score {
+            place = i + 1;
+        }
+        let name = if place == 1 {
+            format.bold(name)
+        } else {
+            format.text(name)
+        };
+        lines.push(format!("{}. {} - {}", place, name, score));
+    }
+    lines.join("\n")
+}
+
+/// Levenshtein distance counted in chars
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}